@@ -0,0 +1,11 @@
+#include "libremu.h"
+
+// Print every argument on its own line, run with `remu args.mem -- one two three`
+int main(int argc, char ** argv) {
+    for (int i = 0; i < argc; i++) {
+        outs(argv[i]);
+        outb('\r');
+        outb('\n');
+    }
+    return argc;
+}
@@ -0,0 +1,54 @@
+# A benchmark for the simulator itself. It fills a buffer with pseudo-random
+# bytes and then takes the CRC-32 of it over and over, a bit at a time, which
+# is mostly tight loops of loads, shifts and branches. Exits with the low byte
+# of the final CRC.
+.equ LEN, 4096
+.equ ROUNDS, 200
+.global main
+.text
+main:
+    # Fill the buffer from a linear congruential generator
+    la t0, buffer
+    li t1, LEN
+    li t2, 12345
+    li t3, 1103515245
+fill:
+    mul t2, t2, t3
+    addi t2, t2, 1234
+    srli t4, t2, 16
+    sb t4, 0(t0)
+    addi t0, t0, 1
+    addi t1, t1, -1
+    bnez t1, fill
+
+    li s0, ROUNDS
+    li a0, 0
+round:
+    la t0, buffer
+    li t1, LEN
+    li t5, 0xedb88320
+    not a0, a0
+byte:
+    lbu t2, 0(t0)
+    xor a0, a0, t2
+    li t3, 8
+bit:
+    andi t4, a0, 1
+    srli a0, a0, 1
+    beqz t4, next
+    xor a0, a0, t5
+next:
+    addi t3, t3, -1
+    bnez t3, bit
+    addi t0, t0, 1
+    addi t1, t1, -1
+    bnez t1, byte
+    not a0, a0
+    addi s0, s0, -1
+    bnez s0, round
+
+    ret
+
+.data
+buffer:
+    .zero LEN
@@ -0,0 +1,32 @@
+#!/bin/bash
+
+# Time the simulator on a program with the decoded instruction cache on and off, keeping the
+# fastest of RUNS runs (5 by default) of each. Takes the program followed by any options for the
+# simulator, and defaults to jpg_decode built to exit once the picture is decoded
+# (`make jpg_decode/jpg_decode_bench.mem`), drawing into a framebuffer with no window.
+#
+#   ./bench.sh
+#   ./bench.sh bench.s
+
+cd "$(dirname "$0")" || exit 1
+if [[ $# -eq 0 ]] ; then
+    set -- jpg_decode/jpg_decode_bench.mem --device fb,backend=none
+fi
+file="$1"
+if ! [[ -e $file ]] ; then
+    echo "File $file not found"
+    exit 1
+fi
+cargo build --release -q || exit 1
+sim=../target/release/ece586-riscv-sim
+
+fastest() {
+    local times=()
+    for ((i = 0; i < ${RUNS:-5}; i++)) ; do
+        times+=("$( { TIMEFORMAT=%R; time "$sim" -q --suppress-status "$@" > /dev/null 2>&1; } 2>&1 )")
+    done
+    printf '%s\n' "${times[@]}" | sort -n | head -n 1
+}
+
+echo "$file with the cache:    $(fastest "$@") s"
+echo "$file without the cache: $(fastest --no-icache "$@") s"
@@ -86,7 +86,10 @@ int main() {
             offset += (image_info.m_MCUHeight-1) * WIDTH;
         }
     }
+#ifndef BENCH
+    // Keep the picture up. The benchmark build exits instead, so it can be timed
     while(1) {}
+#endif
     return 0;
 
 }
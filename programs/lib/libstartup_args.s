@@ -0,0 +1,18 @@
+# Startup code for programs that take arguments, run with `remu prog.mem -- arg1 arg2`
+# The simulator leaves argc at the stack pointer, followed by the argv and envp
+# arrays, which are forwarded to main(argc, argv, envp)
+.global _start
+.extern main, exit
+.section .text.prologue
+_start:
+        lw a0, 0(sp)
+        addi a1, sp, 4
+        slli t0, a0, 2
+        add a2, a1, t0
+        addi a2, a2, 4
+        call main
+        call _exit
+.global _exit
+_exit:
+        add ra,zero,zero
+        ret
@@ -0,0 +1,22 @@
+# A benchmark for the simulator's device accesses. It polls the serial port's
+# line status register and writes its scratch register over and over, so most
+# of the time goes to loads and stores that are handed to a device. Needs
+# `--device serial`, and exits with the low byte of the sum of the status bits
+# it read, which is 0 unless something was typed.
+.equ SERIAL, 0xF00003F8
+.equ ROUNDS, 5000000
+.global main
+.text
+main:
+    li t0, SERIAL
+    li t1, ROUNDS
+    li a0, 0
+poll:
+    lbu t2, 5(t0)
+    andi t2, t2, 1
+    add a0, a0, t2
+    sb t1, 7(t0)
+    addi t1, t1, -1
+    bnez t1, poll
+
+    ret
@@ -8,6 +8,7 @@ use crossbeam_channel::Sender as CbSender;
 use single_value_channel::Receiver as SvcReceiver;
 use std::sync::{LazyLock,Mutex};
 use crate::register::Register;
+use crate::{assembler, loader};
 use url_encoded_data::UrlEncodedData;
 use crate::statetransfer::{self, ControlCode, MachineState};
 use serde_json::json;
@@ -60,6 +61,14 @@ fn post_control(req: Request) -> Response {
             send_commands(ControlCode::STEP);
             200
         },
+        "stepback" => {
+            send_commands(ControlCode::STEPBACK);
+            200
+        },
+        "rcontinue" => {
+            send_commands(ControlCode::RCONTINUE);
+            200
+        },
         "pokereg" => {
             send_commands(ControlCode::POKEREG { register: (Register::from_num(params.get_first("reg").unwrap_or("").parse().unwrap_or(0)).unwrap_or(Register::A0)), value: (params.get_first("val").unwrap_or("").parse().unwrap_or(0)) });
             200
@@ -75,24 +84,52 @@ fn post_control(req: Request) -> Response {
 
 #[post("/api/compile")]
 fn post_compile(code: Option<&str>) -> Response {
-    build_riscv(code, "RVGCC")
+    build_riscv(code)
 }
+// Assembly is handled in-process. Like /api/compile, /api/assemble sends back something to run
+// as application/octet-stream, here the raw binary image to load at address 0
 #[post("/api/assemble")]
 fn post_assemble(code: Option<&str>) -> Response {
-    build_riscv(code, "RVASM")
+    assemble_riscv(code, "application/octet-stream", |image| {
+        let top = image.segments.iter().map(|s| s.addr as usize + s.size).max().unwrap_or(0);
+        let mut bin = vec![0; top];
+        for segment in image.segments.iter() {
+            let addr = segment.addr as usize;
+            bin[addr..addr + segment.data.len()].copy_from_slice(&segment.data);
+        }
+        bin
+    })
+}
+// The same, but as the text of a .mem file that can be loaded straight into the simulator
+#[post("/api/assemble/mem")]
+fn post_assemble_mem(code: Option<&str>) -> Response {
+    assemble_riscv(code, "text/plain", |image| {
+        image.segments.iter()
+            .flat_map(|segment| loader::mem::write(segment.addr, &segment.data).into_bytes())
+            .collect()
+    })
 }
-fn build_riscv(code: Option<&str>, lang: &str) -> Response {
+// Mistakes in the source are the client's, so they get a 400 with the assembler's message
+fn assemble_riscv(code: Option<&str>, mime_type: &str, encode: impl FnOnce(&loader::Image) -> Vec<u8>) -> Response {
+    let (response_message, code, mime_type) = match code.map(|code| assembler::assemble(code, "input.s")) {
+        Some(Ok(image)) => (encode(&image), 200, mime_type),
+        Some(Err(e)) => (format!("{e}\n").into_bytes(), 400, "text/plain"),
+        None => (b"Nothing to assemble\n".to_vec(), 400, "text/plain"),
+    };
+    Response::new()
+    .status_line(format!("HTTP/1.1 {} {}\r\n", code, match code { 400 => "Bad Request", _ => "OK" }))
+        .mime(mime_type)
+        .body(response_message)
+}
+fn build_riscv(code: Option<&str>) -> Response {
     let (response_message, code, mime_type) = (|| { 
         /* first, check if the RVGCC environment variable is set - if not, we can't go any further */
-        let rvgcc = match env::var(lang) {
+        let rvgcc = match env::var("RVGCC") {
             Ok(val) => val,
             Err(_) => { eprintln!("Compiler environment variable not set."); return (b"Compiler environment variable not set.".to_vec(),500, "text/plain"); }
         };
         /* if it is, great - now we check if there is any code to compile (passed as raw form data) */
-        let args = match lang {
-            "RVGCC" => &["-fpic", "-march=rv32i", "-mabi=ilp32", "-x", "c", "-", "-o", "/tmp/a.out"],
-            _ => &["-o", "/tmp/a.out", "--", "","","","",""] // evil
-        };
+        let args = ["-fpic", "-march=rv32i", "-mabi=ilp32", "-x", "c", "-", "-o", "/tmp/a.out"];
         if let Some(code) = code {
             /* if so, get cooking - spawn compiler process */
             let mut child = match Command::new(rvgcc)
@@ -146,6 +183,7 @@ fn receive_state() -> String {
         "pc": s.pc,
         "cur_inst": s.cur_inst,
         "registers": s.registers,
+        "memory_changes": s.memory_changes,
         "cycle": s.cycle
     }))
 }
@@ -156,7 +194,7 @@ pub fn run_server(commands_tx: CbSender<statetransfer::ControlCode>, state_rx: S
     *STATE_RX.lock().unwrap() = Some(state_rx);
 
     let socket = TcpListener::bind(":::9001").unwrap();
-    let routes = Routes::new(vec![get_assets(), post_compile(), post_assemble(),post_control(),get_state()]);
+    let routes = Routes::new(vec![get_assets(), post_compile(), post_assemble(), post_assemble_mem(),post_control(),get_state()]);
     let config = Config::new().routes(routes);
     let http = HttpListener::new(socket, config);
 
@@ -0,0 +1,1292 @@
+use crate::csr;
+use crate::loader::Image;
+use crate::opcode::{sign_extend, Operation};
+use crate::register::{FRegister, Register};
+use crate::softfloat::RoundingMode;
+use crate::symbols::{Symbol, SymbolKind};
+use crate::ReadFileError;
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// The two places code and data can go. `.rodata`, `.bss` and friends all end up in `Data`,
+/// which is laid out after `Text`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Section {
+    Text,
+    Data,
+}
+
+#[derive(Debug)]
+enum Item {
+    /// An instruction, or a pseudo-instruction that expands to `size / 4` of them
+    Instruction { mnemonic: String, args: Vec<String>, size: u32 },
+    /// `.word`, `.half` or `.byte`, with expressions that are evaluated once labels are known
+    Data { width: u32, exprs: Vec<String> },
+    Bytes(Vec<u8>),
+    /// `.zero`, kept as a count so that nothing is allocated before the layout is known to fit
+    Zeros(u32),
+}
+
+#[derive(Debug)]
+struct Statement {
+    line: usize,
+    section: Section,
+    offset: u32,
+    item: Item,
+}
+
+/// Assemble RV32IM source into an image. The text section starts at address 0 and the data
+/// section follows it, and execution starts at `_start` or `main` if either is defined.
+///
+/// This is a two pass assembler. The first pass works out how big every statement is, so that
+/// labels can be given addresses, and the second evaluates operands and encodes instructions.
+pub fn assemble(text: &str, file: &str) -> Result<Image, ReadFileError> {
+    let error = |line: usize, message: String| ReadFileError::AsmSyntax {
+        file: file.to_string(),
+        line,
+        message,
+    };
+
+    let mut section = Section::Text;
+    let mut offsets = [0u32; 2];
+    let mut alignment = [4u32; 2];
+    let mut labels: HashMap<String, (Section, u32)> = HashMap::new();
+    let mut constants: HashMap<String, i64> = HashMap::new();
+    let mut statements = Vec::new();
+
+    for (index, raw) in text.lines().enumerate() {
+        let line = index + 1;
+        let mut rest = strip_comment(raw).trim();
+
+        // Any number of labels can start a line
+        while let Some((label, after)) = split_label(rest) {
+            if labels.contains_key(label) || constants.contains_key(label) {
+                return Err(error(line, format!("`{label}` is defined more than once")));
+            }
+            labels.insert(label.to_string(), (section, offsets[section as usize]));
+            rest = after.trim_start();
+        }
+        if rest.is_empty() {
+            continue;
+        }
+
+        let (head, operands) = match rest.split_once(char::is_whitespace) {
+            Some((head, operands)) => (head, operands.trim()),
+            None => (rest, ""),
+        };
+        let head = head.to_lowercase();
+        let args = split_operands(operands);
+        let offset = offsets[section as usize];
+
+        let item = if head.starts_with('.') {
+            match head.as_str() {
+                ".text" => {
+                    section = Section::Text;
+                    continue;
+                }
+                ".data" | ".rodata" | ".bss" | ".sdata" | ".sbss" => {
+                    section = Section::Data;
+                    continue;
+                }
+                ".section" => {
+                    section = match args.first().map(|s| s.as_str()) {
+                        Some(name) if name.starts_with(".text") => Section::Text,
+                        Some(_) => Section::Data,
+                        None => return Err(error(line, "expected a section name".to_string())),
+                    };
+                    continue;
+                }
+                ".equ" | ".set" => {
+                    let [name, value] = &args[..] else {
+                        return Err(error(line, format!("expected `{head} NAME, VALUE`")));
+                    };
+                    if labels.contains_key(name) {
+                        return Err(error(line, format!("`{name}` is defined more than once")));
+                    }
+                    let value = eval(value, &constants).map_err(|e| error(line, e))?;
+                    constants.insert(name.clone(), value);
+                    continue;
+                }
+                ".word" | ".long" | ".4byte" => Item::Data { width: 4, exprs: args },
+                ".half" | ".short" | ".2byte" => Item::Data { width: 2, exprs: args },
+                ".byte" => Item::Data { width: 1, exprs: args },
+                ".ascii" | ".asciz" | ".string" => {
+                    let mut bytes = Vec::new();
+                    for arg in args.iter() {
+                        bytes.extend(parse_string(arg).map_err(|e| error(line, e))?);
+                        if head != ".ascii" {
+                            bytes.push(0);
+                        }
+                    }
+                    Item::Bytes(bytes)
+                }
+                ".zero" | ".space" | ".skip" => {
+                    let count = args
+                        .first()
+                        .ok_or(error(line, format!("expected `{head} COUNT`")))
+                        .and_then(|count| eval(count, &constants).map_err(|e| error(line, e)))?;
+                    let count = u32::try_from(count)
+                        .map_err(|_| error(line, format!("{count} is not a valid number of bytes")))?;
+                    Item::Zeros(count)
+                }
+                ".align" | ".p2align" | ".balign" => {
+                    let amount = args
+                        .first()
+                        .ok_or(error(line, format!("expected `{head} ALIGNMENT`")))
+                        .and_then(|amount| eval(amount, &constants).map_err(|e| error(line, e)))?;
+                    let align = if head == ".balign" {
+                        u32::try_from(amount).ok().filter(|align| align.is_power_of_two())
+                            .ok_or(error(line, format!("alignment {amount} is not a power of two")))?
+                    } else {
+                        u32::try_from(amount).ok().and_then(|amount| 1u32.checked_shl(amount))
+                            .ok_or(error(line, format!("alignment 2^{amount} is out of range")))?
+                    };
+                    alignment[section as usize] = alignment[section as usize].max(align);
+                    let padding = offset.checked_next_multiple_of(align)
+                        .ok_or(error(line, "the section is too big for the address space".to_string()))?
+                        - offset;
+                    // Code is padded with NOPs so that falling through the padding is harmless
+                    let bytes = match section {
+                        Section::Text => 0x13u32.to_le_bytes().into_iter().cycle().take(padding as usize).collect(),
+                        Section::Data => vec![0; padding as usize],
+                    };
+                    Item::Bytes(bytes)
+                }
+                // Directives that only matter to a linker
+                ".globl" | ".global" | ".local" | ".extern" | ".type" | ".size" | ".file" | ".ident"
+                | ".option" | ".attribute" | ".weak" => continue,
+                _ => return Err(error(line, format!("unknown directive `{head}`"))),
+            }
+        } else {
+            let size = instruction_size(&head, &args, &constants);
+            Item::Instruction { mnemonic: head, args, size }
+        };
+
+        let size = match &item {
+            Item::Instruction { size, .. } => *size,
+            Item::Data { width, exprs } => width * exprs.len() as u32,
+            Item::Bytes(bytes) => bytes.len() as u32,
+            Item::Zeros(count) => *count,
+        };
+        // Compressed instructions mean only 2-byte alignment is needed
+        if section == Section::Text && !offset.is_multiple_of(2) && matches!(item, Item::Instruction { .. }) {
+            return Err(error(line, "instruction is not halfword aligned".to_string()));
+        }
+        offsets[section as usize] = offset.checked_add(size)
+            .ok_or(error(line, "the section is too big for the address space".to_string()))?;
+        statements.push(Statement { line, section, offset, item });
+    }
+
+    // Lay out the sections and give every label its final address
+    let data_base = offsets[Section::Text as usize]
+        .checked_next_multiple_of(alignment[Section::Data as usize].max(16))
+        .filter(|base| base.checked_add(offsets[Section::Data as usize]).is_some());
+    let Some(data_base) = data_base else {
+        let line = statements.last().map_or(0, |s| s.line);
+        return Err(error(line, "the program is too big for the address space".to_string()));
+    };
+    let bases = [0, data_base];
+    let mut symbols = constants;
+    let mut image_symbols = Vec::new();
+    for (name, (section, offset)) in labels.iter() {
+        let addr = bases[*section as usize] + offset;
+        symbols.insert(name.clone(), addr as i64);
+        image_symbols.push(Symbol {
+            name: name.clone(),
+            addr,
+            size: 0,
+            kind: match section {
+                Section::Text => SymbolKind::Function,
+                Section::Data => SymbolKind::Object,
+            },
+        });
+    }
+
+    let mut output = [Vec::new(), Vec::new()];
+    for statement in statements.iter() {
+        let bytes = &mut output[statement.section as usize];
+        let pc = bases[statement.section as usize] + statement.offset;
+        match &statement.item {
+            Item::Instruction { mnemonic, args, size } => {
+                let ops = instruction(mnemonic, args, pc, *size, &symbols)
+                    .map_err(|e| error(statement.line, e))?;
+                debug_assert_eq!(ops.len() as u32 * 4, *size);
+                for op in ops.iter() {
+                    bytes.extend(op.to_bytes());
+                }
+            }
+            Item::Data { width, exprs } => {
+                for expr in exprs.iter() {
+                    let value = eval(expr, &symbols).map_err(|e| error(statement.line, e))?;
+                    let max = 1i64 << (width * 8);
+                    if value >= max || value < -(max / 2) {
+                        return Err(error(statement.line, format!("{value} does not fit in {width} bytes")));
+                    }
+                    bytes.extend(&(value as u32).to_le_bytes()[..*width as usize]);
+                }
+            }
+            Item::Bytes(data) => bytes.extend(data),
+            Item::Zeros(count) => bytes.resize(bytes.len() + *count as usize, 0),
+        }
+    }
+
+    let mut image = Image::default();
+    for (base, bytes) in bases.iter().zip(output.iter()) {
+        if !bytes.is_empty() {
+            image.push_bytes(*base, bytes);
+        }
+    }
+    image.entry = Some(["_start", "main"]
+        .iter()
+        .find_map(|name| labels.get(*name).map(|(section, offset)| bases[*section as usize] + offset))
+        .unwrap_or(0));
+    image.symbols = image_symbols;
+    Ok(image)
+}
+
+/// How many bytes an instruction will take up. Everything is one instruction except for `la`,
+/// `call` and `tail`, which are always two, and `li`, which is two unless the value is known up
+/// front and small enough for a single `addi` or `lui`.
+fn instruction_size(mnemonic: &str, args: &[String], constants: &HashMap<String, i64>) -> u32 {
+    match mnemonic {
+        "la" | "call" | "tail" => 8,
+        "li" => match args.get(1).map(|value| eval(value, constants)) {
+            Some(Ok(value)) if (-2048..2048).contains(&value) || value & 0xFFF == 0 => 4,
+            _ => 8,
+        },
+        _ => 4,
+    }
+}
+
+/// An `Operation` variant that takes two registers and an immediate
+type ImmConstructor = fn(Register, Register, i32) -> Operation;
+/// Floating point `Operation` variants that take two or three sources and a rounding mode
+type FpConstructor = fn(FRegister, FRegister, FRegister, RoundingMode) -> Operation;
+type FmaConstructor = fn(FRegister, FRegister, FRegister, FRegister, RoundingMode) -> Operation;
+
+/// Turn one line of assembly into the operations it stands for
+fn instruction(
+    mnemonic: &str,
+    args: &[String],
+    pc: u32,
+    size: u32,
+    symbols: &HashMap<String, i64>,
+) -> Result<Vec<Operation>, String> {
+    use Operation::*;
+
+    let expect = |count: usize| {
+        if args.len() == count {
+            Ok(())
+        } else {
+            Err(format!("`{mnemonic}` expects {count} operands, found {}", args.len()))
+        }
+    };
+    let expr = |s: &str| eval(s, symbols);
+    let imm12 = |s: &str| {
+        // Like GNU as on rv32, 32-bit values such as 0xffffffff are taken as their signed value
+        let value = match expr(s)? {
+            value @ 0x80000000..=0xFFFFFFFF => value as u32 as i32 as i64,
+            value => value,
+        };
+        if (-2048..2048).contains(&value) {
+            Ok(value as i32)
+        } else {
+            Err(format!("immediate {value} does not fit in 12 bits"))
+        }
+    };
+    let shamt = |s: &str| {
+        let value = expr(s)?;
+        if (0..32).contains(&value) {
+            Ok(value as i32)
+        } else {
+            Err(format!("shift amount {value} is out of range"))
+        }
+    };
+    let upper = |s: &str| {
+        let value = expr(s)?;
+        if (-0x80000..0x100000).contains(&value) {
+            Ok((value << 12) as i32)
+        } else {
+            Err(format!("immediate {value} does not fit in 20 bits"))
+        }
+    };
+    // pc-relative targets, `bits` is the size of the signed offset that can be encoded
+    let target = |s: &str, bits: u32| {
+        let offset = (expr(s)? as u32).wrapping_sub(pc) as i32;
+        if offset % 2 != 0 {
+            Err(format!("target {s} is not 2-byte aligned"))
+        } else if offset >= 1 << (bits - 1) || offset < -(1 << (bits - 1)) {
+            Err(format!("target {s} is out of range"))
+        } else {
+            Ok(offset)
+        }
+    };
+    // `offset(reg)`, where the offset can be left out
+    let memory = |s: &str| -> Result<(Register, i32), String> {
+        let open = s.rfind('(').filter(|_| s.ends_with(')'));
+        match open {
+            Some(open) => {
+                let base = reg(&s[open + 1..s.len() - 1])?;
+                let offset = match s[..open].trim() {
+                    "" => 0,
+                    offset => imm12(offset)?,
+                };
+                Ok((base, offset))
+            }
+            None => Err(format!("expected a memory operand like 8(sp), found `{s}`")),
+        }
+    };
+    let csr = |s: &str| csr::parse(s).ok_or_else(|| format!("unknown CSR `{s}`"));
+    let uimm5 = |s: &str| {
+        let value = expr(s)?;
+        if (0..32).contains(&value) {
+            Ok(value as i32)
+        } else {
+            Err(format!("immediate {value} does not fit in 5 bits"))
+        }
+    };
+    // Split a pc-relative offset into the parts for an auipc and the instruction after it
+    let pcrel = |s: &str| -> Result<(i32, i32), String> {
+        let offset = (expr(s)? as u32).wrapping_sub(pc);
+        let hi = offset.wrapping_add(0x800) & 0xFFFFF000;
+        Ok((hi as i32, offset.wrapping_sub(hi) as i32))
+    };
+
+    let itype: Option<ImmConstructor> = match mnemonic {
+        "addi" => Some(ADDI),
+        "slti" => Some(SLTI),
+        "sltiu" => Some(SLTIU),
+        "andi" => Some(ANDI),
+        "ori" => Some(ORI),
+        "xori" => Some(XORI),
+        _ => None,
+    };
+    let shift: Option<ImmConstructor> = match mnemonic {
+        "slli" => Some(SLLI),
+        "srli" => Some(SRLI),
+        "srai" => Some(SRAI),
+        "rori" => Some(RORI),
+        "bclri" => Some(BCLRI),
+        "bexti" => Some(BEXTI),
+        "binvi" => Some(BINVI),
+        "bseti" => Some(BSETI),
+        _ => None,
+    };
+    let rtype: Option<fn(Register, Register, Register) -> Operation> = match mnemonic {
+        "add" => Some(ADD),
+        "sub" => Some(SUB),
+        "sll" => Some(SLL),
+        "slt" => Some(SLT),
+        "sltu" => Some(SLTU),
+        "xor" => Some(XOR),
+        "srl" => Some(SRL),
+        "sra" => Some(SRA),
+        "or" => Some(OR),
+        "and" => Some(AND),
+        "mul" => Some(MUL),
+        "mulh" => Some(MULH),
+        "mulhsu" => Some(MULSU),
+        "mulhu" => Some(MULU),
+        "div" => Some(DIV),
+        "divu" => Some(DIVU),
+        "rem" => Some(REM),
+        "remu" => Some(REMU),
+        "sh1add" => Some(SH1ADD),
+        "sh2add" => Some(SH2ADD),
+        "sh3add" => Some(SH3ADD),
+        "andn" => Some(ANDN),
+        "orn" => Some(ORN),
+        "xnor" => Some(XNOR),
+        "max" => Some(MAX),
+        "maxu" => Some(MAXU),
+        "min" => Some(MIN),
+        "minu" => Some(MINU),
+        "rol" => Some(ROL),
+        "ror" => Some(ROR),
+        "clmul" => Some(CLMUL),
+        "clmulh" => Some(CLMULH),
+        "clmulr" => Some(CLMULR),
+        "bclr" => Some(BCLR),
+        "bext" => Some(BEXT),
+        "binv" => Some(BINV),
+        "bset" => Some(BSET),
+        _ => None,
+    };
+    let unary: Option<fn(Register, Register) -> Operation> = match mnemonic {
+        "clz" => Some(CLZ),
+        "ctz" => Some(CTZ),
+        "cpop" => Some(CPOP),
+        "sext.b" => Some(SEXTB),
+        "sext.h" => Some(SEXTH),
+        "zext.h" => Some(ZEXTH),
+        "orc.b" => Some(ORCB),
+        "rev8" => Some(REV8),
+        _ => None,
+    };
+    // CSR instructions, and the pseudo-instructions for them that leave out rd
+    let csr_reg: Option<fn(Register, Register, u16) -> Operation> = match mnemonic {
+        "csrrw" | "csrw" => Some(CSRRW),
+        "csrrs" | "csrs" => Some(CSRRS),
+        "csrrc" | "csrc" => Some(CSRRC),
+        _ => None,
+    };
+    let csr_imm: Option<fn(Register, i32, u16) -> Operation> = match mnemonic {
+        "csrrwi" | "csrwi" => Some(CSRRWI),
+        "csrrsi" | "csrsi" => Some(CSRRSI),
+        "csrrci" | "csrci" => Some(CSRRCI),
+        _ => None,
+    };
+    // The ordering suffixes don't change anything with a single hart, so they are accepted and
+    // dropped
+    let atomic_name = ["aqrl", "aq", "rl"]
+        .iter()
+        .find_map(|suffix| mnemonic.strip_suffix(suffix).and_then(|m| m.strip_suffix('.')))
+        .unwrap_or(mnemonic);
+    let atomic: Option<fn(Register, Register, Register) -> Operation> = match atomic_name {
+        "sc.w" => Some(SCW),
+        "amoswap.w" => Some(AMOSWAPW),
+        "amoadd.w" => Some(AMOADDW),
+        "amoxor.w" => Some(AMOXORW),
+        "amoand.w" => Some(AMOANDW),
+        "amoor.w" => Some(AMOORW),
+        "amomin.w" => Some(AMOMINW),
+        "amomax.w" => Some(AMOMAXW),
+        "amominu.w" => Some(AMOMINUW),
+        "amomaxu.w" => Some(AMOMAXUW),
+        _ => None,
+    };
+    // Atomics take their address as `(reg)`, with no offset
+    let address = |s: &str| match memory(s)? {
+        (base, 0) => Ok(base),
+        _ => Err(format!("atomic address must not have an offset, found `{s}`")),
+    };
+    let branch: Option<ImmConstructor> = match mnemonic {
+        "beq" => Some(BEQ),
+        "bne" => Some(BNE),
+        "blt" => Some(BLT),
+        "bge" => Some(BGE),
+        "bltu" => Some(BLTU),
+        "bgeu" => Some(BGEU),
+        _ => None,
+    };
+    // Branches with their operands swapped
+    let swapped: Option<ImmConstructor> = match mnemonic {
+        "bgt" => Some(BLT),
+        "ble" => Some(BGE),
+        "bgtu" => Some(BLTU),
+        "bleu" => Some(BGEU),
+        _ => None,
+    };
+    // Branches that compare against zero, with whether the register goes first
+    let zero: Option<(ImmConstructor, bool)> = match mnemonic {
+        "beqz" => Some((BEQ, true)),
+        "bnez" => Some((BNE, true)),
+        "bltz" => Some((BLT, true)),
+        "bgez" => Some((BGE, true)),
+        "blez" => Some((BGE, false)),
+        "bgtz" => Some((BLT, false)),
+        _ => None,
+    };
+    let load: Option<ImmConstructor> = match mnemonic {
+        "lb" => Some(LB),
+        "lh" => Some(LH),
+        "lw" => Some(LW),
+        "lbu" => Some(LBU),
+        "lhu" => Some(LHU),
+        _ => None,
+    };
+    let store: Option<ImmConstructor> = match mnemonic {
+        "sb" => Some(SB),
+        "sh" => Some(SH),
+        "sw" => Some(SW),
+        _ => None,
+    };
+
+    // Floating point instructions that round take the rounding mode as an optional last operand,
+    // and use the one in `frm` without it. Conversions to double are always exact, so GNU as
+    // leaves their rounding mode as `rne`.
+    let exact = matches!(mnemonic, "fcvt.d.s" | "fcvt.d.w" | "fcvt.d.wu");
+    let rounding = |count: usize| -> Result<RoundingMode, String> {
+        match args.len() {
+            n if n == count && exact => Ok(RoundingMode::RNE),
+            n if n == count => Ok(RoundingMode::DYN),
+            n if n == count + 1 => {
+                let rm = args[count].trim();
+                RoundingMode::from_name(rm).ok_or_else(|| format!("unknown rounding mode `{rm}`"))
+            }
+            n => Err(format!("`{mnemonic}` expects {count} operands, found {n}")),
+        }
+    };
+    let fp_arith: Option<FpConstructor> = match mnemonic {
+        "fadd.s" => Some(FADDS),
+        "fsub.s" => Some(FSUBS),
+        "fmul.s" => Some(FMULS),
+        "fdiv.s" => Some(FDIVS),
+        "fadd.d" => Some(FADDD),
+        "fsub.d" => Some(FSUBD),
+        "fmul.d" => Some(FMULD),
+        "fdiv.d" => Some(FDIVD),
+        _ => None,
+    };
+    let fma: Option<FmaConstructor> = match mnemonic {
+        "fmadd.s" => Some(FMADDS),
+        "fmsub.s" => Some(FMSUBS),
+        "fnmsub.s" => Some(FNMSUBS),
+        "fnmadd.s" => Some(FNMADDS),
+        "fmadd.d" => Some(FMADDD),
+        "fmsub.d" => Some(FMSUBD),
+        "fnmsub.d" => Some(FNMSUBD),
+        "fnmadd.d" => Some(FNMADDD),
+        _ => None,
+    };
+    let fp_sign: Option<fn(FRegister, FRegister, FRegister) -> Operation> = match mnemonic {
+        "fsgnj.s" => Some(FSGNJS),
+        "fsgnjn.s" => Some(FSGNJNS),
+        "fsgnjx.s" => Some(FSGNJXS),
+        "fmin.s" => Some(FMINS),
+        "fmax.s" => Some(FMAXS),
+        "fsgnj.d" => Some(FSGNJD),
+        "fsgnjn.d" => Some(FSGNJND),
+        "fsgnjx.d" => Some(FSGNJXD),
+        "fmin.d" => Some(FMIND),
+        "fmax.d" => Some(FMAXD),
+        _ => None,
+    };
+    // fmv, fneg and fabs are sign injection with the same register twice
+    let fp_sign_pseudo: Option<fn(FRegister, FRegister, FRegister) -> Operation> = match mnemonic {
+        "fmv.s" => Some(FSGNJS),
+        "fneg.s" => Some(FSGNJNS),
+        "fabs.s" => Some(FSGNJXS),
+        "fmv.d" => Some(FSGNJD),
+        "fneg.d" => Some(FSGNJND),
+        "fabs.d" => Some(FSGNJXD),
+        _ => None,
+    };
+    let fp_compare: Option<fn(Register, FRegister, FRegister) -> Operation> = match mnemonic {
+        "feq.s" => Some(FEQS),
+        "flt.s" => Some(FLTS),
+        "fle.s" => Some(FLES),
+        "feq.d" => Some(FEQD),
+        "flt.d" => Some(FLTD),
+        "fle.d" => Some(FLED),
+        _ => None,
+    };
+    let fp_to_int: Option<fn(Register, FRegister, RoundingMode) -> Operation> = match mnemonic {
+        "fcvt.w.s" => Some(FCVTWS),
+        "fcvt.wu.s" => Some(FCVTWUS),
+        "fcvt.w.d" => Some(FCVTWD),
+        "fcvt.wu.d" => Some(FCVTWUD),
+        _ => None,
+    };
+    let fp_from_int: Option<fn(FRegister, Register, RoundingMode) -> Operation> = match mnemonic {
+        "fcvt.s.w" => Some(FCVTSW),
+        "fcvt.s.wu" => Some(FCVTSWU),
+        "fcvt.d.w" => Some(FCVTDW),
+        "fcvt.d.wu" => Some(FCVTDWU),
+        _ => None,
+    };
+
+    if let Some(op) = fp_arith {
+        let rm = rounding(3)?;
+        return Ok(vec![op(freg(&args[0])?, freg(&args[1])?, freg(&args[2])?, rm)]);
+    }
+    if let Some(op) = fma {
+        let rm = rounding(4)?;
+        return Ok(vec![op(freg(&args[0])?, freg(&args[1])?, freg(&args[2])?, freg(&args[3])?, rm)]);
+    }
+    if let Some(op) = fp_sign {
+        expect(3)?;
+        return Ok(vec![op(freg(&args[0])?, freg(&args[1])?, freg(&args[2])?)]);
+    }
+    if let Some(op) = fp_sign_pseudo {
+        expect(2)?;
+        let rs = freg(&args[1])?;
+        return Ok(vec![op(freg(&args[0])?, rs, rs)]);
+    }
+    if let Some(op) = fp_compare {
+        expect(3)?;
+        return Ok(vec![op(reg(&args[0])?, freg(&args[1])?, freg(&args[2])?)]);
+    }
+    if let Some(op) = fp_to_int {
+        let rm = rounding(2)?;
+        return Ok(vec![op(reg(&args[0])?, freg(&args[1])?, rm)]);
+    }
+    if let Some(op) = fp_from_int {
+        let rm = rounding(2)?;
+        return Ok(vec![op(freg(&args[0])?, reg(&args[1])?, rm)]);
+    }
+    if let Some(op) = itype {
+        expect(3)?;
+        return Ok(vec![op(reg(&args[0])?, reg(&args[1])?, imm12(&args[2])?)]);
+    }
+    if let Some(op) = shift {
+        expect(3)?;
+        return Ok(vec![op(reg(&args[0])?, reg(&args[1])?, shamt(&args[2])?)]);
+    }
+    if let Some(op) = rtype {
+        expect(3)?;
+        return Ok(vec![op(reg(&args[0])?, reg(&args[1])?, reg(&args[2])?)]);
+    }
+    if let Some(op) = unary {
+        expect(2)?;
+        return Ok(vec![op(reg(&args[0])?, reg(&args[1])?)]);
+    }
+    if let Some(op) = csr_reg {
+        if mnemonic.starts_with("csrr") {
+            expect(3)?;
+            return Ok(vec![op(reg(&args[0])?, reg(&args[2])?, csr(&args[1])?)]);
+        }
+        expect(2)?;
+        return Ok(vec![op(Register::Zero, reg(&args[1])?, csr(&args[0])?)]);
+    }
+    if let Some(op) = csr_imm {
+        if mnemonic.starts_with("csrr") {
+            expect(3)?;
+            return Ok(vec![op(reg(&args[0])?, uimm5(&args[2])?, csr(&args[1])?)]);
+        }
+        expect(2)?;
+        return Ok(vec![op(Register::Zero, uimm5(&args[1])?, csr(&args[0])?)]);
+    }
+    if let Some(op) = atomic {
+        expect(3)?;
+        return Ok(vec![op(reg(&args[0])?, address(&args[2])?, reg(&args[1])?)]);
+    }
+    if atomic_name == "lr.w" {
+        expect(2)?;
+        return Ok(vec![LRW(reg(&args[0])?, address(&args[1])?)]);
+    }
+    if let Some(op) = branch {
+        expect(3)?;
+        return Ok(vec![op(reg(&args[0])?, reg(&args[1])?, target(&args[2], 13)?)]);
+    }
+    if let Some(op) = swapped {
+        expect(3)?;
+        return Ok(vec![op(reg(&args[1])?, reg(&args[0])?, target(&args[2], 13)?)]);
+    }
+    if let Some((op, first)) = zero {
+        expect(2)?;
+        let rs = reg(&args[0])?;
+        let offset = target(&args[1], 13)?;
+        return Ok(vec![if first { op(rs, Register::Zero, offset) } else { op(Register::Zero, rs, offset) }]);
+    }
+    if let Some(op) = load {
+        expect(2)?;
+        let (base, offset) = memory(&args[1])?;
+        return Ok(vec![op(reg(&args[0])?, base, offset)]);
+    }
+    if let Some(op) = store {
+        expect(2)?;
+        let (base, offset) = memory(&args[1])?;
+        return Ok(vec![op(base, reg(&args[0])?, offset)]);
+    }
+
+    Ok(match mnemonic {
+        "lui" => {
+            expect(2)?;
+            vec![LUI(reg(&args[0])?, upper(&args[1])?)]
+        }
+        "auipc" => {
+            expect(2)?;
+            vec![AUIPC(reg(&args[0])?, upper(&args[1])?)]
+        }
+        "jal" => match args.len() {
+            1 => vec![JAL(Register::RA, target(&args[0], 21)?)],
+            _ => {
+                expect(2)?;
+                vec![JAL(reg(&args[0])?, target(&args[1], 21)?)]
+            }
+        },
+        "jalr" => match args.len() {
+            1 => vec![JALR(Register::RA, reg(&args[0])?, 0)],
+            2 => {
+                let (base, offset) = memory(&args[1])?;
+                vec![JALR(reg(&args[0])?, base, offset)]
+            }
+            _ => {
+                expect(3)?;
+                vec![JALR(reg(&args[0])?, reg(&args[1])?, imm12(&args[2])?)]
+            }
+        },
+        "ecall" => {
+            expect(0)?;
+            vec![ECALL]
+        }
+        "ebreak" => {
+            expect(0)?;
+            vec![EBREAK]
+        }
+        "mret" => {
+            expect(0)?;
+            vec![MRET]
+        }
+        // Every fence is treated as a full fence, which is all the simulator needs
+        "fence" => vec![FENCE],
+        "fence.i" => {
+            expect(0)?;
+            vec![FENCEI]
+        }
+
+        // Pseudo-instructions
+        "nop" => {
+            expect(0)?;
+            vec![ADDI(Register::Zero, Register::Zero, 0)]
+        }
+        "li" => {
+            expect(2)?;
+            let rd = reg(&args[0])?;
+            let value = expr(&args[1])?;
+            if !(-(1i64 << 31)..(1i64 << 32)).contains(&value) {
+                return Err(format!("{value} does not fit in 32 bits"));
+            }
+            let value = value as u32;
+            let hi = value.wrapping_add(0x800) & 0xFFFFF000;
+            let lo = value.wrapping_sub(hi) as i32;
+            match size {
+                4 if hi == 0 => vec![ADDI(rd, Register::Zero, lo)],
+                4 => vec![LUI(rd, hi as i32)],
+                _ => vec![LUI(rd, hi as i32), ADDI(rd, rd, lo)],
+            }
+        }
+        "la" => {
+            expect(2)?;
+            let rd = reg(&args[0])?;
+            let (hi, lo) = pcrel(&args[1])?;
+            vec![AUIPC(rd, hi), ADDI(rd, rd, lo)]
+        }
+        "call" => {
+            expect(1)?;
+            let (hi, lo) = pcrel(&args[0])?;
+            vec![AUIPC(Register::RA, hi), JALR(Register::RA, Register::RA, lo)]
+        }
+        "tail" => {
+            expect(1)?;
+            let (hi, lo) = pcrel(&args[0])?;
+            vec![AUIPC(Register::T1, hi), JALR(Register::Zero, Register::T1, lo)]
+        }
+        "mv" => {
+            expect(2)?;
+            vec![ADDI(reg(&args[0])?, reg(&args[1])?, 0)]
+        }
+        "not" => {
+            expect(2)?;
+            vec![XORI(reg(&args[0])?, reg(&args[1])?, -1)]
+        }
+        "neg" => {
+            expect(2)?;
+            vec![SUB(reg(&args[0])?, Register::Zero, reg(&args[1])?)]
+        }
+        "seqz" => {
+            expect(2)?;
+            vec![SLTIU(reg(&args[0])?, reg(&args[1])?, 1)]
+        }
+        "snez" => {
+            expect(2)?;
+            vec![SLTU(reg(&args[0])?, Register::Zero, reg(&args[1])?)]
+        }
+        "sltz" => {
+            expect(2)?;
+            vec![SLT(reg(&args[0])?, reg(&args[1])?, Register::Zero)]
+        }
+        "sgtz" => {
+            expect(2)?;
+            vec![SLT(reg(&args[0])?, Register::Zero, reg(&args[1])?)]
+        }
+        "j" => {
+            expect(1)?;
+            vec![JAL(Register::Zero, target(&args[0], 21)?)]
+        }
+        "jr" => {
+            expect(1)?;
+            vec![JALR(Register::Zero, reg(&args[0])?, 0)]
+        }
+        "ret" => {
+            expect(0)?;
+            vec![JALR(Register::Zero, Register::RA, 0)]
+        }
+        "csrr" => {
+            expect(2)?;
+            vec![CSRRS(reg(&args[0])?, Register::Zero, csr(&args[1])?)]
+        }
+        "rdcycle" | "rdcycleh" | "rdtime" | "rdtimeh" | "rdinstret" | "rdinstreth" => {
+            expect(1)?;
+            vec![CSRRS(reg(&args[0])?, Register::Zero, csr(&mnemonic[2..])?)]
+        }
+
+        // Floating point
+        "flw" => {
+            expect(2)?;
+            let (base, offset) = memory(&args[1])?;
+            vec![FLW(freg(&args[0])?, base, offset)]
+        }
+        "fsw" => {
+            expect(2)?;
+            let (base, offset) = memory(&args[1])?;
+            vec![FSW(base, freg(&args[0])?, offset)]
+        }
+        "fld" => {
+            expect(2)?;
+            let (base, offset) = memory(&args[1])?;
+            vec![FLD(freg(&args[0])?, base, offset)]
+        }
+        "fsd" => {
+            expect(2)?;
+            let (base, offset) = memory(&args[1])?;
+            vec![FSD(base, freg(&args[0])?, offset)]
+        }
+        "fsqrt.s" | "fsqrt.d" | "fcvt.s.d" | "fcvt.d.s" => {
+            let rm = rounding(2)?;
+            let op = match mnemonic {
+                "fsqrt.s" => FSQRTS,
+                "fsqrt.d" => FSQRTD,
+                "fcvt.s.d" => FCVTSD,
+                _ => FCVTDS,
+            };
+            vec![op(freg(&args[0])?, freg(&args[1])?, rm)]
+        }
+        "fclass.s" => {
+            expect(2)?;
+            vec![FCLASSS(reg(&args[0])?, freg(&args[1])?)]
+        }
+        "fclass.d" => {
+            expect(2)?;
+            vec![FCLASSD(reg(&args[0])?, freg(&args[1])?)]
+        }
+        "fmv.x.w" | "fmv.x.s" => {
+            expect(2)?;
+            vec![FMVXW(reg(&args[0])?, freg(&args[1])?)]
+        }
+        "fmv.w.x" | "fmv.s.x" => {
+            expect(2)?;
+            vec![FMVWX(freg(&args[0])?, reg(&args[1])?)]
+        }
+        "frcsr" | "frrm" | "frflags" => {
+            expect(1)?;
+            vec![CSRRS(reg(&args[0])?, Register::Zero, csr(&format!("f{}", &mnemonic[2..]))?)]
+        }
+        // These swap in the new value, with the old one going to rd if it is given
+        "fscsr" | "fsrm" | "fsflags" => match args.len() {
+            1 => vec![CSRRW(Register::Zero, reg(&args[0])?, csr(&format!("f{}", &mnemonic[2..]))?)],
+            _ => {
+                expect(2)?;
+                vec![CSRRW(reg(&args[0])?, reg(&args[1])?, csr(&format!("f{}", &mnemonic[2..]))?)]
+            }
+        },
+        _ => return Err(format!("unknown instruction `{mnemonic}`")),
+    })
+}
+
+/// Parse a register by ABI name (`a0`, `zero`, `fp`) or number (`x10`)
+fn reg(s: &str) -> Result<Register, String> {
+    let s = s.trim();
+    s.strip_prefix('x')
+        .and_then(|n| n.parse::<u32>().ok())
+        .and_then(Register::from_num)
+        .or_else(|| Register::from_str(s).ok())
+        .ok_or(format!("invalid register `{s}`"))
+}
+
+/// Parse a floating point register by ABI name (`fa0`) or number (`f10`)
+fn freg(s: &str) -> Result<FRegister, String> {
+    FRegister::parse(s).ok_or(format!("invalid floating point register `{}`", s.trim()))
+}
+
+/// Evaluate an operand. These are numbers, character literals and symbols joined with `+` and
+/// `-`, optionally wrapped in `%hi(...)` or `%lo(...)`.
+fn eval(s: &str, symbols: &HashMap<String, i64>) -> Result<i64, String> {
+    let s = s.trim();
+    if let Some(inner) = s.strip_prefix("%hi(").and_then(|s| s.strip_suffix(')')) {
+        let value = eval(inner, symbols)? as u32;
+        return Ok((value.wrapping_add(0x800) >> 12) as i64);
+    }
+    if let Some(inner) = s.strip_prefix("%lo(").and_then(|s| s.strip_suffix(')')) {
+        let value = eval(inner, symbols)? as u32;
+        return Ok(sign_extend(value & 0xFFF, 12) as i64);
+    }
+
+    let mut total = 0i64;
+    let mut negative = false;
+    let mut term = String::new();
+    let mut terms = Vec::new();
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                // Keep character literals in one piece, they may contain a + or -
+                term.push(c);
+                for c in chars.by_ref() {
+                    term.push(c);
+                    if c == '\'' && !term.ends_with("\\'") {
+                        break;
+                    }
+                }
+            }
+            '+' | '-' => {
+                if !term.trim().is_empty() {
+                    terms.push((negative, std::mem::take(&mut term)));
+                    negative = false;
+                }
+                if c == '-' {
+                    negative = !negative;
+                }
+            }
+            c => term.push(c),
+        }
+    }
+    if term.trim().is_empty() {
+        return Err(format!("expected a value, found `{s}`"));
+    }
+    terms.push((negative, term));
+
+    for (negative, term) in terms.iter() {
+        let term = term.trim();
+        let value = if let Some(literal) = term.strip_prefix('\'').and_then(|t| t.strip_suffix('\'')) {
+            match &parse_string(&format!("\"{literal}\""))?[..] {
+                [byte] => *byte as i64,
+                _ => return Err(format!("invalid character literal {term}")),
+            }
+        } else if term.starts_with(|c: char| c.is_ascii_digit()) {
+            parse_integer(term).ok_or(format!("invalid number `{term}`"))?
+        } else {
+            *symbols.get(term).ok_or(format!("undefined symbol `{term}`"))?
+        };
+        total = if *negative { total - value } else { total + value };
+    }
+    Ok(total)
+}
+
+fn parse_integer(s: &str) -> Option<i64> {
+    let lower = s.to_lowercase();
+    if let Some(hex) = lower.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = lower.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()
+    } else {
+        lower.parse().ok()
+    }
+}
+
+/// Parse a double quoted string, handling the usual backslash escapes
+fn parse_string(s: &str) -> Result<Vec<u8>, String> {
+    let inner = s
+        .trim()
+        .strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .ok_or(format!("expected a quoted string, found {s}"))?;
+    let mut bytes = Vec::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0; 4];
+            bytes.extend(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('n') => bytes.push(b'\n'),
+            Some('r') => bytes.push(b'\r'),
+            Some('t') => bytes.push(b'\t'),
+            Some('0') => bytes.push(0),
+            Some('\\') => bytes.push(b'\\'),
+            Some('"') => bytes.push(b'"'),
+            Some('\'') => bytes.push(b'\''),
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                bytes.push(u8::from_str_radix(&hex, 16).map_err(|_| format!("invalid escape \\x{hex}"))?);
+            }
+            Some(c) => return Err(format!("unknown escape \\{c}")),
+            None => return Err("string ends with a backslash".to_string()),
+        }
+    }
+    Ok(bytes)
+}
+
+/// Remove a `#` or `//` comment, ignoring anything inside a string
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..i],
+            '/' if !in_string && line[i..].starts_with("//") => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+/// Split `label: rest` into the label and whatever follows it
+fn split_label(s: &str) -> Option<(&str, &str)> {
+    let (label, rest) = s.split_once(':')?;
+    let is_ident = !label.is_empty()
+        && !label.starts_with(|c: char| c.is_ascii_digit())
+        && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '$');
+    is_ident.then_some((label, rest))
+}
+
+/// Split operands on commas, leaving commas inside strings and parentheses alone
+fn split_operands(s: &str) -> Vec<String> {
+    let mut operands = Vec::new();
+    let mut current = String::new();
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    for c in s.chars() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '(' if !in_string => depth += 1,
+            ')' if !in_string => depth -= 1,
+            ',' if !in_string && depth == 0 => {
+                operands.push(current.trim().to_string());
+                current.clear();
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    if !current.trim().is_empty() {
+        operands.push(current.trim().to_string());
+    }
+    operands
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::machine::{ExecutionError, Machine};
+    use crate::memory::Memory;
+
+    fn words(image: &Image) -> Vec<u32> {
+        image.segments[0]
+            .data
+            .chunks(4)
+            .map(|w| u32::from_le_bytes([w[0], w[1], w[2], w[3]]))
+            .collect()
+    }
+
+    #[test]
+    fn test_encodings() {
+        // Checked against GNU as
+        let image = assemble(
+            "addi a0, zero, 42\n\
+             sw a0, 256(zero)\n\
+             lui a0, 0x12345\n\
+             beq a0, a1, skip\n\
+             mul a0, a0, a1\n\
+             skip: srai t0, t1, 3\n\
+             lw ra, -4(sp)\n\
+             ret\n",
+            "test.s",
+        )
+        .unwrap();
+        assert_eq!(
+            words(&image),
+            vec![0x02a00513, 0x10a02023, 0x12345537, 0x00b50463, 0x02b50533, 0x40335293, 0xffc12083, 0x00008067]
+        );
+    }
+
+    #[test]
+    fn test_csr_instructions() {
+        let image = assemble(
+            "csrr a0, mhartid\n\
+             csrw mtvec, t0\n\
+             csrwi mtvec, 4\n\
+             rdcycle a1\n\
+             csrrc a0, mstatus, a1\n\
+             csrrsi zero, 0x7c0, 1\n",
+            "test.s",
+        )
+        .unwrap();
+        assert_eq!(
+            words(&image),
+            vec![0xf1402573, 0x30529073, 0x30525073, 0xc00025f3, 0x3005b573, 0x7c00e073]
+        );
+        assert!(assemble("csrw bogus, a0\n", "test.s").is_err());
+    }
+
+    #[test]
+    fn test_atomic_instructions() {
+        // The same as GNU as gives, except that the ordering bits are left clear
+        let image = assemble(
+            "lr.w a0, (a1)\n\
+             sc.w.rl t0, a2, (a1)\n\
+             amoswap.w.aqrl a0, a2, 0(a1)\n\
+             amomaxu.w zero, a2, (a1)\n",
+            "test.s",
+        )
+        .unwrap();
+        assert_eq!(words(&image), vec![0x1005a52f, 0x18c5a2af, 0x08c5a52f, 0xe0c5a02f]);
+        assert!(assemble("amoadd.w a0, a1, 4(a2)\n", "test.s").is_err());
+        assert!(assemble("csrwi mtvec, 32\n", "test.s").is_err());
+    }
+
+    #[test]
+    fn test_bitmanip_instructions() {
+        // Checked against GNU as
+        let image = assemble(
+            "sh1add a0, a1, a2\n\
+             andn a0, a1, a2\n\
+             clz a0, a1\n\
+             cpop a0, a1\n\
+             rev8 a0, a1\n\
+             orc.b a0, a1\n\
+             zext.h a0, a1\n\
+             rori a0, a1, 3\n\
+             bseti a0, a1, 5\n\
+             clmul a0, a1, a2\n",
+            "test.s",
+        )
+        .unwrap();
+        assert_eq!(
+            words(&image),
+            vec![0x20c5a533, 0x40c5f533, 0x60059513, 0x60259513, 0x6985d513, 0x2875d513, 0x0805c533,
+                 0x6035d513, 0x28559513, 0x0ac59533]
+        );
+        assert!(assemble("bseti a0, a1, 32\n", "test.s").is_err());
+    }
+
+    #[test]
+    fn test_float_instructions() {
+        // Checked against GNU as
+        let image = assemble(
+            "flw fa0, 4(a0)\n\
+             fsw fa0, -4(sp)\n\
+             fadd.s fa0, fa1, fa2, rtz\n\
+             fmadd.s fa0, fa1, fa2, fa3\n\
+             fcvt.w.s a0, fa0, rtz\n\
+             fneg.s ft0, ft1\n\
+             fmv.x.w a0, fa0\n\
+             feq.s a0, fa0, f11\n\
+             frcsr a0\n",
+            "test.s",
+        )
+        .unwrap();
+        assert_eq!(
+            words(&image),
+            vec![0x00452507, 0xfea12e27, 0x00c59553, 0x68c5f543, 0xc0051553, 0x20109053, 0xe0050553,
+                 0xa0b52553, 0x00302573]
+        );
+        let image = assemble(
+            "fld fa0, 8(sp)\n\
+             fsd fa0, -8(a0)\n\
+             fadd.d fa0, fa1, fa2\n\
+             fmadd.d fa0, fa1, fa2, fa3\n\
+             fcvt.s.d fa0, fa1\n\
+             fcvt.d.w fa0, a0\n\
+             fle.d a0, fa0, fa1\n",
+            "test.s",
+        )
+        .unwrap();
+        assert_eq!(
+            words(&image),
+            vec![0x00813507, 0xfea53c27, 0x02c5f553, 0x6ac5f543, 0x4015f553, 0xd2050553, 0xa2b50553]
+        );
+        assert!(assemble("fadd.s fa0, fa1, fa2, rmx\n", "test.s").is_err());
+        assert!(assemble("fadd.s fa0, fa1, a2\n", "test.s").is_err());
+    }
+
+    #[test]
+    fn test_pseudo_instructions() {
+        let image = assemble(
+            ".equ BIG, 0x12345678\n\
+             main: li a0, 5\n\
+             li a1, BIG\n\
+             li a2, 0x1000\n\
+             mv a3, a0\n\
+             call main\n\
+             j main\n",
+            "test.s",
+        )
+        .unwrap();
+        assert_eq!(
+            words(&image),
+            vec![
+                0x00500513, // li a0, 5
+                0x123455b7, 0x67858593, // lui a1, 0x12345; addi a1, a1, 0x678
+                0x00001637, // lui a2, 1
+                0x00050693, // mv a3, a0
+                0x00000097, 0xfec080e7, // auipc ra, 0; jalr ra, -20(ra)
+                0xfe5ff06f, // j main
+            ]
+        );
+    }
+
+    #[test]
+    fn test_data_and_symbols() {
+        let image = assemble(
+            ".data\n\
+             msg: .asciz \"hi, \\\"you\\\"\\n\" # comment\n\
+             .align 2\n\
+             table: .word msg, 'a', -1\n\
+             .byte 1, 2\n\
+             .text\n\
+             _start: lui a0, %hi(table)\n\
+             addi a0, a0, %lo(table)\n",
+            "test.s",
+        )
+        .unwrap();
+        assert_eq!(image.entry, Some(0));
+        let data = &image.segments[1];
+        assert_eq!(data.addr, 16);
+        assert_eq!(&data.data[..11], b"hi, \"you\"\n\0");
+        assert_eq!(&data.data[12..26], &[16, 0, 0, 0, b'a', 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF, 1, 2]);
+        assert_eq!(words(&image), vec![0x00000537, 0x01c50513]);
+    }
+
+    #[test]
+    fn test_errors() {
+        let err = |s: &str| assemble(s, "test.s").unwrap_err().to_string();
+        assert_eq!(err("nop\nfoo a0\n"), "test.s:2: unknown instruction `foo`");
+        assert_eq!(err("addi a0, a0, 4096\n"), "test.s:1: immediate 4096 does not fit in 12 bits");
+        assert_eq!(err("addi a0, a0, 0xfffff000\n"), "test.s:1: immediate -4096 does not fit in 12 bits");
+        assert!(assemble("addi a0, a0, 0xffffffff\n", "test.s").is_ok());
+        assert_eq!(err("j nowhere\n"), "test.s:1: undefined symbol `nowhere`");
+        assert_eq!(err("add a0, a1\n"), "test.s:1: `add` expects 3 operands, found 2");
+        assert_eq!(err("a:\na:\n"), "test.s:2: `a` is defined more than once");
+        // Sizes that can't be laid out in 32 bits are errors rather than panics
+        assert_eq!(err(".zero -1\n"), "test.s:1: -1 is not a valid number of bytes");
+        assert_eq!(err(".align 40\n"), "test.s:1: alignment 2^40 is out of range");
+        assert_eq!(err(".balign 3\n"), "test.s:1: alignment 3 is not a power of two");
+        assert_eq!(err(".space 0x7fffffff\n.space 0x7fffffff\n.space 0x7fffffff\n"),
+                   "test.s:3: the section is too big for the address space");
+        assert_eq!(err(".space 0xfffffff0\n.data\n.space 0x20\n"),
+                   "test.s:3: the program is too big for the address space");
+    }
+
+    #[test]
+    fn test_run_assembled() {
+        let image = assemble(
+            ".data\n\
+             values: .word 3, 4, 5\n\
+             .text\n\
+             main:\n\
+                 la t0, values\n\
+                 li t1, 3\n\
+                 li a0, 0\n\
+             loop:\n\
+                 lw t2, 0(t0)\n\
+                 add a0, a0, t2\n\
+                 addi t0, t0, 4\n\
+                 addi t1, t1, -1\n\
+                 bnez t1, loop\n\
+                 ret\n",
+            "sum.s",
+        )
+        .unwrap();
+        let mut memory = Memory::new(256);
+        image.write_to(&mut memory).unwrap();
+        let mut machine = Machine::new(image.entry.unwrap(), None, memory, false, Bus::new(256, Vec::new()).unwrap());
+        let result = loop {
+            if let Err(e) = machine.step() {
+                break e;
+            }
+        };
+        assert_eq!(result, ExecutionError::FinishedExecution(12));
+    }
+}
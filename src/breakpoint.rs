@@ -0,0 +1,382 @@
+//! Breakpoints, which can carry a condition and an ignore count on top of their address.
+//! Conditions are small expressions over registers and memory like `a0 == 5 && [sp+8] != 0`,
+//! parsed once when the breakpoint is set and evaluated every time the pc reaches it.
+//!
+//! Watchpoints are here too. They stop on the instruction that touches a range of memory or
+//! changes a register, rather than at an address.
+
+use crate::machine::{ExecutionError, Machine};
+use crate::register::Register;
+use crate::symbols::SymbolTable;
+
+use serde::Serialize;
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+#[derive(Clone, PartialEq, Debug, Serialize)]
+pub struct Breakpoint {
+    pub addr: u32,
+    pub condition: Option<Condition>,
+    /// How many times the pc has reached this with the condition true
+    pub hits: u64,
+    /// How many more hits to run through without stopping
+    pub ignore: u64,
+}
+
+impl Breakpoint {
+    pub fn new(addr: u32, condition: Option<Condition>) -> Self {
+        Breakpoint { addr, condition, hits: 0, ignore: 0 }
+    }
+
+    /// Count a hit if the condition holds, and return whether execution should stop here. A
+    /// condition that can't be evaluated, like one reading unmapped memory, stops as if it
+    /// were true so the problem gets noticed.
+    pub fn hit(&mut self, machine: &Machine) -> bool {
+        if !self.holds(machine) {
+            return false;
+        }
+        self.hits += 1;
+        if self.ignore > 0 {
+            self.ignore -= 1;
+            return false;
+        }
+        true
+    }
+
+    /// Whether the condition holds, or there isn't one
+    pub fn holds(&self, machine: &Machine) -> bool {
+        match self.condition {
+            Some(ref condition) => condition.eval(machine) != Ok(0),
+            None => true,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Watch {
+    /// Loads and/or stores that touch any byte in the range
+    Memory { range: RangeInclusive<u32>, read: bool, write: bool },
+    /// Any instruction that changes the register's value
+    Register(Register),
+}
+
+impl fmt::Display for Watch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Watch::Memory { range, read, write } => {
+                let kind = match (read, write) {
+                    (true, true) => "rw",
+                    (true, false) => "r",
+                    _ => "w",
+                };
+                write!(f, "{kind} {:#010x}-{:#010x}", range.start(), range.end())
+            }
+            Watch::Register(reg) => write!(f, "{reg:?}"),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Watchpoint {
+    pub watch: Watch,
+    pub hits: u64,
+}
+
+impl Watchpoint {
+    pub fn new(watch: Watch) -> Self {
+        Watchpoint { watch, hits: 0 }
+    }
+
+    /// Whether an access of `len` bytes at `addr` sets this off
+    pub fn matches(&self, addr: u32, len: u32, is_write: bool) -> bool {
+        match self.watch {
+            Watch::Memory { ref range, read, write } => {
+                let end = addr.saturating_add(len - 1);
+                (if is_write { write } else { read }) && addr <= *range.end() && end >= *range.start()
+            }
+            Watch::Register(_) => false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum WatchEvent {
+    Read { addr: u32, value: u32 },
+    /// The old value is only known for RAM, reading a device could change it
+    Write { addr: u32, old: Option<u32>, new: u32 },
+    Register { reg: Register, old: u32, new: u32 },
+}
+
+/// Which watchpoint went off, and the instruction that did it
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct WatchHit {
+    pub index: usize,
+    pub pc: u32,
+    pub event: WatchEvent,
+}
+
+impl fmt::Display for WatchHit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let WatchHit { index, pc, event } = *self;
+        write!(f, "Watchpoint {index} hit by the instruction at {pc:#x}: ")?;
+        match event {
+            WatchEvent::Read { addr, value } => write!(f, "read {value:#x} from {addr:#x}"),
+            WatchEvent::Write { addr, old: Some(old), new } => write!(f, "{addr:#x} changed from {old:#x} to {new:#x}"),
+            WatchEvent::Write { addr, old: None, new } => write!(f, "wrote {new:#x} to {addr:#x}"),
+            WatchEvent::Register { reg, old, new } => write!(f, "{reg:?} changed from {old:#x} to {new:#x}"),
+        }
+    }
+}
+
+/// A parsed condition, along with the text it came from for display
+#[derive(Clone, PartialEq, Debug, Serialize)]
+pub struct Condition {
+    text: String,
+    #[serde(skip_serializing)]
+    expr: Expr,
+}
+
+impl Condition {
+    /// Parse a condition. Numbers are decimal unless they start with 0x, and any name that isn't
+    /// a register or `pc` is looked up as a symbol.
+    pub fn parse(s: &str, symbols: &SymbolTable) -> Result<Self, String> {
+        let tokens = tokenize(s)?;
+        let mut parser = Parser { tokens: &tokens, pos: 0, symbols };
+        let expr = parser.or()?;
+        match parser.peek() {
+            None => Ok(Condition { text: s.split_whitespace().collect::<Vec<_>>().join(" "), expr }),
+            Some(token) => Err(format!("unexpected `{token}`")),
+        }
+    }
+
+    /// The value of the condition, where anything other than 0 is true
+    pub fn eval(&self, machine: &Machine) -> Result<u32, ExecutionError> {
+        self.expr.eval(machine)
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum BinaryOp {
+    Add,
+    Sub,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Expr {
+    Number(u32),
+    Register(Register),
+    Pc,
+    /// The word at an address
+    Memory(Box<Expr>),
+    Not(Box<Expr>),
+    Negate(Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    fn eval(&self, machine: &Machine) -> Result<u32, ExecutionError> {
+        use BinaryOp::*;
+        Ok(match self {
+            Expr::Number(n) => *n,
+            Expr::Register(reg) => machine.get_reg(*reg),
+            Expr::Pc => machine.get_pc(),
+            Expr::Memory(addr) => machine.read_word(addr.eval(machine)?)?,
+            Expr::Not(e) => (e.eval(machine)? == 0) as u32,
+            Expr::Negate(e) => e.eval(machine)?.wrapping_neg(),
+            // Evaluated separately so && and || can short circuit
+            Expr::Binary(And, l, r) => (l.eval(machine)? != 0 && r.eval(machine)? != 0) as u32,
+            Expr::Binary(Or, l, r) => (l.eval(machine)? != 0 || r.eval(machine)? != 0) as u32,
+            Expr::Binary(op, l, r) => {
+                let (l, r) = (l.eval(machine)?, r.eval(machine)?);
+                // Comparisons are signed, so `a0 < 0` works as expected
+                let (sl, sr) = (l as i32, r as i32);
+                match op {
+                    Add => l.wrapping_add(r),
+                    Sub => l.wrapping_sub(r),
+                    Eq => (l == r) as u32,
+                    Ne => (l != r) as u32,
+                    Lt => (sl < sr) as u32,
+                    Le => (sl <= sr) as u32,
+                    Gt => (sl > sr) as u32,
+                    Ge => (sl >= sr) as u32,
+                    And | Or => unreachable!("logical operators are handled above"),
+                }
+            }
+        })
+    }
+}
+
+const OPERATORS: &[&str] = &["==", "!=", "<=", ">=", "&&", "||", "<", ">", "+", "-", "!", "(", ")", "[", "]"];
+
+fn tokenize(s: &str) -> Result<Vec<&str>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = s.trim_start();
+    while !rest.is_empty() {
+        let len = if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+            op.len()
+        } else {
+            let end = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.'))
+                          .unwrap_or(rest.len());
+            if end == 0 {
+                return Err(format!("unexpected `{}`", rest.chars().next().unwrap()));
+            }
+            end
+        };
+        tokens.push(&rest[..len]);
+        rest = rest[len..].trim_start();
+    }
+    Ok(tokens)
+}
+
+// Recursive descent, from lowest to highest precedence: ||, &&, comparisons, + and -, then
+// unary operators and operands
+struct Parser<'a> {
+    tokens: &'a [&'a str],
+    pos: usize,
+    symbols: &'a SymbolTable,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Result<&'a str, String> {
+        let token = self.peek().ok_or("unexpected end of condition")?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), String> {
+        match self.next()? {
+            token if token == expected => Ok(()),
+            token => Err(format!("expected `{expected}`, found `{token}`")),
+        }
+    }
+
+    /// Parse a left associative chain of whichever of `ops` come up, with `operand` between
+    fn chain(&mut self, ops: &[(&str, BinaryOp)], operand: fn(&mut Self) -> Result<Expr, String>) -> Result<Expr, String> {
+        let mut expr = operand(self)?;
+        while let Some(&(_, op)) = self.peek().and_then(|token| ops.iter().find(|(s, _)| *s == token)) {
+            self.pos += 1;
+            expr = Expr::Binary(op, Box::new(expr), Box::new(operand(self)?));
+        }
+        Ok(expr)
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        self.chain(&[("||", BinaryOp::Or)], Self::and)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        self.chain(&[("&&", BinaryOp::And)], Self::comparison)
+    }
+
+    fn comparison(&mut self) -> Result<Expr, String> {
+        use BinaryOp::*;
+        self.chain(&[("==", Eq), ("!=", Ne), ("<", Lt), ("<=", Le), (">", Gt), (">=", Ge)], Self::sum)
+    }
+
+    fn sum(&mut self) -> Result<Expr, String> {
+        self.chain(&[("+", BinaryOp::Add), ("-", BinaryOp::Sub)], Self::unary)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        Ok(match self.next()? {
+            "!" => Expr::Not(Box::new(self.unary()?)),
+            "-" => Expr::Negate(Box::new(self.unary()?)),
+            "(" => {
+                let expr = self.or()?;
+                self.expect(")")?;
+                expr
+            }
+            "[" => {
+                let expr = self.or()?;
+                self.expect("]")?;
+                Expr::Memory(Box::new(expr))
+            }
+            token => self.operand(token)?,
+        })
+    }
+
+    fn operand(&self, token: &str) -> Result<Expr, String> {
+        if let Some(hex) = token.strip_prefix("0x").or_else(|| token.strip_prefix("0X")) {
+            return u32::from_str_radix(hex, 16).map(Expr::Number)
+                       .map_err(|_| format!("invalid hex number `{token}`"));
+        }
+        if token.starts_with(|c: char| c.is_ascii_digit()) {
+            return token.parse().map(Expr::Number)
+                        .map_err(|_| format!("invalid number `{token}`"));
+        }
+        if token.eq_ignore_ascii_case("pc") {
+            return Ok(Expr::Pc);
+        }
+        if let Ok(reg) = Register::from_str(token) {
+            return Ok(Expr::Register(reg));
+        }
+        match self.symbols.get(token) {
+            Some(addr) => Ok(Expr::Number(addr)),
+            None if OPERATORS.contains(&token) => Err(format!("unexpected `{token}`")),
+            None => Err(format!("unknown register or symbol `{token}`")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::memory::Memory;
+    use crate::symbols::{Symbol, SymbolKind};
+
+    fn eval(s: &str, machine: &Machine) -> Result<u32, String> {
+        let symbols = SymbolTable::new(vec![
+            Symbol { name: "count".to_string(), addr: 0x100, size: 4, kind: SymbolKind::Object },
+        ]);
+        let condition = Condition::parse(s, &symbols)?;
+        condition.eval(machine).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn test_condition() {
+        let mut machine = Machine::new(0, Some(0x200), Memory::new(0x1000), false, Bus::new(0x1000, Vec::new()).unwrap());
+        machine.set_reg(Register::A0, 5);
+        machine.set_reg(Register::A1, -3i32 as u32);
+        machine.store_word(7, 0x208).unwrap();
+        machine.store_word(42, 0x100).unwrap();
+
+        assert_eq!(eval("a0 == 5", &machine), Ok(1));
+        assert_eq!(eval("a0==5&&[sp+8]!=0", &machine), Ok(1));
+        assert_eq!(eval("a0 == 5 && [sp+8] == 0", &machine), Ok(0));
+        assert_eq!(eval("a0 == 4 || [sp + 0x8] == 7", &machine), Ok(1));
+        // Comparisons are signed
+        assert_eq!(eval("a1 < 0", &machine), Ok(1));
+        assert_eq!(eval("a1 == -3", &machine), Ok(1));
+        assert_eq!(eval("!(a0 > 4)", &machine), Ok(0));
+        assert_eq!(eval("[count] - 2 == a0 * 8", &machine), Err("unexpected `*`".to_string()));
+        assert_eq!(eval("[count] == 42 && pc == 0", &machine), Ok(1));
+        // The right hand side isn't evaluated if it doesn't need to be
+        assert_eq!(eval("a0 == 4 && [0x10000] == 0", &machine), Ok(0));
+        assert!(eval("[0x10000] == 0", &machine).is_err());
+
+        assert_eq!(eval("a0 ==", &machine), Err("unexpected end of condition".to_string()));
+        assert_eq!(eval("(a0 == 5", &machine), Err("unexpected end of condition".to_string()));
+        assert_eq!(eval("a0 5", &machine), Err("unexpected `5`".to_string()));
+        assert_eq!(eval("foo == 1", &machine), Err("unknown register or symbol `foo`".to_string()));
+    }
+}
@@ -0,0 +1,128 @@
+//! The address map, which decides whether an access goes to RAM, to a device, or nowhere. RAM
+//! starts at 0 and runs up to the memory top, and the devices live in the top 256 MiB. Each
+//! device picks its own range within that, as an offset from `DEVICE_BASE`.
+
+use crate::devices::Device;
+
+use std::ops::RangeInclusive;
+use thiserror::Error;
+
+/// Device ranges are offsets from here, and nothing above it is RAM
+pub const DEVICE_BASE: u32 = 0xF000_0000;
+
+/// Where an access ends up
+pub enum Region<'a> {
+    Ram,
+    /// A device, with the offset into the device space to pass to it
+    Device(&'a Device, u32),
+    Unmapped,
+}
+
+pub struct Bus {
+    // One past the last byte of RAM
+    ram_top: u64,
+    // Sorted by start address, and no two of them overlap
+    devices: Vec<(RangeInclusive<u32>, Device)>,
+}
+
+impl Bus {
+    /// Lay out RAM up to `memory_top`, or all of the space below the devices if it is 0, and
+    /// the given devices. Devices that claim the same addresses are an error.
+    pub fn new(memory_top: u32, devices: Vec<Device>) -> Result<Bus, BusError> {
+        let mut devices: Vec<_> = devices.into_iter().map(|device| (device.memory_range(), device)).collect();
+        devices.sort_by_key(|(range, _)| *range.start());
+        for pair in devices.windows(2) {
+            let (first, second) = (&pair[0].0, &pair[1].0);
+            if second.start() <= first.end() {
+                return Err(BusError::Overlap(first.clone(), second.clone()));
+            }
+        }
+        let ram_top = if memory_top == 0 { 1 << 32 } else { memory_top as u64 };
+        Ok(Bus { ram_top, devices })
+    }
+
+    /// The memory top this was set up with
+    pub fn memory_top(&self) -> u32 {
+        self.ram_top as u32
+    }
+
+    /// Find what an access of `len` bytes at `addr` goes to. Devices are only checked for the
+    /// first byte, they handle the rest of the access themselves.
+    pub fn decode(&self, addr: u32, len: u32) -> Region<'_> {
+        if addr >= DEVICE_BASE {
+            let offset = addr - DEVICE_BASE;
+            let index = self.devices.partition_point(|(range, _)| *range.end() < offset);
+            match self.devices.get(index) {
+                Some((range, device)) if range.contains(&offset) => Region::Device(device, offset),
+                _ => Region::Unmapped,
+            }
+        } else if addr as u64 + len as u64 <= self.ram_top {
+            Region::Ram
+        } else {
+            Region::Unmapped
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum BusError {
+    #[error("Devices at {:#x}-{:#x} and {:#x}-{:#x} overlap",
+            DEVICE_BASE + .0.start(), DEVICE_BASE + .0.end(), DEVICE_BASE + .1.start(), DEVICE_BASE + .1.end())]
+    Overlap(RangeInclusive<u32>, RangeInclusive<u32>),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::ByteDevice;
+
+    use std::error::Error;
+
+    struct Dummy(RangeInclusive<u32>);
+    impl ByteDevice for Dummy {
+        fn read_byte(&self, addr: u32) -> Result<i8, Box<dyn Error>> {
+            Ok((addr - self.0.start()) as i8)
+        }
+        fn store_byte(&self, _addr: u32, _data: u8) -> Result<(), Box<dyn Error>> {
+            Ok(())
+        }
+        fn memory_range(&self) -> RangeInclusive<u32> {
+            self.0.clone()
+        }
+    }
+    fn dummy(range: RangeInclusive<u32>) -> Device {
+        Device::ByteDevice(Box::new(Dummy(range)))
+    }
+
+    #[test]
+    fn test_decode() {
+        let bus = Bus::new(0x1000, vec![dummy(0x300..=0x3FF), dummy(0x100..=0x1FF), dummy(0x200..=0x2FF)]).unwrap();
+        assert!(matches!(bus.decode(0, 4), Region::Ram));
+        assert!(matches!(bus.decode(0xFFC, 4), Region::Ram));
+        assert!(matches!(bus.decode(0xFFE, 4), Region::Unmapped));
+        assert!(matches!(bus.decode(0x1000, 1), Region::Unmapped));
+        for (addr, start) in [(0x100, 0x100), (0x1FF, 0x100), (0x200, 0x200), (0x3FF, 0x300)] {
+            match bus.decode(DEVICE_BASE + addr, 1) {
+                Region::Device(device, offset) => {
+                    assert_eq!(offset, addr);
+                    assert_eq!(device.memory_range().start(), &start);
+                }
+                _ => panic!("{addr:#x} should be a device"),
+            }
+        }
+        assert!(matches!(bus.decode(DEVICE_BASE + 0xFF, 1), Region::Unmapped));
+        assert!(matches!(bus.decode(DEVICE_BASE + 0x400, 1), Region::Unmapped));
+
+        // With no memory top everything below the devices is RAM
+        let bus = Bus::new(0, Vec::new()).unwrap();
+        assert_eq!(bus.memory_top(), 0);
+        assert!(matches!(bus.decode(DEVICE_BASE - 4, 4), Region::Ram));
+        assert!(matches!(bus.decode(DEVICE_BASE, 4), Region::Unmapped));
+    }
+
+    #[test]
+    fn test_overlap() {
+        let error = Bus::new(0, vec![dummy(0x3F8..=0x400), dummy(0x0..=0x100), dummy(0x400..=0x500)]).err().unwrap();
+        assert_eq!(error.to_string(), "Devices at 0xf00003f8-0xf0000400 and 0xf0000400-0xf0000500 overlap");
+    }
+}
@@ -0,0 +1,379 @@
+use crate::decode::{bitrange, ParseError};
+use crate::opcode::{sign_extend, Operation};
+use crate::register::{FRegister, Register};
+
+use std::fmt;
+
+type Immediate = i32;
+
+/// A 16-bit instruction from the RV32C extension. Each one is shorthand for a 32-bit
+/// `Operation`, which is what gets executed, but they are kept around so that disassembly can
+/// show what is actually in memory.
+///
+/// The immediates here are already scaled and sign-extended, so they are the values the expanded
+/// instruction uses.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CompressedOp {
+    // Quadrant 0
+    CADDI4SPN(Register, Immediate),
+    CFLD(FRegister, Register, Immediate),
+    CLW(Register, Register, Immediate),
+    CSW(Register, Register, Immediate),
+    CFLW(FRegister, Register, Immediate),
+    CFSD(Register, FRegister, Immediate),
+    CFSW(Register, FRegister, Immediate),
+
+    // Quadrant 1
+    CNOP,
+    CADDI(Register, Immediate),
+    CJAL(Immediate),
+    CLI(Register, Immediate),
+    CADDI16SP(Immediate),
+    CLUI(Register, Immediate),
+    CSRLI(Register, Immediate),
+    CSRAI(Register, Immediate),
+    CANDI(Register, Immediate),
+    CSUB(Register, Register),
+    CXOR(Register, Register),
+    COR(Register, Register),
+    CAND(Register, Register),
+    CJ(Immediate),
+    CBEQZ(Register, Immediate),
+    CBNEZ(Register, Immediate),
+
+    // Quadrant 2
+    CSLLI(Register, Immediate),
+    CFLDSP(FRegister, Immediate),
+    CLWSP(Register, Immediate),
+    CJR(Register),
+    CMV(Register, Register),
+    CEBREAK,
+    CJALR(Register),
+    CADD(Register, Register),
+    CFSDSP(FRegister, Immediate),
+    CSWSP(Register, Immediate),
+    CFLWSP(FRegister, Immediate),
+    CFSWSP(FRegister, Immediate),
+}
+
+/// Whether the first byte of an instruction marks it as a 16-bit one. 32-bit instructions always
+/// have their lowest two bits set.
+pub fn is_compressed(first_byte: u8) -> bool {
+    first_byte & 0b11 != 0b11
+}
+
+fn reg(num: u32) -> Result<Register, ParseError> {
+    Register::from_num(num).ok_or(ParseError::RegisterDecode(num))
+}
+
+// The 3-bit register fields can only name x8-x15
+fn reg_prime(num: u32) -> Result<Register, ParseError> {
+    reg(num + 8)
+}
+
+fn freg(num: u32) -> Result<FRegister, ParseError> {
+    FRegister::from_num(num).ok_or(ParseError::RegisterDecode(num))
+}
+
+impl CompressedOp {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ParseError> {
+        use CompressedOp::*;
+        let inst = bytes[0] as u32 | ((bytes[1] as u32) << 8);
+        let bits = |start, end| bitrange(inst, start, end);
+        let invalid = ParseError::InvalidInstruction(inst);
+        let funct3 = bits(13, 15);
+
+        // Immediates shared by a few instructions
+        let imm6 = sign_extend((bits(12, 12) << 5) | bits(2, 6), 6);
+        let jump = sign_extend(
+            (bits(12, 12) << 11)
+                | (bits(11, 11) << 4)
+                | (bits(9, 10) << 8)
+                | (bits(8, 8) << 10)
+                | (bits(7, 7) << 6)
+                | (bits(6, 6) << 7)
+                | (bits(3, 5) << 1)
+                | (bits(2, 2) << 5),
+            12,
+        );
+        let branch = sign_extend(
+            (bits(12, 12) << 8) | (bits(10, 11) << 3) | (bits(5, 6) << 6) | (bits(3, 4) << 1) | (bits(2, 2) << 5),
+            9,
+        );
+        let word_offset = ((bits(10, 12) << 3) | (bits(6, 6) << 2) | (bits(5, 5) << 6)) as i32;
+        let double_offset = ((bits(10, 12) << 3) | (bits(5, 6) << 6)) as i32;
+
+        Ok(match (bits(0, 1), funct3) {
+            (0b00, 0b000) => {
+                let imm = (bits(11, 12) << 4) | (bits(7, 10) << 6) | (bits(6, 6) << 2) | (bits(5, 5) << 3);
+                // This also covers the all-zero halfword, which is defined to be illegal
+                if imm == 0 {
+                    return Err(invalid);
+                }
+                CADDI4SPN(reg_prime(bits(2, 4))?, imm as i32)
+            }
+            (0b00, 0b001) => CFLD(freg(bits(2, 4) + 8)?, reg_prime(bits(7, 9))?, double_offset),
+            (0b00, 0b010) => CLW(reg_prime(bits(2, 4))?, reg_prime(bits(7, 9))?, word_offset),
+            (0b00, 0b101) => CFSD(reg_prime(bits(7, 9))?, freg(bits(2, 4) + 8)?, double_offset),
+            (0b00, 0b110) => CSW(reg_prime(bits(7, 9))?, reg_prime(bits(2, 4))?, word_offset),
+            (0b00, 0b011) => CFLW(freg(bits(2, 4) + 8)?, reg_prime(bits(7, 9))?, word_offset),
+            (0b00, 0b111) => CFSW(reg_prime(bits(7, 9))?, freg(bits(2, 4) + 8)?, word_offset),
+
+            (0b01, 0b000) => match bits(7, 11) {
+                0 if imm6 == 0 => CNOP,
+                rd => CADDI(reg(rd)?, imm6),
+            },
+            (0b01, 0b001) => CJAL(jump),
+            (0b01, 0b010) => CLI(reg(bits(7, 11))?, imm6),
+            (0b01, 0b011) => match bits(7, 11) {
+                2 => {
+                    let imm = sign_extend(
+                        (bits(12, 12) << 9)
+                            | (bits(6, 6) << 4)
+                            | (bits(5, 5) << 6)
+                            | (bits(3, 4) << 7)
+                            | (bits(2, 2) << 5),
+                        10,
+                    );
+                    if imm == 0 {
+                        return Err(invalid);
+                    }
+                    CADDI16SP(imm)
+                }
+                rd => {
+                    if imm6 == 0 {
+                        return Err(invalid);
+                    }
+                    CLUI(reg(rd)?, imm6 << 12)
+                }
+            },
+            (0b01, 0b100) => {
+                let rd = reg_prime(bits(7, 9))?;
+                match bits(10, 11) {
+                    // shamt[5] has to be zero on RV32
+                    0b00 if bits(12, 12) == 0 => CSRLI(rd, bits(2, 6) as i32),
+                    0b01 if bits(12, 12) == 0 => CSRAI(rd, bits(2, 6) as i32),
+                    0b10 => CANDI(rd, imm6),
+                    0b11 if bits(12, 12) == 0 => {
+                        let rs2 = reg_prime(bits(2, 4))?;
+                        match bits(5, 6) {
+                            0b00 => CSUB(rd, rs2),
+                            0b01 => CXOR(rd, rs2),
+                            0b10 => COR(rd, rs2),
+                            _ => CAND(rd, rs2),
+                        }
+                    }
+                    _ => return Err(invalid),
+                }
+            }
+            (0b01, 0b101) => CJ(jump),
+            (0b01, 0b110) => CBEQZ(reg_prime(bits(7, 9))?, branch),
+            (0b01, 0b111) => CBNEZ(reg_prime(bits(7, 9))?, branch),
+
+            (0b10, 0b000) if bits(12, 12) == 0 => CSLLI(reg(bits(7, 11))?, bits(2, 6) as i32),
+            (0b10, 0b001) => {
+                let imm = (bits(12, 12) << 5) | (bits(5, 6) << 3) | (bits(2, 4) << 6);
+                CFLDSP(freg(bits(7, 11))?, imm as i32)
+            }
+            (0b10, 0b010) => {
+                let rd = bits(7, 11);
+                if rd == 0 {
+                    return Err(invalid);
+                }
+                let imm = (bits(12, 12) << 5) | (bits(4, 6) << 2) | (bits(2, 3) << 6);
+                CLWSP(reg(rd)?, imm as i32)
+            }
+            (0b10, 0b011) => {
+                let imm = (bits(12, 12) << 5) | (bits(4, 6) << 2) | (bits(2, 3) << 6);
+                CFLWSP(freg(bits(7, 11))?, imm as i32)
+            }
+            (0b10, 0b100) => match (bits(12, 12), bits(7, 11), bits(2, 6)) {
+                (0, 0, 0) => return Err(invalid),
+                (0, rs1, 0) => CJR(reg(rs1)?),
+                (0, rd, rs2) => CMV(reg(rd)?, reg(rs2)?),
+                (_, 0, 0) => CEBREAK,
+                (_, rs1, 0) => CJALR(reg(rs1)?),
+                (_, rd, rs2) => CADD(reg(rd)?, reg(rs2)?),
+            },
+            (0b10, 0b101) => {
+                let imm = (bits(10, 12) << 3) | (bits(7, 9) << 6);
+                CFSDSP(freg(bits(2, 6))?, imm as i32)
+            }
+            (0b10, 0b110) => {
+                let imm = (bits(9, 12) << 2) | (bits(7, 8) << 6);
+                CSWSP(reg(bits(2, 6))?, imm as i32)
+            }
+            (0b10, 0b111) => {
+                let imm = (bits(9, 12) << 2) | (bits(7, 8) << 6);
+                CFSWSP(freg(bits(2, 6))?, imm as i32)
+            }
+            // The remaining encodings are reserved or RV64/128 only
+            _ => return Err(invalid),
+        })
+    }
+
+    /// The 32-bit instruction this one stands for
+    pub fn expand(&self) -> Operation {
+        use Operation::*;
+        use Register::{Zero, RA, SP};
+        match *self {
+            CompressedOp::CADDI4SPN(rd, imm) => ADDI(rd, SP, imm),
+            CompressedOp::CFLD(rd, rs1, imm) => FLD(rd, rs1, imm),
+            CompressedOp::CLW(rd, rs1, imm) => LW(rd, rs1, imm),
+            CompressedOp::CFSD(rs1, rs2, imm) => FSD(rs1, rs2, imm),
+            CompressedOp::CSW(rs1, rs2, imm) => SW(rs1, rs2, imm),
+            CompressedOp::CFLW(rd, rs1, imm) => FLW(rd, rs1, imm),
+            CompressedOp::CFSW(rs1, rs2, imm) => FSW(rs1, rs2, imm),
+            CompressedOp::CNOP => NOP,
+            CompressedOp::CADDI(rd, imm) => ADDI(rd, rd, imm),
+            CompressedOp::CJAL(imm) => JAL(RA, imm),
+            CompressedOp::CLI(rd, imm) => ADDI(rd, Zero, imm),
+            CompressedOp::CADDI16SP(imm) => ADDI(SP, SP, imm),
+            CompressedOp::CLUI(rd, imm) => LUI(rd, imm),
+            CompressedOp::CSRLI(rd, shamt) => SRLI(rd, rd, shamt),
+            CompressedOp::CSRAI(rd, shamt) => SRAI(rd, rd, shamt),
+            CompressedOp::CANDI(rd, imm) => ANDI(rd, rd, imm),
+            CompressedOp::CSUB(rd, rs2) => SUB(rd, rd, rs2),
+            CompressedOp::CXOR(rd, rs2) => XOR(rd, rd, rs2),
+            CompressedOp::COR(rd, rs2) => OR(rd, rd, rs2),
+            CompressedOp::CAND(rd, rs2) => AND(rd, rd, rs2),
+            CompressedOp::CJ(imm) => JAL(Zero, imm),
+            CompressedOp::CBEQZ(rs1, imm) => BEQ(rs1, Zero, imm),
+            CompressedOp::CBNEZ(rs1, imm) => BNE(rs1, Zero, imm),
+            CompressedOp::CSLLI(rd, shamt) => SLLI(rd, rd, shamt),
+            CompressedOp::CFLDSP(rd, imm) => FLD(rd, SP, imm),
+            CompressedOp::CLWSP(rd, imm) => LW(rd, SP, imm),
+            CompressedOp::CJR(rs1) => JALR(Zero, rs1, 0),
+            CompressedOp::CMV(rd, rs2) => ADD(rd, Zero, rs2),
+            CompressedOp::CEBREAK => EBREAK,
+            CompressedOp::CJALR(rs1) => JALR(RA, rs1, 0),
+            CompressedOp::CADD(rd, rs2) => ADD(rd, rd, rs2),
+            CompressedOp::CFSDSP(rs2, imm) => FSD(SP, rs2, imm),
+            CompressedOp::CSWSP(rs2, imm) => SW(SP, rs2, imm),
+            CompressedOp::CFLWSP(rd, imm) => FLW(rd, SP, imm),
+            CompressedOp::CFSWSP(rs2, imm) => FSW(SP, rs2, imm),
+        }
+    }
+
+    pub fn mnemonic(&self) -> &'static str {
+        use CompressedOp::*;
+        match self {
+            CADDI4SPN(..) => "C.ADDI4SPN",
+            CFLD(..) => "C.FLD",
+            CLW(..) => "C.LW",
+            CFSD(..) => "C.FSD",
+            CSW(..) => "C.SW",
+            CFLW(..) => "C.FLW",
+            CFSW(..) => "C.FSW",
+            CNOP => "C.NOP",
+            CADDI(..) => "C.ADDI",
+            CJAL(..) => "C.JAL",
+            CLI(..) => "C.LI",
+            CADDI16SP(..) => "C.ADDI16SP",
+            CLUI(..) => "C.LUI",
+            CSRLI(..) => "C.SRLI",
+            CSRAI(..) => "C.SRAI",
+            CANDI(..) => "C.ANDI",
+            CSUB(..) => "C.SUB",
+            CXOR(..) => "C.XOR",
+            COR(..) => "C.OR",
+            CAND(..) => "C.AND",
+            CJ(..) => "C.J",
+            CBEQZ(..) => "C.BEQZ",
+            CBNEZ(..) => "C.BNEZ",
+            CSLLI(..) => "C.SLLI",
+            CFLDSP(..) => "C.FLDSP",
+            CLWSP(..) => "C.LWSP",
+            CJR(..) => "C.JR",
+            CMV(..) => "C.MV",
+            CEBREAK => "C.EBREAK",
+            CJALR(..) => "C.JALR",
+            CADD(..) => "C.ADD",
+            CFSDSP(..) => "C.FSDSP",
+            CSWSP(..) => "C.SWSP",
+            CFLWSP(..) => "C.FLWSP",
+            CFSWSP(..) => "C.FSWSP",
+        }
+    }
+}
+
+impl fmt::Display for CompressedOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use CompressedOp::*;
+        let name = self.mnemonic();
+        match self {
+            CNOP | CEBREAK => write!(f, "{name}"),
+            CJAL(imm) | CJ(imm) | CADDI16SP(imm) => write!(f, "{name:<10} {imm:#x}"),
+            CJR(r) | CJALR(r) => write!(f, "{name:<10} {r}"),
+            CADDI4SPN(r, imm) | CADDI(r, imm) | CLI(r, imm) | CLUI(r, imm) | CSRLI(r, imm)
+            | CSRAI(r, imm) | CANDI(r, imm) | CBEQZ(r, imm) | CBNEZ(r, imm) | CSLLI(r, imm)
+            | CLWSP(r, imm) | CSWSP(r, imm) => write!(f, "{name:<10} {r}, {imm:#x}"),
+            CSUB(r1, r2) | CXOR(r1, r2) | COR(r1, r2) | CAND(r1, r2) | CMV(r1, r2) | CADD(r1, r2) => {
+                write!(f, "{name:<10} {r1}, {r2}")
+            }
+            CFLWSP(r, imm) | CFSWSP(r, imm) | CFLDSP(r, imm) | CFSDSP(r, imm) => write!(f, "{name:<10} {r}, {imm:#x}"),
+            CLW(r1, r2, imm) | CSW(r1, r2, imm) => write!(f, "{name:<10} {r1}, {r2}, {imm:#x}"),
+            CFLW(r1, r2, imm) | CFLD(r1, r2, imm) => write!(f, "{name:<10} {r1}, {r2}, {imm:#x}"),
+            CFSW(r1, r2, imm) | CFSD(r1, r2, imm) => write!(f, "{name:<10} {r1}, {r2}, {imm:#x}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use CompressedOp::*;
+    use Register::*;
+
+    fn decode(half: u16) -> Result<CompressedOp, ParseError> {
+        CompressedOp::from_bytes(&half.to_le_bytes())
+    }
+
+    #[test]
+    fn test_decode() {
+        // Encodings from GNU objdump
+        assert_eq!(decode(0x4505), Ok(CLI(A0, 1)));
+        assert_eq!(decode(0x1141), Ok(CADDI(SP, -16)));
+        assert_eq!(decode(0x0141), Ok(CADDI(SP, 16)));
+        assert_eq!(decode(0x8082), Ok(CJR(RA)));
+        assert_eq!(decode(0x852e), Ok(CMV(A0, A1)));
+        assert_eq!(decode(0xc606), Ok(CSWSP(RA, 12)));
+        assert_eq!(decode(0x40b2), Ok(CLWSP(RA, 12)));
+        assert_eq!(decode(0x0800), Ok(CADDI4SPN(S0, 16)));
+        assert_eq!(decode(0x411c), Ok(CLW(A5, A0, 0)));
+        assert_eq!(decode(0xc11c), Ok(CSW(A0, A5, 0)));
+        assert_eq!(decode(0x9002), Ok(CEBREAK));
+        assert_eq!(decode(0x0001), Ok(CNOP));
+        assert_eq!(decode(0x7179), Ok(CADDI16SP(-48)));
+        assert_eq!(decode(0x6785), Ok(CLUI(A5, 0x1000)));
+        assert_eq!(decode(0x8d89), Ok(CSUB(A1, A0)));
+        assert_eq!(decode(0x0000), Err(ParseError::InvalidInstruction(0)));
+        assert_eq!(decode(0x6108), Ok(CFLW(FRegister::FA0, A0, 0)));
+        assert_eq!(decode(0xe188), Ok(CFSW(A1, FRegister::FA0, 0)));
+        assert_eq!(decode(0x6532), Ok(CFLWSP(FRegister::FA0, 12)));
+        assert_eq!(decode(0xe62a), Ok(CFSWSP(FRegister::FA0, 12)));
+        assert_eq!(decode(0x2508), Ok(CFLD(FRegister::FA0, A0, 8)));
+        assert_eq!(decode(0xa588), Ok(CFSD(A1, FRegister::FA0, 8)));
+        assert_eq!(decode(0x2522), Ok(CFLDSP(FRegister::FA0, 8)));
+        assert_eq!(decode(0xa42a), Ok(CFSDSP(FRegister::FA0, 8)));
+    }
+
+    #[test]
+    fn test_expand() {
+        assert_eq!(CLI(A0, 1).expand(), Operation::ADDI(A0, Zero, 1));
+        assert_eq!(CJR(RA).expand(), Operation::JALR(Zero, RA, 0));
+        assert_eq!(CSWSP(RA, 12).expand(), Operation::SW(SP, RA, 12));
+        // Hints write x0, so the full size instruction does nothing just the same
+        assert_eq!(CMV(Zero, A0).expand(), Operation::ADD(Zero, Zero, A0));
+    }
+
+    #[test]
+    fn test_jump_offsets() {
+        // c.j -2 and c.beqz a0, -2, the only set bits in the offset are the sign and bit 1
+        assert_eq!(decode(0xbffd), Ok(CJ(-2)));
+        assert_eq!(decode(0xdd7d), Ok(CBEQZ(A0, -2)));
+        // c.jal 2046 and c.bnez s0, 254, the largest forward offsets
+        assert_eq!(decode(0x2ffd), Ok(CJAL(2046)));
+        assert_eq!(decode(0xec7d), Ok(CBNEZ(S0, 254)));
+    }
+}
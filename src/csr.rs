@@ -0,0 +1,244 @@
+use crate::machine::ExecutionError;
+
+use serde::Serialize;
+
+// Floating point control and status, `fcsr` is `frm` and `fflags` together
+pub const FFLAGS: u16 = 0x001;
+pub const FRM: u16 = 0x002;
+pub const FCSR: u16 = 0x003;
+
+// Machine information
+pub const MHARTID: u16 = 0xF14;
+
+// Machine trap setup and handling
+pub const MSTATUS: u16 = 0x300;
+pub const MISA: u16 = 0x301;
+pub const MTVEC: u16 = 0x305;
+pub const MSCRATCH: u16 = 0x340;
+pub const MEPC: u16 = 0x341;
+pub const MCAUSE: u16 = 0x342;
+pub const MTVAL: u16 = 0x343;
+
+// Unprivileged counters, the `h` versions are the upper 32 bits
+pub const CYCLE: u16 = 0xC00;
+pub const TIME: u16 = 0xC01;
+pub const INSTRET: u16 = 0xC02;
+pub const CYCLEH: u16 = 0xC80;
+pub const TIMEH: u16 = 0xC81;
+pub const INSTRETH: u16 = 0xC82;
+
+/// Every CSR the simulator has, in the order they are dumped
+pub const CSRS: [(u16, &str); 17] = [
+    (FFLAGS, "fflags"),
+    (FRM, "frm"),
+    (FCSR, "fcsr"),
+    (CYCLE, "cycle"),
+    (CYCLEH, "cycleh"),
+    (TIME, "time"),
+    (TIMEH, "timeh"),
+    (INSTRET, "instret"),
+    (INSTRETH, "instreth"),
+    (MHARTID, "mhartid"),
+    (MISA, "misa"),
+    (MSTATUS, "mstatus"),
+    (MTVEC, "mtvec"),
+    (MSCRATCH, "mscratch"),
+    (MEPC, "mepc"),
+    (MCAUSE, "mcause"),
+    (MTVAL, "mtval"),
+];
+
+// mstatus fields. Only machine mode exists, so MPP always reads back as machine mode
+pub const MSTATUS_MIE: u32 = 1 << 3;
+pub const MSTATUS_MPIE: u32 = 1 << 7;
+pub const MSTATUS_MPP: u32 = 0b11 << 11;
+
+// mcause values for the exceptions the simulator raises
+pub const CAUSE_MISALIGNED_FETCH: u32 = 0;
+pub const CAUSE_FETCH_ACCESS: u32 = 1;
+pub const CAUSE_ILLEGAL_INSTRUCTION: u32 = 2;
+pub const CAUSE_BREAKPOINT: u32 = 3;
+pub const CAUSE_MISALIGNED_LOAD: u32 = 4;
+pub const CAUSE_LOAD_ACCESS: u32 = 5;
+pub const CAUSE_MISALIGNED_STORE: u32 = 6;
+pub const CAUSE_STORE_ACCESS: u32 = 7;
+pub const CAUSE_MACHINE_ECALL: u32 = 11;
+
+pub fn name(csr: u16) -> Option<&'static str> {
+    CSRS.iter().find(|(num, _)| *num == csr).map(|(_, name)| *name)
+}
+
+/// The name of a CSR along with its number, for error messages
+pub fn describe(csr: u16) -> String {
+    match name(csr) {
+        Some(name) => format!("{name} ({csr:#x})"),
+        None => format!("{csr:#x}"),
+    }
+}
+
+/// Look up a CSR by name or number, the way the assembler and debugger take them
+pub fn parse(s: &str) -> Option<u16> {
+    let s = s.trim();
+    CSRS.iter()
+        .find(|(_, name)| name.eq_ignore_ascii_case(s))
+        .map(|(num, _)| *num)
+        .or_else(|| match s.strip_prefix("0x") {
+            Some(hex) => u16::from_str_radix(hex, 16).ok(),
+            None => s.parse().ok(),
+        })
+        .filter(|num| *num < 0x1000)
+}
+
+/// `misa` for RV32 with the given extension letters
+pub fn misa_for(extensions: &str) -> u32 {
+    let mxl = 1 << 30;
+    extensions
+        .chars()
+        .filter(|c| c.is_ascii_alphabetic())
+        .fold(mxl, |misa, c| misa | 1 << (c.to_ascii_lowercase() as u32 - 'a' as u32))
+}
+
+/// The control and status registers that hold state of their own. The counters are backed by the
+/// machine's cycle count, which is passed in when they are read.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct CsrFile {
+    pub fflags: u32,
+    pub frm: u32,
+    pub mhartid: u32,
+    pub misa: u32,
+    pub mstatus: u32,
+    pub mtvec: u32,
+    pub mscratch: u32,
+    pub mepc: u32,
+    pub mcause: u32,
+    pub mtval: u32,
+}
+
+impl Default for CsrFile {
+    fn default() -> Self {
+        CsrFile {
+            fflags: 0,
+            frm: 0,
+            mhartid: 0,
+            misa: misa_for("imafdc"),
+            mstatus: MSTATUS_MPP,
+            mtvec: 0,
+            mscratch: 0,
+            mepc: 0,
+            mcause: 0,
+            mtval: 0,
+        }
+    }
+}
+
+impl CsrFile {
+    /// Whether the program has installed a trap handler. Without one, exceptions stop the
+    /// simulation instead.
+    pub fn has_trap_handler(&self) -> bool {
+        self.mtvec & !0b11 != 0
+    }
+
+    /// Record an exception and hand back the address of the handler. Exceptions always go to
+    /// the base address, even in vectored mode.
+    pub fn trap(&mut self, pc: u32, cause: u32, tval: u32) -> u32 {
+        self.mepc = pc;
+        self.mcause = cause;
+        self.mtval = tval;
+        // Interrupts are disabled in the handler, with the old setting saved in MPIE
+        let mie = self.mstatus & MSTATUS_MIE != 0;
+        self.mstatus &= !(MSTATUS_MIE | MSTATUS_MPIE);
+        if mie {
+            self.mstatus |= MSTATUS_MPIE;
+        }
+        self.mtvec & !0b11
+    }
+
+    /// Return from a trap handler, handing back the address to return to
+    pub fn mret(&mut self) -> u32 {
+        let mpie = self.mstatus & MSTATUS_MPIE != 0;
+        self.mstatus &= !MSTATUS_MIE;
+        if mpie {
+            self.mstatus |= MSTATUS_MIE;
+        }
+        self.mstatus |= MSTATUS_MPIE;
+        self.mepc
+    }
+
+    pub fn read(&self, csr: u16, cycle: u128) -> Result<u32, ExecutionError> {
+        Ok(match csr {
+            FFLAGS => self.fflags,
+            FRM => self.frm,
+            FCSR => (self.frm << 5) | self.fflags,
+            // Every instruction takes one cycle, and there is no real time to speak of
+            CYCLE | TIME | INSTRET => cycle as u32,
+            CYCLEH | TIMEH | INSTRETH => (cycle >> 32) as u32,
+            MHARTID => self.mhartid,
+            MISA => self.misa,
+            MSTATUS => self.mstatus,
+            MTVEC => self.mtvec,
+            MSCRATCH => self.mscratch,
+            MEPC => self.mepc,
+            MCAUSE => self.mcause,
+            MTVAL => self.mtval,
+            _ => return Err(ExecutionError::IllegalCsrAccess(csr)),
+        })
+    }
+
+    pub fn write(&mut self, csr: u16, value: u32) -> Result<(), ExecutionError> {
+        // The top two bits of the number being set marks a read-only CSR
+        if csr >> 10 == 0b11 {
+            return Err(ExecutionError::IllegalCsrAccess(csr));
+        }
+        match csr {
+            FFLAGS => self.fflags = value & 0x1F,
+            FRM => self.frm = value & 0x7,
+            FCSR => {
+                self.fflags = value & 0x1F;
+                self.frm = (value >> 5) & 0x7;
+            }
+            // The extensions can't be turned on and off, so writes are ignored
+            MISA => {}
+            MSTATUS => self.mstatus = (value & (MSTATUS_MIE | MSTATUS_MPIE)) | MSTATUS_MPP,
+            // Modes 2 and 3 are reserved, so only direct and vectored can be set
+            MTVEC => self.mtvec = value & !0b10,
+            MSCRATCH => self.mscratch = value,
+            // Instructions are at least 2-byte aligned
+            MEPC => self.mepc = value & !1,
+            MCAUSE => self.mcause = value,
+            MTVAL => self.mtval = value,
+            _ => return Err(ExecutionError::IllegalCsrAccess(csr)),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_csr_access() {
+        let mut csrs = CsrFile::default();
+        assert_eq!(csrs.read(MISA, 0), Ok(0x4000112d));
+        assert_eq!(csrs.read(CYCLEH, 0x1_0000_0002), Ok(1));
+        assert_eq!(csrs.write(MEPC, 0x101), Ok(()));
+        assert_eq!(csrs.read(MEPC, 0), Ok(0x100));
+        assert_eq!(csrs.write(MSTATUS, 0xFFFFFFFF), Ok(()));
+        assert_eq!(csrs.read(MSTATUS, 0), Ok(MSTATUS_MIE | MSTATUS_MPIE | MSTATUS_MPP));
+        assert_eq!(csrs.write(CYCLE, 0), Err(ExecutionError::IllegalCsrAccess(CYCLE)));
+        assert_eq!(csrs.write(MHARTID, 0), Err(ExecutionError::IllegalCsrAccess(MHARTID)));
+        assert_eq!(csrs.read(0x7C0, 0), Err(ExecutionError::IllegalCsrAccess(0x7C0)));
+        assert_eq!(csrs.write(FCSR, 0xFFFFFFFF), Ok(()));
+        assert_eq!((csrs.read(FFLAGS, 0), csrs.read(FRM, 0)), (Ok(0x1F), Ok(0x7)));
+        assert_eq!(csrs.read(FCSR, 0), Ok(0xFF));
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse("mstatus"), Some(MSTATUS));
+        assert_eq!(parse("0xc00"), Some(CYCLE));
+        assert_eq!(parse("833"), Some(MEPC));
+        assert_eq!(parse("0x1000"), None);
+        assert_eq!(parse("mscratchy"), None);
+    }
+}
@@ -1,6 +1,13 @@
-use crate::register::Register;
+use crate::breakpoint::{Breakpoint, Condition, Watch, Watchpoint};
+use crate::loader::{self, ExportFormat};
+use crate::register::{FRegister, Register};
+use crate::softfloat;
 use crate::machine::{Machine,ExecutionError};
+use crate::symbols::SymbolTable;
+use clap::ValueEnum;
 use thiserror::Error;
+use std::fs;
+use std::ops::RangeInclusive;
 use std::str::FromStr;
 use std::fmt;
 
@@ -11,17 +18,32 @@ use std::fmt;
 /// PEEK    [format]    <addr/reg>      # Read data at a memory location or from a register
 ///                                     # NOTE:  s0 shows the integer in s0
 ///                                     #       [s0] dereferences s0 and shows memory contents
+///                                     #       f0-f31 also show the value they hold
 /// POKE    <data>      <addr/reg>      # Modify data at a memory location or in a register
-/// WATCH   [format]    <addr/reg>      # Read data every time control is returned
+///                                     # Data for f0-f31 is the bits of a single
+/// DISPLAY [format]    <addr/reg>      # Read data every time control is returned
 ///                                     # to the debugger
+/// UNDISPLAY           <addr/reg>      # Stop displaying a variable
+/// WATCH   [r|w|rw] <addr> [len]       # Stop when a load or store touches memory,
+///                                     # stores only by default, 4 bytes by default
+/// WATCH   <reg>                       # Stop when an instruction changes a register
+/// RMWATCH <num>                       # Remove a watchpoint by index
+/// LSWATCH                             # List out all watchpoints
 /// STEP    [count]                     # Step once, or the given number of times
-/// BREAK   [address]                   # Set a breakpoint at the given address
+/// BSTEP   [count]                     # Step backwards once, or the given number of times
+/// BREAK   [address] [IF <condition>]  # Set a breakpoint at the given address
+///                                     # Addresses can be symbols like main or outs+0x10
+///                                     # Conditions compare registers and memory, like
+///                                     # a0 == 5 && [sp+8] != 0
 /// RMBRK   [address/num]               # Remove a breakpoint at the given address
 ///                                     # or by breakpoint index
-/// LSBRK                               # List out all breakpoints
+/// IGNORE  <num>       <count>         # Run through the next count hits of a breakpoint
+/// LSBRK                               # List out all breakpoints and their hit counts
+/// DUMP    <start-end> <file> [format] # Write memory to a file as mem, ihex or bin
 /// CONTINUE                            # Return control to the program and run
 ///                                     # until a breakpoint is hit
 /// RUN                                 # Synonym for CONTINUE
+/// RCONTINUE                           # Run backwards until a breakpoint or watchpoint
 /// EXIT                                # Close the emulator
 /// HELP                                # Show this help message
 
@@ -52,6 +74,8 @@ impl DisplayFormat {
 pub enum Location {
     /// A register number
     Register(Register),
+    /// A floating point register
+    FRegister(FRegister),
     /// A memory address
     Addr(u32)
 }
@@ -59,6 +83,7 @@ impl fmt::Display for Location {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Location::Register(r) => write!(f, "{:?}", r),
+            Location::FRegister(r) => write!(f, "{:?}", r),
             Location::Addr(a) => write!(f, "{a:#x}"),
 
         }
@@ -66,30 +91,67 @@ impl fmt::Display for Location {
 }
 
 impl Location {
-    pub fn parse(s: &str) -> Result<Self,DebugParseError> {
+    pub fn parse(s: &str, symbols: &SymbolTable) -> Result<Self,DebugParseError> {
         match Register::from_str(s) {
             // If in parses as a register the use that
             Ok(reg) => Ok(Location::Register(reg)),
-            Err(_) => {
-                // Otherwise try hex
-                let addr_str = s.trim_start_matches("0x").trim_start_matches("0X");
-                Ok(Location::Addr(
-                        u32::from_str_radix(addr_str,16)
-                        .map_err(|_| DebugParseError::InvalidLocation(s.to_string()))?))
+            // Floating point registers have to come before addresses, `f3` is valid hex
+            Err(_) => match FRegister::parse(s) {
+                Some(reg) => Ok(Location::FRegister(reg)),
+                None => Ok(Location::Addr(parse_address(s, symbols)
+                            .map_err(|_| DebugParseError::InvalidLocation(s.to_string()))?))
             }
-
         }
     }
 }
+
+/// Parse an address as a symbol, optionally with an offset, or as hex. Symbols are checked first
+/// so that names which happen to be valid hex, like `add`, still work.
+fn parse_address(s: &str, symbols: &SymbolTable) -> Result<u32,DebugParseError> {
+    if let Some(addr) = symbols.resolve(s) {
+        return Ok(addr);
+    }
+    let addr_str = s.trim_start_matches("0x").trim_start_matches("0X");
+    u32::from_str_radix(addr_str,16).map_err(|_| DebugParseError::InvalidHex(s.to_string()))
+}
+
+/// Parse a length in bytes, as decimal or as hex with 0x
+fn parse_length(s: &str) -> Result<u32,DebugParseError> {
+    let len = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex,16).map_err(|_| DebugParseError::InvalidHex(s.to_string()))?,
+        None => u32::from_str(s).map_err(|_| DebugParseError::InvalidNumber(s.to_string()))?,
+    };
+    if len == 0 {
+        return Err(DebugParseError::InvalidNumber(s.to_string()));
+    }
+    Ok(len)
+}
+
+/// Explain why running backwards stopped short
+fn end_of_history(undone: usize) -> String {
+    match undone {
+        0 => "There is no history to go back through".to_string(),
+        1 => "Reached the start of the history after going back 1 instruction".to_string(),
+        n => format!("Reached the start of the history after going back {n} instructions"),
+    }
+}
+
+/// Format an address with the symbol it falls in, if there is one
+fn describe_address(addr: u32, symbols: &SymbolTable) -> String {
+    match symbols.symbolize(addr) {
+        Some(name) => format!("{addr:#010x} <{name}>"),
+        None => format!("{addr:#010x}"),
+    }
+}
 #[derive(Clone,PartialEq,Debug)]
 pub enum BreakpointIdentifier {
     Addr(u32),
     Index(usize)
 }
 // These are either an address or an integer index
-// Addresses are formatted as 0x.... or 0X....
+// Addresses are formatted as 0x.... or 0X...., or are a symbol name
 impl BreakpointIdentifier {
-    pub fn parse(s: &str) -> Result<Self,DebugParseError> {
+    pub fn parse(s: &str, symbols: &SymbolTable) -> Result<Self,DebugParseError> {
         // Parse as an address if the string starts with 0x
         if s.to_lowercase().starts_with("0x") {
             Ok(BreakpointIdentifier::Addr(
@@ -99,9 +161,12 @@ impl BreakpointIdentifier {
 
 
         // Or as a decimal index otherwise
+        } else if let Ok(index) = s.parse::<usize>() {
+            Ok(BreakpointIdentifier::Index(index))
+        // And finally as a symbol
         } else {
-            Ok(BreakpointIdentifier::Index(usize::from_str_radix(s,10)
-                         .map_err(|_| DebugParseError::InvalidNumber(s.to_string()))?
+            Ok(BreakpointIdentifier::Addr(symbols.resolve(s)
+                         .ok_or(DebugParseError::InvalidNumber(s.to_string()))?
                          ))
         }
     }
@@ -183,19 +248,30 @@ pub enum DebugParseError {
     FailedToParseSuffix(String),
     #[error("Invalid hex number `{0}`")]
     InvalidHex(String),
+    #[error("Invalid range: {0}")]
+    InvalidRange(String),
+    #[error("Invalid condition: {0}")]
+    InvalidCondition(String),
 }
 #[derive(Clone,PartialEq,Debug)]
 pub enum DebugCommand {
     PEEK(DisplayFormat,Location),
-    WATCH(DisplayFormat,Location),
-    RMWATCH(Location),
+    DISPLAY(DisplayFormat,Location),
+    UNDISPLAY(Location),
+    WATCH(Watch),
+    RMWATCH(usize),
+    LSWATCH,
     // Depending on number of characters in data will pick byte, halfword, or word, smallest first
     POKE(Data,Location),
     STEP(usize),
-    BREAK(u32),
+    BSTEP(usize),
+    BREAK(u32,Option<Condition>),
     RMBRK(BreakpointIdentifier),
+    IGNORE(usize,u64),
     LSBRK,
+    DUMP(RangeInclusive<u32>,String,Option<ExportFormat>),
     CONTINUE,
+    RCONTINUE,
     HELP,
     EXIT
 
@@ -203,72 +279,133 @@ pub enum DebugCommand {
 }
 
 impl DebugCommand {
+    #[cfg(test)]
     pub fn from_string(s: &str) -> Result<Self,DebugParseError> {
+        DebugCommand::from_string_with_symbols(s, &SymbolTable::default())
+    }
+    /// Parse a command, allowing symbol names anywhere an address is expected
+    pub fn from_string_with_symbols(s: &str, symbols: &SymbolTable) -> Result<Self,DebugParseError> {
         let mut iterator = s.trim().split(' ');
         let first = iterator.next().ok_or(DebugParseError::Empty)?.to_lowercase();
         let mut rest: Vec<&str> = iterator.collect();
         let command = match first.as_str() {
             "peek" => {
                 if rest.len() > 2 { return Err(DebugParseError::TooManyArguments) };
-                let location = Location::parse(rest.pop().ok_or(DebugParseError::NotEnoughArguments)?)?;
+                let location = Location::parse(rest.pop().ok_or(DebugParseError::NotEnoughArguments)?, symbols)?;
                 let format = match rest.pop() {
                     Some(s) => DisplayFormat::parse(s)?,
                     None => DisplayFormat::default()
                 };
                 DebugCommand::PEEK(format,location)
             },
-            "watch" => {
+            "display" => {
                 if rest.len() > 2 { return Err(DebugParseError::TooManyArguments) };
-                let location = Location::parse(rest.pop().ok_or(DebugParseError::NotEnoughArguments)?)?;
+                let location = Location::parse(rest.pop().ok_or(DebugParseError::NotEnoughArguments)?, symbols)?;
                 let format = match rest.pop() {
                     Some(s) => DisplayFormat::parse(s)?,
                     None => DisplayFormat::default()
                 };
-                DebugCommand::WATCH(format,location)
+                DebugCommand::DISPLAY(format,location)
+            },
+            "undisplay" => {
+                if rest.len() > 1 { return Err(DebugParseError::TooManyArguments) };
+                let location = Location::parse(rest.pop().ok_or(DebugParseError::NotEnoughArguments)?, symbols)?;
+                DebugCommand::UNDISPLAY(location)
+            },
+            "watch" => {
+                // Stores only unless it says otherwise, like gdb's watch
+                let (read, write) = match rest.first().map(|s| s.to_lowercase()).as_deref() {
+                    Some("r") => (true, false),
+                    Some("w") => (false, true),
+                    Some("rw") => (true, true),
+                    _ => (false, false),
+                };
+                let kind_given = read || write;
+                if kind_given { rest.remove(0); }
+                if rest.len() > 2 { return Err(DebugParseError::TooManyArguments) };
+                let target = *rest.first().ok_or(DebugParseError::NotEnoughArguments)?;
+                match Location::parse(target, symbols)? {
+                    Location::Register(reg) if !kind_given && rest.len() == 1 => DebugCommand::WATCH(Watch::Register(reg)),
+                    Location::Addr(addr) => {
+                        let len = match rest.get(1) {
+                            Some(len) => parse_length(len)?,
+                            None => 4,
+                        };
+                        let end = addr.checked_add(len - 1)
+                                      .ok_or(DebugParseError::InvalidRange(format!("{len} bytes at {addr:#x} run past the end of memory")))?;
+                        DebugCommand::WATCH(Watch::Memory { range: addr..=end, read, write: write || !kind_given })
+                    },
+                    _ => return Err(DebugParseError::InvalidLocation(target.to_string()))
+                }
             },
             "rmwatch" => {
                 if rest.len() > 1 { return Err(DebugParseError::TooManyArguments) };
-                let location = Location::parse(rest.pop().ok_or(DebugParseError::NotEnoughArguments)?)?;
-                DebugCommand::RMWATCH(location)
+                let num_str = rest.pop().ok_or(DebugParseError::NotEnoughArguments)?;
+                DebugCommand::RMWATCH(usize::from_str(num_str)
+                                            .map_err(|_| DebugParseError::InvalidNumber(num_str.to_owned()))?)
             },
             "poke" => {
                 if rest.len() > 2 { return Err(DebugParseError::TooManyArguments) };
                 let data = Data::parse(rest.pop().ok_or(DebugParseError::NotEnoughArguments)?)?;
-                let location = Location::parse(rest.pop().ok_or(DebugParseError::NotEnoughArguments)?)?;
+                let location = Location::parse(rest.pop().ok_or(DebugParseError::NotEnoughArguments)?, symbols)?;
                 DebugCommand::POKE(data,location)
 
             },
-            "step" => {
+            "step" | "bstep" => {
                 if rest.len() > 1 { return Err(DebugParseError::TooManyArguments) };
-                match rest.pop() {
-                    None => DebugCommand::STEP(1),
-                    Some(num_str) => {
-                        let num = usize::from_str(num_str)
-                                        .map_err(|_| DebugParseError::InvalidNumber(num_str.to_owned()))?;
-                        DebugCommand::STEP(num)
-                    }
-
-                }
+                let num = match rest.pop() {
+                    None => 1,
+                    Some(num_str) => usize::from_str(num_str)
+                                          .map_err(|_| DebugParseError::InvalidNumber(num_str.to_owned()))?
+                };
+                if first == "step" { DebugCommand::STEP(num) } else { DebugCommand::BSTEP(num) }
             },
             "break" => {
-                if rest.len() > 2 { return Err(DebugParseError::TooManyArguments) };
+                // Everything after IF is the condition, which can have spaces in it
+                let condition = match rest.iter().position(|s| s.eq_ignore_ascii_case("if")) {
+                    Some(index) => {
+                        let condition = rest.split_off(index)[1..].join(" ");
+                        Some(Condition::parse(&condition, symbols).map_err(DebugParseError::InvalidCondition)?)
+                    },
+                    None => None
+                };
+                if rest.len() > 1 { return Err(DebugParseError::TooManyArguments) };
                 let location = rest.pop().ok_or(DebugParseError::NotEnoughArguments)?;
-                let address = location.trim_start_matches("0x").trim_start_matches("0X");
-                DebugCommand::BREAK(
-                        u32::from_str_radix(address,16)
-                        .map_err(|_| DebugParseError::InvalidHex(address.to_string()))?)
+                DebugCommand::BREAK(parse_address(location, symbols)?,condition)
 
             },
             "rmbrk" => {
                 if rest.len() > 1 { return Err(DebugParseError::TooManyArguments) };
-                let brk = BreakpointIdentifier::parse(rest.pop().ok_or(DebugParseError::NotEnoughArguments)?)?;
+                let brk = BreakpointIdentifier::parse(rest.pop().ok_or(DebugParseError::NotEnoughArguments)?, symbols)?;
                 DebugCommand::RMBRK(brk)
 
             },
+            "ignore" => {
+                if rest.len() > 2 { return Err(DebugParseError::TooManyArguments) };
+                if rest.len() < 2 { return Err(DebugParseError::NotEnoughArguments) };
+                let index = usize::from_str(rest[0])
+                                  .map_err(|_| DebugParseError::InvalidNumber(rest[0].to_owned()))?;
+                let count = u64::from_str(rest[1])
+                                .map_err(|_| DebugParseError::InvalidNumber(rest[1].to_owned()))?;
+                DebugCommand::IGNORE(index,count)
+            },
+            "dump" => {
+                if rest.len() > 3 { return Err(DebugParseError::TooManyArguments) };
+                if rest.len() < 2 { return Err(DebugParseError::NotEnoughArguments) };
+                let range = loader::parse_range(rest[0]).map_err(DebugParseError::InvalidRange)?;
+                let format = match rest.get(2) {
+                    Some(f) => Some(ExportFormat::from_str(f, true)
+                                    .map_err(|_| DebugParseError::InvalidFormat(f.to_string()))?),
+                    None => None
+                };
+                DebugCommand::DUMP(range,rest[1].to_string(),format)
+            },
             "help" => DebugCommand::HELP,
             "exit" => DebugCommand::EXIT,
             "lsbrk" => DebugCommand::LSBRK,
+            "lswatch" => DebugCommand::LSWATCH,
             "run" | "continue" => DebugCommand::CONTINUE,
+            "rcontinue" => DebugCommand::RCONTINUE,
             c@ _ => return Err(DebugParseError::InvalidCommand(c.to_string()))
 
         };
@@ -280,18 +417,33 @@ impl DebugCommand {
 "PEEK    [format]    <addr/reg>      # Read data at a memory location or from a register",
 "                                    # Valid formats are /x (hex), /u (unsigned),",
 "                                    # /i (integer), and /b (binary)",
+"                                    # f0-f31 also show the value they hold",
 "POKE    <addr/reg>      <data>      # Modify data at a memory location or in a register",
-"WATCH   [format]    <addr/reg>      # Read data every time control is returned",
+"                                    # Data for f0-f31 is the bits of a single",
+"DISPLAY [format]    <addr/reg>      # Read data every time control is returned",
 "                                    # to the debugger",
-"RMWATCH             <addr/reg>      # Stop watching a variable",
+"UNDISPLAY           <addr/reg>      # Stop displaying a variable",
+"WATCH   [r|w|rw] <addr> [len]       # Stop when a load or store touches memory,",
+"                                    # stores only by default, 4 bytes by default",
+"WATCH   <reg>                       # Stop when an instruction changes a register",
+"RMWATCH <num>                       # Remove a watchpoint by index",
+"LSWATCH                             # List out all watchpoints",
 "STEP    [count]                     # Step once, or the given number of times",
-"BREAK   [address]                   # Set a breakpoint at the given address",
+"BSTEP   [count]                     # Step backwards once, or the given number of times",
+"BREAK   [address] [IF <condition>]  # Set a breakpoint at the given address",
+"                                    # Addresses can be symbols like main or outs+0x10",
+"                                    # Conditions compare registers and memory, like",
+"                                    # a0 == 5 && [sp+8] != 0",
 "RMBRK   [address/num]               # Remove a breakpoint at the given address",
 "                                    # or by breakpoint index",
-"LSBRK                               # List out all breakpoints",
+"IGNORE  <num>       <count>         # Run through the next count hits of a breakpoint",
+"LSBRK                               # List out all breakpoints and their hit counts",
+"DUMP    <start-end> <file> [format] # Write memory to a file, formats are mem,",
+"                                    # ihex and bin, chosen by extension by default",
 "CONTINUE                            # Return control to the program and run",
 "                                    # until a breakpoint is hit",
 "RUN                                 # Synonym for CONTINUE",
+"RCONTINUE                           # Run backwards until a breakpoint or watchpoint",
 "EXIT                                # Close the emulator",
 "HELP                                # Show this help message",
 ]
@@ -320,16 +472,43 @@ impl DebugCommand {
                 *should_step = Some(count);
                 *run = true;
             },
-            &DebugCommand::BREAK(addr) => {
-                if !machine.breakpoints().contains(&addr) {
-                    machine.breakpoints().push(addr)
-                } else {
-                    status.push(
-                        format!("Unable to insert duplicate breakpoint at {0:#010x}", addr));
+            // Going backwards happens right here, there is no need to go back to the run loop
+            &DebugCommand::BSTEP(count) => match machine.run_backwards(Some(count)) {
+                Ok(undone) if undone < count => status.push(end_of_history(undone)),
+                Ok(_) => {},
+                Err(e) => status.push(format!("{}",e)),
+            },
+            &DebugCommand::RCONTINUE => match machine.run_backwards(None) {
+                Ok(undone) => status.push(end_of_history(undone)),
+                Err(e) => status.push(format!("{}",e)),
+            },
+            DebugCommand::BREAK(addr,condition) => {
+                let addr = *addr;
+                match machine.breakpoints().iter().position(|b| b.addr == addr) {
+                    // Setting a breakpoint again just replaces its condition
+                    Some(index) if condition.is_some() => {
+                        machine.breakpoints()[index].condition = condition.clone();
+                        status.push(format!("Updated the condition of breakpoint {index}"));
+                    },
+                    Some(_) => status.push(
+                        format!("Unable to insert duplicate breakpoint at {0:#010x}", addr)),
+                    None => {
+                        machine.breakpoints().push(Breakpoint::new(addr,condition.clone()));
+                        status.push(format!("Added breakpoint {} at {}",machine.breakpoints().len()-1,
+                                            describe_address(addr,machine.symbols())));
+                    }
                 }
-                status.push(format!("Added breakpoint {} at {:#010x}",machine.breakpoints().len(),addr));
 
             },
+            &DebugCommand::IGNORE(index,count) => {
+                match machine.breakpoints().get_mut(index) {
+                    Some(breakpoint) => {
+                        breakpoint.ignore = count;
+                        status.push(format!("Will ignore the next {count} hits of breakpoint {index}"));
+                    },
+                    None => status.push(format!("No breakpoint {index}")),
+                }
+            },
             &DebugCommand::RMBRK(BreakpointIdentifier::Index(index)) => {
                 machine.breakpoints().remove(index);
                 status.push(format!("Successfully removed breakpoint {}",index));
@@ -337,7 +516,7 @@ impl DebugCommand {
             &DebugCommand::RMBRK(BreakpointIdentifier::Addr(address)) => {
                 let mut to_remove = None;
                 for (k,v) in machine.breakpoints().iter().enumerate() {
-                    if *v == address {
+                    if v.addr == address {
                         to_remove = Some(k);
                         break;
                     }
@@ -349,9 +528,48 @@ impl DebugCommand {
                     status.push("Unable to find breakpoint".to_string());
                 };
             },
+            DebugCommand::WATCH(watch) => {
+                machine.watchpoints().push(Watchpoint::new(watch.clone()));
+                status.push(format!("Added watchpoint {} on {watch}",machine.watchpoints().len()-1));
+            },
+            &DebugCommand::RMWATCH(index) => {
+                if index < machine.watchpoints().len() {
+                    machine.watchpoints().remove(index);
+                    status.push(format!("Successfully removed watchpoint {}",index));
+                } else {
+                    status.push(format!("No watchpoint {index}"));
+                }
+            },
+            &DebugCommand::LSWATCH => {
+                for (index,watchpoint) in machine.watchpoints().iter().enumerate() {
+                    status.push(format!("{index}: {}, hit {} time{}",watchpoint.watch,watchpoint.hits,
+                                        if watchpoint.hits == 1 { "" } else { "s" }));
+                }
+            },
             &DebugCommand::LSBRK => {
-                for (index,address) in machine.breakpoints().iter().enumerate() {
-                    status.push(format!("{index}: {address:#010x}"));
+                let breakpoints = machine.breakpoints().clone();
+                for (index,breakpoint) in breakpoints.iter().enumerate() {
+                    let mut line = format!("{index}: {}",describe_address(breakpoint.addr,machine.symbols()));
+                    if let Some(ref condition) = breakpoint.condition {
+                        line += &format!(" if {condition}");
+                    }
+                    line += &format!(", hit {} time{}",breakpoint.hits,if breakpoint.hits == 1 { "" } else { "s" });
+                    if breakpoint.ignore > 0 {
+                        line += &format!(", ignoring the next {}",breakpoint.ignore);
+                    }
+                    status.push(line);
+                }
+            },
+
+            DebugCommand::DUMP(range,file,format) => {
+                let format = format.map_or_else(|| ExportFormat::from_path(file), Ok);
+                match (format, machine.read_memory(range.clone())) {
+                    (Err(e), _) => status.push(format!("Failed to write {file} with error {e}")),
+                    (_, Err(e)) => status.push(format!("Failed to read memory with error {e}")),
+                    (Ok(format), Ok(bytes)) => match fs::write(file, format.export(*range.start(), &bytes)) {
+                        Ok(()) => status.push(format!("Wrote {} bytes to {file}",bytes.len())),
+                        Err(e) => status.push(format!("Failed to write {file} with error {e}")),
+                    },
                 }
             },
 
@@ -387,9 +605,34 @@ impl DebugCommand {
                 };
                 machine.set_reg(reg,converted_data);
             },
+            // The data is taken as the bits of a single, which gets NaN-boxed
+            &DebugCommand::POKE(ref data,Location::FRegister(reg)) => {
+                let converted_data = match *data {
+                    Data::Byte(d) => d as u32,
+                    Data::Halfword(d) => d as u32,
+                    Data::Word(d) => d as u32,
+                };
+                machine.set_freg(reg,0xFFFF_FFFF_0000_0000 | converted_data as u64);
+            },
+            // Floating point registers are 64 bits, so they are shown with the value they hold
+            DebugCommand::PEEK(fmt,Location::FRegister(reg)) => {
+                let data = machine.get_freg(*reg);
+                let value = softfloat::describe(data);
+                status.push(match fmt {
+                    DisplayFormat::Hex => format!("{reg:?}: {:#X} ({value})",data),
+                    DisplayFormat::Unsigned => format!("{reg:?}: {} ({value})",data),
+                    DisplayFormat::Signed => format!("{reg:?}: {} ({value})",data as i64),
+                    DisplayFormat::Binary => format!("{reg:?}: {:#b} ({value})",data),
+                });
+            },
             DebugCommand::PEEK(fmt,location) => {
+                let location_str = match location {
+                    Location::Addr(a) => describe_address(*a,machine.symbols()),
+                    _ => location.to_string(),
+                };
                 let data = match location {
                     Location::Register(reg) => machine.get_reg(*reg),
+                    Location::FRegister(_) => unreachable!("floating point registers are peeked above"),
                     Location::Addr(a) => match machine.read_word(*a) {
                         Ok(data) => data,
                         Err(e) => {
@@ -399,10 +642,10 @@ impl DebugCommand {
                     }
                 };
                 status.push(match fmt {
-                    DisplayFormat::Hex => format!("{location}: {:#X}",data),
-                    DisplayFormat::Unsigned => format!("{location}: {}",data as u32),
-                    DisplayFormat::Signed => format!("{location}: {}",data as i32),
-                    DisplayFormat::Binary => format!("{location}: {:#b}",data),
+                    DisplayFormat::Hex => format!("{location_str}: {:#X}",data),
+                    DisplayFormat::Unsigned => format!("{location_str}: {}",data as u32),
+                    DisplayFormat::Signed => format!("{location_str}: {}",data as i32),
+                    DisplayFormat::Binary => format!("{location_str}: {:#b}",data),
 
                 });
             },
@@ -410,10 +653,10 @@ impl DebugCommand {
             // currently it consists of a buch of debugcommands that will be executed each
             // time the status is printed, but generally only commands that will just print
             // something. Hopefully CONTINUEs never make it in here.
-            DebugCommand::WATCH(fmt,location) => {
+            DebugCommand::DISPLAY(fmt,location) => {
                 watchlist.push(DebugCommand::PEEK(fmt.clone(),location.clone()));
             },
-            DebugCommand::RMWATCH(location) => {
+            DebugCommand::UNDISPLAY(location) => {
                 *watchlist = watchlist.clone()
                                       .into_iter()
                                       .filter(|c| match c {
@@ -443,12 +686,78 @@ mod tests {
     #[test]
     fn test_parse_step() {
         assert_eq!(DebugCommand::from_string("step 125"), Ok(DebugCommand::STEP(125)));
+        assert_eq!(DebugCommand::from_string("BSTEP"), Ok(DebugCommand::BSTEP(1)));
+        assert_eq!(DebugCommand::from_string("bstep 3"), Ok(DebugCommand::BSTEP(3)));
+        assert_eq!(DebugCommand::from_string("rcontinue"), Ok(DebugCommand::RCONTINUE));
     }
 
     #[test]
     fn test_parse_peek() {
         assert_eq!(DebugCommand::from_string("peek /x 0xDEAD"), 
                    Ok(DebugCommand::PEEK(DisplayFormat::Hex,Location::Addr(0xDEAD))));
+        assert_eq!(DebugCommand::from_string("peek f3"),
+                   Ok(DebugCommand::PEEK(DisplayFormat::Hex,Location::FRegister(FRegister::FT3))));
+        assert_eq!(DebugCommand::from_string("peek fa0"),
+                   Ok(DebugCommand::PEEK(DisplayFormat::Hex,Location::FRegister(FRegister::FA0))));
+        assert_eq!(DebugCommand::from_string("peek 0xf3"),
+                   Ok(DebugCommand::PEEK(DisplayFormat::Hex,Location::Addr(0xF3))));
+    }
+    #[test]
+    fn test_parse_dump() {
+        assert_eq!(DebugCommand::from_string("dump 0x100-0x1ff out.hex"),
+                   Ok(DebugCommand::DUMP(0x100..=0x1ff,"out.hex".to_string(),None)));
+        assert_eq!(DebugCommand::from_string("dump 0x100-0x1ff out bin"),
+                   Ok(DebugCommand::DUMP(0x100..=0x1ff,"out".to_string(),Some(ExportFormat::Bin))));
+        assert!(matches!(DebugCommand::from_string("dump 0x100 out"),
+                   Err(DebugParseError::InvalidRange(_))));
+    }
+    #[test]
+    fn test_parse_symbols() {
+        use crate::symbols::{Symbol, SymbolKind};
+        let symbols = SymbolTable::new(vec![
+            Symbol { name: "main".to_string(), addr: 0x40, size: 0x20, kind: SymbolKind::Function },
+            Symbol { name: "add".to_string(), addr: 0x80, size: 0, kind: SymbolKind::Function },
+        ]);
+        assert_eq!(DebugCommand::from_string_with_symbols("break main", &symbols),
+                   Ok(DebugCommand::BREAK(0x40,None)));
+        assert_eq!(DebugCommand::from_string_with_symbols("break add", &symbols),
+                   Ok(DebugCommand::BREAK(0x80,None)));
+        assert_eq!(DebugCommand::from_string_with_symbols("peek main+0x10", &symbols),
+                   Ok(DebugCommand::PEEK(DisplayFormat::Hex,Location::Addr(0x50))));
+        assert_eq!(DebugCommand::from_string_with_symbols("rmbrk main", &symbols),
+                   Ok(DebugCommand::RMBRK(BreakpointIdentifier::Addr(0x40))));
+        assert_eq!(DebugCommand::from_string_with_symbols("rmbrk 1", &symbols),
+                   Ok(DebugCommand::RMBRK(BreakpointIdentifier::Index(1))));
+        assert_eq!(DebugCommand::from_string_with_symbols("peek a0", &symbols),
+                   Ok(DebugCommand::PEEK(DisplayFormat::Hex,Location::Register(Register::A0))));
+    }
+    #[test]
+    fn test_parse_conditional_break() {
+        let symbols = SymbolTable::default();
+        let condition = Condition::parse("a0 == 5 && [sp+8] != 0", &symbols).unwrap();
+        assert_eq!(DebugCommand::from_string("break 0x40 IF a0 == 5 &&  [sp+8] != 0"),
+                   Ok(DebugCommand::BREAK(0x40,Some(condition))));
+        assert_eq!(DebugCommand::from_string("break 0x40 if"),
+                   Err(DebugParseError::InvalidCondition("unexpected end of condition".to_string())));
+        assert_eq!(DebugCommand::from_string("break 0x40 0x50 if a0"), Err(DebugParseError::TooManyArguments));
+        assert_eq!(DebugCommand::from_string("ignore 1 500"), Ok(DebugCommand::IGNORE(1,500)));
+        assert_eq!(DebugCommand::from_string("ignore 1"), Err(DebugParseError::NotEnoughArguments));
+    }
+    #[test]
+    fn test_parse_watch() {
+        assert_eq!(DebugCommand::from_string("watch 0x100"),
+                   Ok(DebugCommand::WATCH(Watch::Memory { range: 0x100..=0x103, read: false, write: true })));
+        assert_eq!(DebugCommand::from_string("watch rw 0x100 16"),
+                   Ok(DebugCommand::WATCH(Watch::Memory { range: 0x100..=0x10F, read: true, write: true })));
+        assert_eq!(DebugCommand::from_string("watch R 0x100 0x2"),
+                   Ok(DebugCommand::WATCH(Watch::Memory { range: 0x100..=0x101, read: true, write: false })));
+        assert_eq!(DebugCommand::from_string("watch a0"), Ok(DebugCommand::WATCH(Watch::Register(Register::A0))));
+        assert_eq!(DebugCommand::from_string("watch r a0"), Err(DebugParseError::InvalidLocation("a0".to_string())));
+        assert_eq!(DebugCommand::from_string("watch 0x100 0"), Err(DebugParseError::InvalidNumber("0".to_string())));
+        assert!(matches!(DebugCommand::from_string("watch 0xFFFFFFFF 2"), Err(DebugParseError::InvalidRange(_))));
+        assert_eq!(DebugCommand::from_string("display /i a0"),
+                   Ok(DebugCommand::DISPLAY(DisplayFormat::Signed,Location::Register(Register::A0))));
+        assert_eq!(DebugCommand::from_string("rmwatch 2"), Ok(DebugCommand::RMWATCH(2)));
     }
     #[test]
     fn test_parse_poke() {
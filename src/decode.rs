@@ -1,3 +1,4 @@
+use crate::isa::Extension;
 use crate::register::Register;
 use thiserror::Error;
 
@@ -41,6 +42,16 @@ pub enum InstructionType {
         imm: u32,
         opcode: u8,
     },
+    // Fused multiply-add, which needs a third source register
+    R4Type {
+        rd: Register,
+        rs1: Register,
+        rs2: Register,
+        rs3: Register,
+        funct3: u8,
+        funct2: u8,
+        opcode: u8,
+    },
 }
 
 impl InstructionType {
@@ -51,7 +62,7 @@ impl InstructionType {
         // Some of these have to look at both opcode and func3
         match opcode {
             // List out all RType opcodes here, separated by |
-            0b0110011 => Ok(InstructionType::RType {
+            0b0110011 | 0b0101111 | 0b1010011 => Ok(InstructionType::RType {
                 rd: Register::from_num(bitrange(combined, 7, 11))
                     .ok_or(ParseError::RegisterDecode(bitrange(combined, 7, 11)))?,
                 rs1: Register::from_num(bitrange(combined, 15, 19))
@@ -63,7 +74,7 @@ impl InstructionType {
                 opcode,
             }),
             // List out all IType opcodes here, separated by |
-            0b0000011 | 0b0010011 | 0b1100111 | 0b1110011 => {
+            0b0000011 | 0b0010011 | 0b1100111 | 0b1110011 | 0b0001111 | 0b0000111 => {
                 Ok(InstructionType::IType {
                     rd: Register::from_num(bitrange(combined, 7, 11))
                         .ok_or(ParseError::RegisterDecode(bitrange(combined, 7, 11)))?,
@@ -77,7 +88,7 @@ impl InstructionType {
                 })
             }
             // List out all SType opcodes here, separated by |
-            0b0100011 => Ok(InstructionType::SType {
+            0b0100011 | 0b0100111 => Ok(InstructionType::SType {
                 rs1: Register::from_num(bitrange(combined, 15, 19))
                     .ok_or(ParseError::RegisterDecode(bitrange(combined, 15, 19)))?,
                 rs2: Register::from_num(bitrange(combined, 20, 24))
@@ -117,6 +128,20 @@ impl InstructionType {
                     + (bitrange(combined, 31, 31) << 20),
                 opcode,
             }),
+            // List out all R4Type opcodes here, separated by |
+            0b1000011 | 0b1000111 | 0b1001011 | 0b1001111 => Ok(InstructionType::R4Type {
+                rd: Register::from_num(bitrange(combined, 7, 11))
+                    .ok_or(ParseError::RegisterDecode(bitrange(combined, 7, 11)))?,
+                rs1: Register::from_num(bitrange(combined, 15, 19))
+                    .ok_or(ParseError::RegisterDecode(bitrange(combined, 15, 19)))?,
+                rs2: Register::from_num(bitrange(combined, 20, 24))
+                    .ok_or(ParseError::RegisterDecode(bitrange(combined, 20, 24)))?,
+                rs3: Register::from_num(bitrange(combined, 27, 31))
+                    .ok_or(ParseError::RegisterDecode(bitrange(combined, 27, 31)))?,
+                funct3: bitrange(combined, 12, 14) as u8,
+                funct2: bitrange(combined, 25, 26) as u8,
+                opcode,
+            }),
             op => Err(ParseError::InvalidOpcode(op)),
         }
     }
@@ -141,6 +166,8 @@ pub enum ParseError {
     InvalidInstruction(u32),
     #[error("Invalid opcode: {0:#x}")]
     InvalidOpcode(u8),
+    #[error("Illegal instruction {0:#x}, the {1} extension is not enabled")]
+    MissingExtension(u32, Extension),
     //#[error("Invalid format: {0:#x}")] // not sure if correct formatting
     //InvalidFormat(u32),
 }
@@ -12,7 +12,9 @@ use std::thread::{JoinHandle,self};
 use thiserror::Error;
 
 enum FramebufferBackend {
-    MiniFB(JoinHandle<()>)
+    MiniFB(JoinHandle<()>),
+    // No window, the pixels are only kept in memory
+    Headless,
 }
 
 const WIDTH: usize = 160;
@@ -72,6 +74,7 @@ impl Framebuffer {
 
                 }))
             },
+            Some("none") => FramebufferBackend::Headless,
             Some(s) => return Err(DeviceConfigError::InvalidOption(s.to_string()))
 
         };
@@ -91,7 +94,7 @@ impl WordDevice for Framebuffer {
     // This only supports aligned access
     fn store_word(&self, addr: u32, data: u32) -> Result<(),Box<dyn error::Error>> {
         match self.backend {
-            FramebufferBackend::MiniFB(_) => {
+            FramebufferBackend::MiniFB(_) | FramebufferBackend::Headless => {
                 let offset: usize = (addr - self.base_address) as usize >> 2;
                 self.pixel_buffer.lock().map_err(|_| FramebufferError::Poisoned )?[offset] = data;
                 Ok(())
@@ -100,7 +103,7 @@ impl WordDevice for Framebuffer {
     }
     fn read_word(&self, addr: u32) -> Result<i32,Box<dyn error::Error>> {
         match self.backend {
-            FramebufferBackend::MiniFB(_) => {
+            FramebufferBackend::MiniFB(_) | FramebufferBackend::Headless => {
                 let offset: usize = (addr - self.base_address) as usize >> 2;
                 Ok(
                     self.pixel_buffer.lock().map_err(|_| FramebufferError::Poisoned )?[offset] as i32
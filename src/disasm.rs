@@ -0,0 +1,200 @@
+use crate::compressed::CompressedOp;
+use crate::decode::{bytes_to_u32, ParseError};
+use crate::loader::{Image, Segment};
+use crate::opcode::{Instruction, Operation};
+use crate::symbols::SymbolTable;
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// Produce an objdump-style listing of every segment in an image. Each line has the address, the
+/// raw word and the instruction, with branch and jump targets shown as absolute addresses. Targets
+/// without a symbol get a generated `L_<addr>` label, and words that don't decode are shown as
+/// `.word` data.
+pub fn disassemble(image: &Image, symbols: &SymbolTable) -> String {
+    let contains = |addr: u32| {
+        image
+            .segments
+            .iter()
+            .any(|s| addr >= s.addr && addr - s.addr < s.data.len() as u32)
+    };
+
+    // Every symbol gets a label, and so does any target inside the image that doesn't have one
+    let mut labels: BTreeMap<u32, String> = BTreeMap::new();
+    for symbol in symbols.iter() {
+        labels.entry(symbol.addr).or_insert(symbol.name.clone());
+    }
+    for (pc, op) in instructions(image) {
+        if let Some(target) = op.ok().and_then(|op| op.target(pc)) {
+            if contains(target) {
+                labels.entry(target).or_insert(format!("L_{target:x}"));
+            }
+        }
+    }
+
+    let mut out = String::new();
+    let mut end = None;
+    for segment in image.segments.iter() {
+        // Leave a gap wherever there is a gap in memory
+        if end != Some(segment.addr) {
+            writeln!(out).unwrap();
+        }
+        end = Some(segment.addr.wrapping_add(segment.data.len() as u32));
+        for (pc, bytes, inst) in segment_instructions(segment) {
+            if let Some(label) = labels.get(&pc) {
+                writeln!(out, "{pc:08x} <{label}>:").unwrap();
+            }
+            let raw = match bytes.len() {
+                4 => format!("{:08x}", bytes_to_u32(bytes)),
+                2 if inst.is_ok() => format!("{:04x}", u16::from_le_bytes([bytes[0], bytes[1]])),
+                _ => {
+                    let bytes: Vec<String> = bytes.iter().map(|b| format!("{b:#04x}")).collect();
+                    writeln!(out, "{pc:8x}:\t{:8}\t.byte {}", "", bytes.join(", ")).unwrap();
+                    continue;
+                }
+            };
+            let text = match inst {
+                Ok(inst) => format_op(&inst, pc, &labels, symbols),
+                Err(_) => format!(".word {:#010x}", bytes_to_u32(bytes)),
+            };
+            writeln!(out, "{pc:8x}:\t{raw:8}\t{text}").unwrap();
+        }
+    }
+    out
+}
+
+/// Every instruction in the image along with what it decodes to
+fn instructions(image: &Image) -> impl Iterator<Item = (u32, Result<Instruction, ParseError>)> + '_ {
+    image
+        .segments
+        .iter()
+        .flat_map(segment_instructions)
+        .map(|(pc, _, inst)| (pc, inst))
+}
+
+/// Walk through a segment one instruction at a time, which is 2 or 4 bytes depending on what is
+/// there. Anything that doesn't decode is skipped over a word at a time, and whatever is left at
+/// the end that is too short for an instruction is returned on its own.
+fn segment_instructions(segment: &Segment) -> Vec<(u32, &[u8], Result<Instruction, ParseError>)> {
+    let mut out = Vec::new();
+    let mut offset = 0;
+    while offset < segment.data.len() {
+        let pc = segment.addr.wrapping_add(offset as u32);
+        let rest = &segment.data[offset..];
+        let (len, inst) = match rest.len() {
+            1 => (1, Err(ParseError::InvalidInstruction(rest[0] as u32))),
+            _ => match Instruction::decode(rest) {
+                Ok(inst) => (inst.len() as usize, Ok(inst)),
+                Err(e) => (rest.len().min(4), Err(e)),
+            },
+        };
+        out.push((pc, &rest[..len], inst));
+        offset += len;
+    }
+    out
+}
+
+/// Display an instruction, swapping the pc-relative offset of a jump or branch for its target
+fn format_op(inst: &Instruction, pc: u32, labels: &BTreeMap<u32, String>, symbols: &SymbolTable) -> String {
+    use Operation::*;
+    let Some(target) = inst.target(pc) else {
+        return inst.to_string();
+    };
+    let name = labels
+        .get(&target)
+        .cloned()
+        .or_else(|| symbols.symbolize(target));
+    let (mnemonic, operands) = match inst {
+        Instruction::Full(op) => {
+            let operands = match op {
+                JAL(rd, _) => format!("{rd}, {target:#x}"),
+                BEQ(rs1, rs2, _) | BNE(rs1, rs2, _) | BLT(rs1, rs2, _) | BLTU(rs1, rs2, _) | BGE(rs1, rs2, _)
+                | BGEU(rs1, rs2, _) => format!("{rs1}, {rs2}, {target:#x}"),
+                _ => unreachable!("only jumps and branches have targets"),
+            };
+            // The mnemonic is the variant name
+            let debug = format!("{op:?}");
+            let mnemonic = debug.split('(').next().unwrap_or_default().to_owned();
+            (format!("{mnemonic:<5}"), operands)
+        }
+        Instruction::Compressed(op) => {
+            let operands = match op {
+                CompressedOp::CJ(_) | CompressedOp::CJAL(_) => format!("{target:#x}"),
+                CompressedOp::CBEQZ(rs1, _) | CompressedOp::CBNEZ(rs1, _) => format!("{rs1}, {target:#x}"),
+                _ => unreachable!("only jumps and branches have targets"),
+            };
+            (format!("{:<10}", op.mnemonic()), operands)
+        }
+    };
+    match name {
+        Some(name) => format!("{mnemonic} {operands} <{name}>"),
+        None => format!("{mnemonic} {operands}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+
+    #[test]
+    fn test_disassemble() {
+        let image = assemble(
+            "main:\n\
+                 li a0, 3\n\
+             loop:\n\
+                 addi a0, a0, -1\n\
+                 bnez a0, loop\n\
+                 jal zero, 16\n\
+                 ret\n\
+             .word 0\n",
+            "test.s",
+        )
+        .unwrap();
+        // Leave out the symbol for `loop` so a label gets generated
+        let symbols = image.symbols.iter().filter(|s| s.name == "main").cloned().collect();
+        let listing = disassemble(&image, &SymbolTable::new(symbols));
+        let lines: Vec<&str> = listing.lines().filter(|l| !l.is_empty()).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "00000000 <main>:",
+                "       0:\t00300513\tADDI  A0, ZERO, 0x3",
+                "00000004 <L_4>:",
+                "       4:\tfff50513\tADDI  A0, A0, 0xffffffff",
+                "       8:\tfe051ee3\tBNE   A0, ZERO, 0x4 <L_4>",
+                "       c:\t0040006f\tJAL   ZERO, 0x10 <L_10>",
+                "00000010 <L_10>:",
+                "      10:\t00008067\tJALR  ZERO, RA, 0x0",
+                "      14:\t00000000\t.word 0x00000000",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_disassemble_compressed() {
+        let image = assemble(
+            "main:\n\
+                 .half 0x4505\n\
+                 addi a0, a0, 1\n\
+                 .half 0xdd6d\n\
+                 .half 0x8082\n\
+                 .byte 1\n",
+            "test.s",
+        )
+        .unwrap();
+        let listing = disassemble(&image, &SymbolTable::new(image.symbols.clone()));
+        let lines: Vec<&str> = listing.lines().filter(|l| !l.is_empty()).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "00000000 <main>:",
+                "       0:\t4505    \tC.LI       A0, 0x1",
+                "       2:\t00150513\tADDI  A0, A0, 0x1",
+                "       6:\tdd6d    \tC.BEQZ     A0, 0x0 <main>",
+                "       8:\t8082    \tC.JR       RA",
+                "       a:\t        \t.byte 0x01",
+            ]
+        );
+    }
+}
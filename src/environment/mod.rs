@@ -2,6 +2,7 @@ use filedescriptor::FileDescriptorTable;
 use termion::raw::IntoRawMode;
 
 use crate::machine::ExecutionError;
+use crate::memory::Memory;
 use std::str;
 use std::time::Instant;
 use std::io::{stdout, IsTerminal, Read, Write};
@@ -46,13 +47,15 @@ impl Environment {
     pub fn reset_timer(&mut self) -> () {
         self.timer = Instant::now();
     }
-    pub fn syscall(&mut self, a7: u32, a0: u32, a1: u32, a2: u32, memory: &mut Box<[u8]>) -> Result<i32, ExecutionError> {
+    /// Run a system call and return what goes in a0. Memory is only read here, the bytes that
+    /// read() gets are left in `read` for the machine to store at a1 like any other store.
+    pub fn syscall(&mut self, a7: u32, a0: u32, a1: u32, a2: u32, memory: &Memory, read: &mut Vec<u8>) -> Result<i32, ExecutionError> {
     
         let read_string = |start:u32| -> Result<Vec<u8>,ExecutionError> { 
             // this allows reading the entire memory if string is not terminated. probably shouldn't
-            let strlen = memory[start as usize..].iter().position(|&c| -> bool { c==b'\0' });
+            let strlen = (start as usize..memory.size()).position(|addr| memory.read_byte(addr as u32) == b'\0');
             match strlen {
-                Some(l) => Ok(memory[start as usize..start as usize + l].to_vec()),
+                Some(l) => Ok(memory.slice(start, l).into_owned()),
                 None => return Err(ExecutionError::IOError(std::io::Error::new(std::io::ErrorKind::Other, "Failed to read filename")))
             }
         };
@@ -88,9 +91,11 @@ impl Environment {
                         f.read(&mut buf).map(|x| x as i32).map_err(|e| ExecutionError::IOError(e))
                     }
                 };
-                if a1+a2 > memory.len() as u32 { return Err(ExecutionError::LoadAccessFault(a1)) };
-                // Actually write the file's contents to memory
-                memory[a1 as usize..a1 as usize+a2 as usize].copy_from_slice(&buf);
+                // Only what was actually read gets written
+                if let Ok(len) = result {
+                    buf.truncate(len.max(0) as usize);
+                }
+                *read = buf;
                 result
 
             }
@@ -98,9 +103,9 @@ impl Environment {
             64 => {
                 // NOTE: not sure if this is a bug, the vector was getting overwritten
                 let mut buf;// = vec![0;a2 as usize];
-                if a1+a2 > memory.len() as u32 { return Err(ExecutionError::LoadAccessFault(a1)) };
+                if a1 as usize + a2 as usize > memory.size() { return Err(ExecutionError::LoadAccessFault(a1)) };
                 // read memory into buffer
-                buf = memory[a1 as usize..a1 as usize+a2 as usize].to_vec();
+                buf = memory.slice(a1, a2 as usize).into_owned();
                 let result = match a0 {
                     // error if trying to write to stdin
                     0 => {
@@ -0,0 +1,109 @@
+//! A cache of decoded instructions, so that loops don't go through the decoder every time
+//! around. It is direct-mapped on the pc, and stores that land on a cached instruction throw it
+//! away, so self-modifying code still sees what it wrote.
+
+use crate::opcode::Operation;
+
+/// Number of entries, one per halfword of code, so 32 KiB of code can be cached at once
+const LINES: usize = 16 * 1024;
+
+#[derive(Clone)]
+struct Line {
+    pc: u32,
+    len: u32,
+    op: Operation,
+}
+
+/// Starts out empty, the lines are only allocated once something is run
+#[derive(Default)]
+pub struct InstructionCache {
+    lines: Vec<Option<Line>>,
+    // Nothing is kept, so every instruction is decoded each time it runs
+    disabled: bool,
+}
+
+impl InstructionCache {
+    fn index(pc: u32) -> usize {
+        (pc >> 1) as usize % LINES
+    }
+
+    /// The operation at `pc` and its length in bytes, if it has been decoded already
+    pub fn get(&self, pc: u32) -> Option<(&Operation, u32)> {
+        match self.lines.get(Self::index(pc)) {
+            Some(Some(line)) if line.pc == pc => Some((&line.op, line.len)),
+            _ => None,
+        }
+    }
+
+    /// A cache that never holds anything, to measure what it saves
+    pub fn disabled() -> Self {
+        InstructionCache { lines: Vec::new(), disabled: true }
+    }
+
+    pub fn insert(&mut self, pc: u32, op: Operation, len: u32) {
+        if self.disabled {
+            return;
+        }
+        if self.lines.is_empty() {
+            self.lines = vec![None; LINES];
+        }
+        self.lines[Self::index(pc)] = Some(Line { pc, len, op });
+    }
+
+    /// Drop any instruction that overlaps the `len` bytes at `addr`
+    pub fn invalidate(&mut self, addr: u32, len: u32) {
+        if self.lines.is_empty() {
+            return;
+        } else if len as usize / 2 >= LINES {
+            return self.flush();
+        }
+        // An instruction can start up to 3 bytes before the write and still overlap it
+        let end = addr as u64 + len as u64;
+        let mut pc = addr.saturating_sub(2) & !1;
+        while (pc as u64) < end {
+            let line = &mut self.lines[Self::index(pc)];
+            if line.as_ref().is_some_and(|line| line.pc == pc) {
+                *line = None;
+            }
+            pc = match pc.checked_add(2) {
+                Some(pc) => pc,
+                None => break,
+            };
+        }
+    }
+
+    pub fn flush(&mut self) {
+        self.lines.fill(None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_invalidate() {
+        let mut cache = InstructionCache::default();
+        for pc in [0, 4, 8, 10] {
+            cache.insert(pc, Operation::NOP, 4);
+        }
+        // Writing the last byte of the word at 4 drops it and nothing else
+        cache.invalidate(7, 1);
+        assert!(cache.get(4).is_none());
+        assert!(cache.get(0).is_some() && cache.get(8).is_some());
+        // An instruction at 8 still covers 10 and 11
+        cache.invalidate(10, 2);
+        assert!(cache.get(8).is_none() && cache.get(10).is_none());
+        // 0x10000 shares a line with 0, so it pushes it out
+        cache.insert(0x10000, Operation::NOP, 4);
+        assert!(cache.get(0).is_none());
+        assert!(cache.get(0x10000).is_some());
+        cache.flush();
+        assert!(cache.get(0x10000).is_none());
+
+        let mut cache = InstructionCache::disabled();
+        cache.insert(0, Operation::NOP, 4);
+        assert!(cache.get(0).is_none());
+        cache.invalidate(0, 4);
+    }
+}
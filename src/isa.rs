@@ -0,0 +1,161 @@
+//! The ISA string, like `rv32imac`, picking which extensions the simulated hart implements.
+//! Instructions from anything left out are illegal, so programs can be checked against the
+//! configuration they are meant for.
+
+use crate::csr;
+
+use std::fmt;
+
+/// The extensions that can be turned off. Zicsr and Zifencei are always there, as traps and
+/// the floating point flags need the CSRs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Extension {
+    M,
+    A,
+    F,
+    D,
+    C,
+    Zba,
+    Zbb,
+    Zbc,
+    Zbs,
+}
+
+impl Extension {
+    const ALL: [Extension; 9] = [
+        Extension::M,
+        Extension::A,
+        Extension::F,
+        Extension::D,
+        Extension::C,
+        Extension::Zba,
+        Extension::Zbb,
+        Extension::Zbc,
+        Extension::Zbs,
+    ];
+
+    fn bit(self) -> u32 {
+        1 << self as u32
+    }
+
+    /// The single letter extensions are the ones with a bit in `misa`
+    fn letter(self) -> Option<char> {
+        let name = self.to_string();
+        match name.len() {
+            1 => name.chars().next(),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Extension {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+/// A set of extensions on top of RV32I
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Isa {
+    extensions: u32,
+}
+
+impl Default for Isa {
+    /// Everything the simulator knows
+    fn default() -> Self {
+        Isa { extensions: Extension::ALL.iter().fold(0, |set, ext| set | ext.bit()) }
+    }
+}
+
+impl Isa {
+    /// Parse an ISA string the way GCC's `-march` spells it, like `rv32imac` or
+    /// `rv32gc_zba_zbb`. `g` is short for `imafd`, and D brings F with it.
+    pub fn parse(s: &str) -> Result<Isa, String> {
+        let lower = s.to_lowercase();
+        let rest = lower
+            .strip_prefix("rv32")
+            .ok_or_else(|| format!("`{s}` is not an RV32 ISA string"))?;
+        let mut parts = rest.split('_');
+        let letters = parts.next().unwrap_or_default();
+        let letters = match letters.strip_prefix('g') {
+            Some(rest) => format!("mafd{rest}"),
+            None => match letters.strip_prefix('i') {
+                Some(rest) => rest.to_string(),
+                None => return Err(format!("`{s}` has to start with rv32i or rv32g")),
+            },
+        };
+        let mut isa = Isa { extensions: 0 };
+        for c in letters.chars() {
+            let ext = Extension::ALL
+                .into_iter()
+                .find(|ext| ext.letter() == Some(c.to_ascii_uppercase()))
+                .ok_or_else(|| format!("unsupported extension `{c}` in `{s}`"))?;
+            isa.extensions |= ext.bit();
+        }
+        for name in parts {
+            match name {
+                "zicsr" | "zifencei" => {}
+                _ => {
+                    let ext = Extension::ALL
+                        .into_iter()
+                        .find(|ext| ext.letter().is_none() && ext.to_string().eq_ignore_ascii_case(name))
+                        .ok_or_else(|| format!("unsupported extension `{name}` in `{s}`"))?;
+                    isa.extensions |= ext.bit();
+                }
+            }
+        }
+        if isa.has(Extension::D) {
+            isa.extensions |= Extension::F.bit();
+        }
+        Ok(isa)
+    }
+
+    pub fn has(&self, ext: Extension) -> bool {
+        self.extensions & ext.bit() != 0
+    }
+
+    /// The value of `misa` for this ISA
+    pub fn misa(&self) -> u32 {
+        let letters: String = Extension::ALL
+            .into_iter()
+            .filter(|ext| self.has(*ext))
+            .filter_map(Extension::letter)
+            .collect();
+        csr::misa_for(&format!("i{letters}"))
+    }
+}
+
+impl fmt::Display for Isa {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "rv32i")?;
+        let enabled = Extension::ALL.into_iter().filter(|ext| self.has(*ext));
+        for ext in enabled.clone().filter(|ext| ext.letter().is_some()) {
+            write!(f, "{}", ext.to_string().to_lowercase())?;
+        }
+        for ext in enabled.filter(|ext| ext.letter().is_none()) {
+            write!(f, "_{}", ext.to_string().to_lowercase())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let isa = Isa::parse("rv32imac").unwrap();
+        assert!(isa.has(Extension::M) && isa.has(Extension::A) && isa.has(Extension::C));
+        assert!(!isa.has(Extension::F) && !isa.has(Extension::Zba));
+        assert_eq!(isa.misa(), csr::misa_for("imac"));
+        assert_eq!(Isa::parse("rv32i").unwrap().misa(), csr::misa_for("i"));
+        assert_eq!(Isa::parse("RV32GC_zba_zicsr").unwrap().to_string(), "rv32imafdc_zba");
+        assert_eq!(Isa::parse("rv32id").unwrap().to_string(), "rv32ifd");
+        assert_eq!(Isa::default().to_string(), "rv32imafdc_zba_zbb_zbc_zbs");
+        assert!(Isa::parse("rv64i").is_err());
+        assert!(Isa::parse("rv32e").is_err());
+        assert!(Isa::parse("rv32iv").is_err());
+        assert!(Isa::parse("rv32i_zbx").is_err());
+    }
+}
@@ -0,0 +1,160 @@
+//! A bounded undo log of what each instruction changed, so that the debugger can run the program
+//! backwards. Changes are kept in one flat queue, each instruction's starting with a `Step`, so
+//! recording one costs a push rather than an allocation.
+
+use crate::csr::CsrFile;
+
+use std::collections::VecDeque;
+
+/// Each change holds the value from before the instruction ran
+#[derive(Debug, PartialEq)]
+pub enum Change {
+    /// The start of an instruction, with the state that isn't logged as it changes
+    Step { pc: u32, reservation: Option<u32> },
+    /// An integer register, by its index into the register file
+    Register(usize, u32),
+    FRegister(usize, u64),
+    /// Up to 4 bytes of RAM, little endian
+    Memory { addr: u32, len: u32, old: u32 },
+    Csrs(Box<CsrFile>),
+}
+
+/// The changes made by one instruction, latest first
+pub struct Entry {
+    pub pc: u32,
+    pub reservation: Option<u32>,
+    pub changes: Vec<Change>,
+}
+
+pub struct Journal {
+    changes: VecDeque<Change>,
+    // The number of instructions in `changes`
+    steps: usize,
+    limit: usize,
+    // Only changes made while running an instruction are logged, not pokes from the debugger
+    recording: bool,
+}
+
+impl Journal {
+    /// A journal holding at least the last `limit` instructions, or nothing at all if it is 0
+    pub fn new(limit: usize) -> Self {
+        Journal { changes: VecDeque::new(), steps: 0, limit, recording: false }
+    }
+
+    /// Whether anything is being kept
+    pub fn is_on(&self) -> bool {
+        self.limit != 0
+    }
+
+    /// The number of instructions that can be undone
+    #[cfg(test)]
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    /// Start logging an instruction
+    #[inline(always)]
+    pub fn begin(&mut self, pc: u32, reservation: Option<u32>) {
+        if self.limit == 0 {
+            return;
+        }
+        // Going a quarter over and then trimming back keeps this to a push most of the time
+        if self.steps >= self.limit + self.limit.div_ceil(4) {
+            self.trim();
+        }
+        self.changes.push_back(Change::Step { pc, reservation });
+        self.steps += 1;
+        self.recording = true;
+    }
+
+    /// Forget the oldest instructions until there are only `limit` left
+    #[cold]
+    fn trim(&mut self) {
+        let mut excess = self.steps - self.limit;
+        let end = self.changes.iter().position(|change| {
+            if matches!(change, Change::Step { .. }) {
+                if excess == 0 {
+                    return true;
+                }
+                excess -= 1;
+            }
+            false
+        });
+        self.changes.drain(..end.unwrap_or(self.changes.len()));
+        self.steps = self.limit;
+    }
+
+    /// Finish logging the instruction started by `begin`
+    #[inline(always)]
+    pub fn end(&mut self) {
+        self.recording = false;
+    }
+
+    /// Forget the instruction started by `begin`, for one that didn't finish
+    pub fn cancel(&mut self) {
+        if self.recording {
+            self.pop();
+            self.recording = false;
+        }
+    }
+
+    /// Log a change if an instruction is running. The change is only made if it is needed.
+    #[inline(always)]
+    pub fn record(&mut self, change: impl FnOnce() -> Change) {
+        if self.recording {
+            self.changes.push_back(change());
+        }
+    }
+
+    /// Take the latest instruction's changes back out of the journal
+    pub fn pop(&mut self) -> Option<Entry> {
+        let mut changes = Vec::new();
+        loop {
+            match self.changes.pop_back()? {
+                Change::Step { pc, reservation } => {
+                    self.steps -= 1;
+                    return Some(Entry { pc, reservation, changes });
+                }
+                change => changes.push(change),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_journal() {
+        let mut journal = Journal::new(2);
+        for pc in [0, 4, 8] {
+            journal.begin(pc, None);
+            journal.record(|| Change::Register(1, pc));
+            journal.record(|| Change::Register(2, pc));
+            journal.end();
+        }
+        // Changes made outside of an instruction aren't logged
+        journal.record(|| Change::Register(3, 0));
+        // It can go over by a quarter before the oldest instructions are dropped
+        assert_eq!(journal.steps(), 3);
+        journal.begin(12, None);
+        journal.end();
+        assert_eq!(journal.steps(), 3);
+        assert_eq!(journal.pop().unwrap().pc, 12);
+        journal.begin(12, Some(0x100));
+        journal.cancel();
+
+        let entry = journal.pop().unwrap();
+        assert_eq!(entry.pc, 8);
+        assert_eq!(entry.changes, vec![Change::Register(2, 8), Change::Register(1, 8)]);
+        assert_eq!(journal.pop().unwrap().pc, 4);
+        assert!(journal.pop().is_none());
+        assert_eq!(journal.steps(), 0);
+
+        let mut journal = Journal::new(0);
+        journal.begin(0, None);
+        journal.record(|| Change::Register(1, 0));
+        assert!(journal.pop().is_none());
+    }
+}
@@ -0,0 +1,10 @@
+use super::{Image, Segment};
+
+/// A raw binary is copied into memory byte for byte, starting at `load_addr`
+pub fn parse(bytes: &[u8], load_addr: u32) -> Image {
+    Image {
+        segments: vec![Segment::new(load_addr, bytes.to_vec())],
+        entry: None,
+        symbols: Vec::new(),
+    }
+}
@@ -0,0 +1,293 @@
+use super::{Image, Segment};
+use crate::symbols::{Symbol, SymbolKind};
+use crate::ReadFileError;
+
+pub const MAGIC: [u8; 4] = [0x7F, b'E', b'L', b'F'];
+
+const ELFCLASS32: u8 = 1;
+const ELFDATA2LSB: u8 = 1;
+const EM_RISCV: u16 = 0xF3;
+// e_flags for RISC-V, the floating point ABI takes up two bits
+const EF_RISCV_FLOAT_ABI: u32 = 0x6;
+const EF_RISCV_FLOAT_ABI_QUAD: u32 = 0x6;
+const EF_RISCV_RVE: u32 = 0x8;
+const PT_LOAD: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const STT_OBJECT: u8 = 1;
+const STT_FUNC: u8 = 2;
+const STT_SECTION: u8 = 3;
+const STT_FILE: u8 = 4;
+
+// Little-endian field readers, these return an error instead of panicking when a header points
+// past the end of the file
+fn read_u16(bytes: &[u8], offset: usize) -> Result<u16, ReadFileError> {
+    bytes
+        .get(offset..offset + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .ok_or(ReadFileError::InvalidElf(format!("truncated file at offset {offset:#x}")))
+}
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32, ReadFileError> {
+    bytes
+        .get(offset..offset + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or(ReadFileError::InvalidElf(format!("truncated file at offset {offset:#x}")))
+}
+
+/// Parse an `elf32-littleriscv` executable into an image.
+/// Only PT_LOAD segments are kept, and anything past the file size of a segment (`.bss`) is
+/// zero-filled.
+pub fn parse(bytes: &[u8]) -> Result<Image, ReadFileError> {
+    if !bytes.starts_with(&MAGIC) {
+        return Err(ReadFileError::InvalidElf("missing ELF magic".to_string()));
+    }
+    if bytes.get(4) != Some(&ELFCLASS32) {
+        return Err(ReadFileError::InvalidElf("not a 32-bit ELF".to_string()));
+    }
+    if bytes.get(5) != Some(&ELFDATA2LSB) {
+        return Err(ReadFileError::InvalidElf("not a little-endian ELF".to_string()));
+    }
+    let machine = read_u16(bytes, 18)?;
+    if machine != EM_RISCV {
+        return Err(ReadFileError::InvalidElf(format!("not a RISC-V ELF, e_machine is {machine:#x}")));
+    }
+
+    // Soft float (ilp32), ilp32f and ilp32d code all runs, but there are no 128-bit float
+    // registers to pass arguments in, and no RV32E
+    let flags = read_u32(bytes, 36)?;
+    if flags & EF_RISCV_FLOAT_ABI == EF_RISCV_FLOAT_ABI_QUAD {
+        return Err(ReadFileError::InvalidElf("built for the ilp32q ABI, which needs the Q extension".to_string()));
+    }
+    if flags & EF_RISCV_RVE != 0 {
+        return Err(ReadFileError::InvalidElf("built for RV32E".to_string()));
+    }
+
+    let entry = read_u32(bytes, 24)?;
+    let phoff = read_u32(bytes, 28)? as usize;
+    let shoff = read_u32(bytes, 32)? as usize;
+    let phentsize = read_u16(bytes, 42)? as usize;
+    let phnum = read_u16(bytes, 44)? as usize;
+    let shentsize = read_u16(bytes, 46)? as usize;
+    let shnum = read_u16(bytes, 48)? as usize;
+
+    let mut segments = Vec::new();
+    for i in 0..phnum {
+        let header = phoff + i * phentsize;
+        if read_u32(bytes, header)? != PT_LOAD {
+            continue;
+        }
+        let offset = read_u32(bytes, header + 4)? as usize;
+        let vaddr = read_u32(bytes, header + 8)?;
+        let filesz = read_u32(bytes, header + 16)? as usize;
+        let memsz = read_u32(bytes, header + 20)? as usize;
+        if filesz > memsz {
+            return Err(ReadFileError::InvalidElf(format!(
+                "segment at {vaddr:#x} has a file size larger than its memory size"
+            )));
+        }
+        if vaddr as u64 + memsz as u64 > 1 << 32 {
+            return Err(ReadFileError::InvalidElf(format!(
+                "segment at {vaddr:#x} runs past the end of the 32-bit address space"
+            )));
+        }
+        let data = bytes
+            .get(offset..offset + filesz)
+            .ok_or(ReadFileError::InvalidElf(format!(
+                "segment at {vaddr:#x} extends past the end of the file"
+            )))?
+            .to_vec();
+        // .bss is left as a size rather than filled with zeros, so it costs nothing until used
+        segments.push(Segment { addr: vaddr, data, size: memsz });
+    }
+
+    let symbols = if shoff != 0 {
+        parse_symbols(bytes, shoff, shentsize, shnum)?
+    } else {
+        Vec::new()
+    };
+
+    Ok(Image {
+        segments,
+        entry: Some(entry),
+        symbols,
+    })
+}
+
+/// Read every named function and object out of the symbol table, if there is one.
+/// Stripped binaries will just return an empty list.
+fn parse_symbols(
+    bytes: &[u8],
+    shoff: usize,
+    shentsize: usize,
+    shnum: usize,
+) -> Result<Vec<Symbol>, ReadFileError> {
+    let mut symbols = Vec::new();
+    for i in 0..shnum {
+        let header = shoff + i * shentsize;
+        if read_u32(bytes, header + 4)? != SHT_SYMTAB {
+            continue;
+        }
+        let offset = read_u32(bytes, header + 16)? as usize;
+        let size = read_u32(bytes, header + 20)? as usize;
+        let link = read_u32(bytes, header + 24)? as usize;
+        let entsize = read_u32(bytes, header + 36)? as usize;
+        if entsize == 0 {
+            continue;
+        }
+        // The linked section holds the names of all symbols
+        let strtab_header = shoff + link * shentsize;
+        let strtab = read_u32(bytes, strtab_header + 16)? as usize;
+        let strtab_size = read_u32(bytes, strtab_header + 20)? as usize;
+        let strings = bytes
+            .get(strtab..strtab + strtab_size)
+            .ok_or(ReadFileError::InvalidElf("string table extends past the end of the file".to_string()))?;
+
+        for entry in (offset..offset + size).step_by(entsize) {
+            let name_offset = read_u32(bytes, entry)? as usize;
+            let addr = read_u32(bytes, entry + 4)?;
+            let sym_size = read_u32(bytes, entry + 8)?;
+            let info = *bytes
+                .get(entry + 12)
+                .ok_or(ReadFileError::InvalidElf("truncated symbol table".to_string()))?;
+            let kind = match info & 0xF {
+                STT_FUNC => SymbolKind::Function,
+                STT_OBJECT => SymbolKind::Object,
+                STT_SECTION | STT_FILE => continue,
+                _ => SymbolKind::Other,
+            };
+            let name = match strings.get(name_offset..) {
+                Some(rest) => {
+                    let len = rest.iter().position(|&c| c == 0).unwrap_or(rest.len());
+                    String::from_utf8_lossy(&rest[..len]).into_owned()
+                }
+                None => continue,
+            };
+            if name.is_empty() {
+                continue;
+            }
+            symbols.push(Symbol { name, addr, size: sym_size, kind });
+        }
+    }
+    Ok(symbols)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::Memory;
+
+    // Build a tiny ELF by hand, with one loadable segment containing 4 bytes of code followed by
+    // 4 bytes of .bss, and a symbol table holding `main`
+    fn tiny_elf() -> Vec<u8> {
+        let mut elf = vec![0u8; 0x100];
+        elf[0..4].copy_from_slice(&MAGIC);
+        elf[4] = ELFCLASS32;
+        elf[5] = ELFDATA2LSB;
+        elf[6] = 1;
+        elf[16..18].copy_from_slice(&2u16.to_le_bytes());
+        elf[18..20].copy_from_slice(&EM_RISCV.to_le_bytes());
+        // e_entry, e_phoff, e_shoff
+        elf[24..28].copy_from_slice(&0x1000u32.to_le_bytes());
+        elf[28..32].copy_from_slice(&0x34u32.to_le_bytes());
+        elf[32..36].copy_from_slice(&0x80u32.to_le_bytes());
+        // e_phentsize, e_phnum, e_shentsize, e_shnum
+        elf[42..44].copy_from_slice(&32u16.to_le_bytes());
+        elf[44..46].copy_from_slice(&1u16.to_le_bytes());
+        elf[46..48].copy_from_slice(&40u16.to_le_bytes());
+        elf[48..50].copy_from_slice(&3u16.to_le_bytes());
+
+        // Program header
+        let ph = 0x34;
+        elf[ph..ph + 4].copy_from_slice(&PT_LOAD.to_le_bytes());
+        elf[ph + 4..ph + 8].copy_from_slice(&0x54u32.to_le_bytes());
+        elf[ph + 8..ph + 12].copy_from_slice(&0x1000u32.to_le_bytes());
+        elf[ph + 16..ph + 20].copy_from_slice(&4u32.to_le_bytes());
+        elf[ph + 20..ph + 24].copy_from_slice(&8u32.to_le_bytes());
+        elf[0x54..0x58].copy_from_slice(&0x00008067u32.to_le_bytes());
+
+        // Section 1 is the symbol table, section 2 its string table
+        let symtab = 0x80 + 40;
+        elf[symtab + 4..symtab + 8].copy_from_slice(&SHT_SYMTAB.to_le_bytes());
+        elf[symtab + 16..symtab + 20].copy_from_slice(&0x60u32.to_le_bytes());
+        elf[symtab + 20..symtab + 24].copy_from_slice(&32u32.to_le_bytes());
+        elf[symtab + 24..symtab + 28].copy_from_slice(&2u32.to_le_bytes());
+        elf[symtab + 36..symtab + 40].copy_from_slice(&16u32.to_le_bytes());
+        let strtab = 0x80 + 80;
+        elf[strtab + 16..strtab + 20].copy_from_slice(&0x58u32.to_le_bytes());
+        elf[strtab + 20..strtab + 24].copy_from_slice(&6u32.to_le_bytes());
+        elf[0x58..0x5E].copy_from_slice(b"\0main\0");
+
+        // Symbol 0 is the null symbol, symbol 1 is main
+        let sym = 0x70;
+        elf[sym..sym + 4].copy_from_slice(&1u32.to_le_bytes());
+        elf[sym + 4..sym + 8].copy_from_slice(&0x1000u32.to_le_bytes());
+        elf[sym + 8..sym + 12].copy_from_slice(&4u32.to_le_bytes());
+        elf[sym + 12] = STT_FUNC;
+        elf
+    }
+
+    #[test]
+    fn test_parse_segments() {
+        let image = parse(&tiny_elf()).unwrap();
+        assert_eq!(image.entry, Some(0x1000));
+        assert_eq!(
+            image.segments,
+            vec![Segment { addr: 0x1000, data: vec![0x67, 0x80, 0, 0], size: 8 }]
+        );
+    }
+
+    #[test]
+    fn test_parse_symbols() {
+        let image = parse(&tiny_elf()).unwrap();
+        assert_eq!(
+            image.symbols,
+            vec![Symbol { name: "main".to_string(), addr: 0x1000, size: 4, kind: SymbolKind::Function }]
+        );
+    }
+
+    #[test]
+    fn test_reject_64_bit() {
+        let mut elf = tiny_elf();
+        elf[4] = 2;
+        assert!(matches!(parse(&elf), Err(ReadFileError::InvalidElf(_))));
+    }
+
+    #[test]
+    fn test_float_abi() {
+        // ilp32d is accepted, ilp32q isn't
+        let mut elf = tiny_elf();
+        elf[36..40].copy_from_slice(&0x4u32.to_le_bytes());
+        assert!(parse(&elf).is_ok());
+        elf[36..40].copy_from_slice(&0x6u32.to_le_bytes());
+        assert!(matches!(parse(&elf), Err(ReadFileError::InvalidElf(_))));
+    }
+
+    #[test]
+    fn test_segment_sizes() {
+        // .bss is written as zeros, over whatever was there before
+        let mut memory = Memory::new(0x2000);
+        memory.write(0x1000, &[0xFF; 16]);
+        parse(&tiny_elf()).unwrap().write_to(&mut memory).unwrap();
+        assert_eq!(&memory.slice(0x1000, 10)[..], &[0x67, 0x80, 0, 0, 0, 0, 0, 0, 0xFF, 0xFF]);
+
+        // A huge .bss isn't allocated, only checked against the memory it is loaded into
+        let mut elf = tiny_elf();
+        elf[0x34 + 20..0x34 + 24].copy_from_slice(&0xFFFF_F000u32.to_le_bytes());
+        let image = parse(&elf).unwrap();
+        assert_eq!(image.segments[0].data.len(), 4);
+        assert!(matches!(image.write_to(&mut memory), Err(ReadFileError::AddressOutOfRange { .. })));
+        assert_eq!(memory.pages_in_use(), 1);
+
+        // A memory size that runs off the end of the address space
+        elf[0x34 + 20..0x34 + 24].copy_from_slice(&0xFFFF_F001u32.to_le_bytes());
+        assert!(matches!(parse(&elf), Err(ReadFileError::InvalidElf(_))));
+        // More in the file than in memory
+        elf[0x34 + 20..0x34 + 24].copy_from_slice(&2u32.to_le_bytes());
+        assert!(matches!(parse(&elf), Err(ReadFileError::InvalidElf(_))));
+    }
+
+    #[test]
+    fn test_truncated() {
+        let elf = tiny_elf();
+        assert!(matches!(parse(&elf[..0x40]), Err(ReadFileError::InvalidElf(_))));
+    }
+}
@@ -0,0 +1,136 @@
+use super::{decode_hex, malformed, Image};
+use crate::ReadFileError;
+
+/// Parse an Intel HEX file.
+/// Supports data, end of file, extended segment/linear address and start address records.
+pub fn parse(text: &str, file: &str) -> Result<Image, ReadFileError> {
+    let mut image = Image::default();
+    // Upper bits of the address, set by record types 02 and 04
+    let mut base: u32 = 0;
+    for (index, line) in text.lines().enumerate() {
+        let line_num = index + 1;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let record = line
+            .strip_prefix(':')
+            .ok_or(malformed(file, line_num, "record does not start with `:`"))?;
+        let bytes = decode_hex(record, file, line_num)?;
+        if bytes.len() < 5 {
+            return Err(malformed(file, line_num, "record is too short"));
+        }
+        let len = bytes[0] as usize;
+        if bytes.len() != len + 5 {
+            let message = format!("byte count is {len} but the record holds {} bytes", bytes.len() - 5);
+            return Err(malformed(file, line_num, &message));
+        }
+        // All bytes including the checksum should sum to zero
+        if bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0 {
+            return Err(malformed(file, line_num, "bad checksum"));
+        }
+        let addr = u16::from_be_bytes([bytes[1], bytes[2]]) as u32;
+        let data = &bytes[4..4 + len];
+        match bytes[3] {
+            // Data
+            0x00 => image.push_bytes(base.wrapping_add(addr), data),
+            // End of file
+            0x01 => break,
+            // Extended segment address, shifted over by 4
+            0x02 => base = be_u16(data, file, line_num)? << 4,
+            // Start segment address, CS:IP
+            0x03 => {
+                let cs = be_u16(data.get(0..2).unwrap_or(&[]), file, line_num)?;
+                let ip = be_u16(data.get(2..4).unwrap_or(&[]), file, line_num)?;
+                image.entry = Some((cs << 4).wrapping_add(ip));
+            }
+            // Extended linear address, the upper 16 bits
+            0x04 => base = be_u16(data, file, line_num)? << 16,
+            // Start linear address
+            0x05 => {
+                let entry: [u8; 4] = data
+                    .try_into()
+                    .map_err(|_| malformed(file, line_num, "start address must be 4 bytes"))?;
+                image.entry = Some(u32::from_be_bytes(entry));
+            }
+            t => return Err(malformed(file, line_num, &format!("unknown record type {t:02x}"))),
+        }
+    }
+    Ok(image)
+}
+
+/// Write bytes as Intel HEX data records, with extended linear address records as needed
+pub fn write(start: u32, bytes: &[u8]) -> String {
+    let mut out = String::new();
+    let mut addr = start;
+    let mut rest = bytes;
+    let mut upper = None;
+    while !rest.is_empty() {
+        if upper != Some(addr >> 16) {
+            upper = Some(addr >> 16);
+            out.push_str(&record(0x04, 0, &((addr >> 16) as u16).to_be_bytes()));
+        }
+        // Records can't cross a 64K boundary, since the address is only 16 bits
+        let to_boundary = 0x10000 - (addr & 0xFFFF) as usize;
+        let len = rest.len().min(16).min(to_boundary);
+        out.push_str(&record(0x00, addr as u16, &rest[..len]));
+        rest = &rest[len..];
+        addr = addr.wrapping_add(len as u32);
+    }
+    out.push_str(&record(0x01, 0, &[]));
+    out
+}
+
+fn record(record_type: u8, addr: u16, data: &[u8]) -> String {
+    let mut bytes = vec![data.len() as u8];
+    bytes.extend_from_slice(&addr.to_be_bytes());
+    bytes.push(record_type);
+    bytes.extend_from_slice(data);
+    let checksum = bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)).wrapping_neg();
+    bytes.push(checksum);
+    let hex: String = bytes.iter().map(|b| format!("{b:02X}")).collect();
+    format!(":{hex}\n")
+}
+
+fn be_u16(data: &[u8], file: &str, line_num: usize) -> Result<u32, ReadFileError> {
+    match data {
+        [hi, lo] => Ok(u16::from_be_bytes([*hi, *lo]) as u32),
+        _ => Err(malformed(file, line_num, "expected a 2 byte address")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::Segment;
+
+    #[test]
+    fn test_parse_ihex() {
+        let text = ":0400000013050000E4\n:020000040001F9\n:02000200AABB97\n:0400000500000100F6\n:00000001FF\n";
+        let image = parse(text, "test").unwrap();
+        assert_eq!(
+            image.segments,
+            vec![
+                Segment::new(0, vec![0x13, 0x05, 0x00, 0x00]),
+                Segment::new(0x10002, vec![0xAA, 0xBB]),
+            ]
+        );
+        assert_eq!(image.entry, Some(0x100));
+    }
+
+    #[test]
+    fn test_bad_checksum() {
+        assert!(matches!(
+            parse(":0400000013050000E5\n", "test"),
+            Err(ReadFileError::MalformedRecord { line: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn test_reports_line_number() {
+        assert!(matches!(
+            parse(":0400000013050000E4\n0400000013050000E4\n", "test"),
+            Err(ReadFileError::MalformedRecord { line: 2, .. })
+        ));
+    }
+}
@@ -0,0 +1,208 @@
+use super::{Image, Segment};
+use crate::ReadFileError;
+
+use std::collections::BTreeMap;
+
+/// Parse the `addr: data data ...` format produced by `make_mem.sh`.
+///
+/// ```text
+/// # Comments start with # or //
+/// 0:  00000013 0013 13        // A word, a halfword and a byte
+/// 10: ff/8 1/16 deadbeef/32   # Widths can also be given explicitly
+/// ```
+///
+/// Addresses and data are hex. Without a suffix the width of a data token comes from the number
+/// of digits: 2 for a byte, 4 for a halfword and 8 for a word. A suffix of `/8`, `/16` or `/32`
+/// picks the width explicitly, in which case any number of digits is allowed as long as the
+/// value fits. Every line becomes its own segment, and writing the same byte twice is an error.
+pub fn parse(text: &str, file: &str) -> Result<Image, ReadFileError> {
+    let mut image = Image::default();
+    // Every range written so far, keyed by start address, holding the end address and line
+    let mut written: BTreeMap<u64, (u64, usize)> = BTreeMap::new();
+    let syntax = |line: usize, column: usize, message: String| ReadFileError::MemSyntax {
+        file: file.to_string(),
+        line,
+        column,
+        message,
+    };
+
+    for (index, line) in text.lines().enumerate() {
+        let line_num = index + 1;
+        let line = strip_comment(line);
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (raw_addr, data) = line.split_once(':').ok_or(syntax(
+            line_num,
+            first_column(line),
+            "expected `address: data`".to_string(),
+        ))?;
+        let addr_column = first_column(raw_addr);
+        let addr_str = raw_addr.trim();
+        let addr_hex = addr_str
+            .strip_prefix("0x")
+            .or(addr_str.strip_prefix("0X"))
+            .unwrap_or(addr_str);
+        let addr = u32::from_str_radix(addr_hex, 16)
+            .map_err(|_| syntax(line_num, addr_column, format!("invalid address `{addr_str}`")))?;
+
+        let data_start = raw_addr.len() + 1;
+        let mut bytes = Vec::new();
+        for (offset, token) in tokens(data) {
+            let column = data_start + offset + 1;
+            let width = parse_token(token, &mut bytes)
+                .map_err(|message| syntax(line_num, column, message))?;
+
+            // Make sure this doesn't overwrite anything from an earlier line
+            let start = addr as u64 + (bytes.len() - width) as u64;
+            let end = addr as u64 + bytes.len() as u64;
+            if end > 1 << 32 {
+                return Err(syntax(line_num, column, "data runs past the end of the address space".to_string()));
+            }
+            if let Some((_, &(prev_end, prev_line))) = written.range(..end).next_back() {
+                if prev_end > start {
+                    return Err(ReadFileError::OverlappingWrite {
+                        file: file.to_string(),
+                        line: line_num,
+                        column,
+                        addr: start as u32,
+                        previous_line: prev_line,
+                    });
+                }
+            }
+        }
+        if !bytes.is_empty() {
+            written.insert(addr as u64, (addr as u64 + bytes.len() as u64, line_num));
+            image.segments.push(Segment::new(addr, bytes));
+        }
+    }
+    Ok(image)
+}
+
+/// Write bytes back out in the same format, four words to a line. Any bytes before the first
+/// word boundary or after the last one are written as single bytes.
+pub fn write(start: u32, bytes: &[u8]) -> String {
+    let mut out = String::new();
+    let mut addr = start;
+    let mut rest = bytes;
+    while !rest.is_empty() {
+        out.push_str(&format!("{addr:x}:"));
+        let line_start = addr;
+        while !rest.is_empty() && addr.wrapping_sub(line_start) < 16 {
+            if addr.is_multiple_of(4) && rest.len() >= 4 {
+                let word = u32::from_le_bytes([rest[0], rest[1], rest[2], rest[3]]);
+                out.push_str(&format!(" {word:08x}"));
+                rest = &rest[4..];
+                addr = addr.wrapping_add(4);
+            } else {
+                out.push_str(&format!(" {:02x}", rest[0]));
+                rest = &rest[1..];
+                addr = addr.wrapping_add(1);
+            }
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// Parse one data token, appending it to `bytes` in little-endian order and returning its width
+fn parse_token(token: &str, bytes: &mut Vec<u8>) -> Result<usize, String> {
+    let (digits, suffix) = match token.split_once('/') {
+        Some((digits, suffix)) => (digits, Some(suffix)),
+        None => (token, None),
+    };
+    let width = match suffix {
+        Some("8") => 1,
+        Some("16") => 2,
+        Some("32") => 4,
+        Some(s) => return Err(format!("invalid width `/{s}`, expected /8, /16 or /32")),
+        None => match digits.len() {
+            2 => 1,
+            4 => 2,
+            8 => 4,
+            n => {
+                return Err(format!(
+                    "`{token}` has {n} digits, use 2, 4 or 8 digits or give a width with /8, /16 or /32"
+                ))
+            }
+        },
+    };
+    let value = u32::from_str_radix(digits, 16).map_err(|_| format!("invalid hex `{digits}`"))?;
+    if width < 4 && value >> (width * 8) != 0 {
+        return Err(format!("`{digits}` does not fit in {} bits", width * 8));
+    }
+    bytes.extend_from_slice(&value.to_le_bytes()[..width]);
+    Ok(width)
+}
+
+/// Remove anything after a `#` or `//`
+fn strip_comment(line: &str) -> &str {
+    let end = [line.find('#'), line.find("//")]
+        .into_iter()
+        .flatten()
+        .min()
+        .unwrap_or(line.len());
+    &line[..end]
+}
+
+/// 1-indexed column of the first non-whitespace character
+fn first_column(s: &str) -> usize {
+    s.len() - s.trim_start().len() + 1
+}
+
+/// Split on whitespace, keeping the byte offset of each token
+fn tokens(s: &str) -> impl Iterator<Item = (usize, &str)> {
+    s.split_whitespace()
+        .map(move |token| (token.as_ptr() as usize - s.as_ptr() as usize, token))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_objdump_line() {
+        let image = parse("      2c:\t00b50533 0a", "test.mem").unwrap();
+        assert_eq!(
+            image.segments,
+            vec![Segment::new(0x2c, vec![0x33, 0x05, 0xb5, 0x00, 0x0a])]
+        );
+    }
+
+    #[test]
+    fn test_comments_and_widths() {
+        let text = "# header\n\n0x10: ff/8 1/16 // trailing\n14: 2/32 # more\n";
+        let image = parse(text, "test.mem").unwrap();
+        assert_eq!(
+            image.segments,
+            vec![
+                Segment::new(0x10, vec![0xff, 0x01, 0x00]),
+                Segment::new(0x14, vec![0x02, 0, 0, 0]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_error_location() {
+        match parse("0: 00000013\n4: 0000001\n", "test.mem") {
+            Err(ReadFileError::MemSyntax { line, column, .. }) => assert_eq!((line, column), (2, 4)),
+            e => panic!("unexpected result {e:?}"),
+        }
+        match parse("0: 1ff/8\n", "test.mem") {
+            Err(ReadFileError::MemSyntax { line, column, .. }) => assert_eq!((line, column), (1, 4)),
+            e => panic!("unexpected result {e:?}"),
+        }
+    }
+
+    #[test]
+    fn test_overlapping_write() {
+        match parse("0: 00000013 00000013\n6: 0000\n", "test.mem") {
+            Err(ReadFileError::OverlappingWrite { line, column, addr, previous_line, .. }) => {
+                assert_eq!((line, column, addr, previous_line), (2, 4, 6, 1))
+            }
+            e => panic!("unexpected result {e:?}"),
+        }
+        // Directly adjacent lines are fine
+        assert!(parse("0: 00000013\n4: 00000013\n", "test.mem").is_ok());
+    }
+}
@@ -0,0 +1,360 @@
+pub mod bin;
+pub mod elf;
+pub mod ihex;
+pub mod mem;
+pub mod srec;
+
+use crate::memory::Memory;
+use crate::symbols::Symbol;
+use crate::ReadFileError;
+
+use clap::ValueEnum;
+use std::fs;
+use std::num;
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+#[clap(rename_all = "kebab_case")]
+pub enum Format {
+    /// Guess the format from the file contents and extension
+    Auto,
+    /// The `addr: data` format produced by `make_mem.sh`
+    Mem,
+    Elf,
+    /// Intel HEX
+    Ihex,
+    /// Motorola S-record
+    Srec,
+    /// Raw binary, loaded at address 0 unless given `file.bin@addr`
+    Bin,
+    /// RV32IM assembly, assembled in-process
+    Asm,
+}
+
+impl Format {
+    /// Figure out which format a file is in. The contents are checked first, as ELF, Intel HEX
+    /// and S-records all have distinctive starts, then the extension.
+    pub fn detect(path: &str, contents: &[u8]) -> Format {
+        if is_elf(contents) {
+            return Format::Elf;
+        }
+        let extension = Path::new(path)
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_lowercase());
+        match extension.as_deref() {
+            Some("bin") => return Format::Bin,
+            Some("hex") | Some("ihex") => return Format::Ihex,
+            Some("srec") | Some("s19") | Some("s28") | Some("s37") => return Format::Srec,
+            Some("mem") => return Format::Mem,
+            Some("s") | Some("asm") => return Format::Asm,
+            _ => {}
+        }
+        let first = contents.iter().find(|c| !c.is_ascii_whitespace());
+        match (first, contents.iter().filter(|c| !c.is_ascii_whitespace()).nth(1)) {
+            (Some(b':'), _) => Format::Ihex,
+            (Some(b'S'), Some(c)) if c.is_ascii_digit() => Format::Srec,
+            _ => Format::Mem,
+        }
+    }
+
+    /// Turn the contents of a file into an image. `path` is only used for error messages and
+    /// `load_addr` is only meaningful for raw binaries.
+    pub fn parse(&self, path: &str, contents: &[u8], load_addr: Option<u32>) -> Result<Image, ReadFileError> {
+        if load_addr.is_some() && *self != Format::Bin {
+            return Err(ReadFileError::ParseError(format!(
+                "a load address can only be given for raw binaries, not {self:?} files"
+            )));
+        }
+        match self {
+            Format::Auto => unreachable!("format should be detected before parsing"),
+            Format::Elf => elf::parse(contents),
+            Format::Bin => Ok(bin::parse(contents, load_addr.unwrap_or(0))),
+            Format::Mem => mem::parse(as_text(contents)?, path),
+            Format::Ihex => ihex::parse(as_text(contents)?, path),
+            Format::Srec => srec::parse(as_text(contents)?, path),
+            Format::Asm => crate::assembler::assemble(as_text(contents)?, path),
+        }
+    }
+}
+
+/// Formats that a region of memory can be written back out as
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+#[clap(rename_all = "kebab_case")]
+pub enum ExportFormat {
+    Mem,
+    Ihex,
+    Bin,
+}
+
+impl ExportFormat {
+    /// Pick a format from the extension of the file being written, defaulting to `.mem`.
+    /// Extensions of formats that can only be loaded, like `.srec`, are an error rather than
+    /// getting `.mem` text under the wrong name.
+    pub fn from_path(path: &str) -> Result<ExportFormat, String> {
+        match Format::detect(path, &[]) {
+            Format::Ihex => Ok(ExportFormat::Ihex),
+            Format::Bin => Ok(ExportFormat::Bin),
+            Format::Mem => Ok(ExportFormat::Mem),
+            format => Err(format!("memory can't be exported as {format:?}, only as mem, ihex or bin")),
+        }
+    }
+
+    /// Encode bytes that start at `start` in this format. Every format here can be read back in
+    /// by the matching loader, raw binaries just need to be given `@start`.
+    pub fn export(&self, start: u32, bytes: &[u8]) -> Vec<u8> {
+        match self {
+            ExportFormat::Mem => mem::write(start, bytes).into_bytes(),
+            ExportFormat::Ihex => ihex::write(start, bytes).into_bytes(),
+            ExportFormat::Bin => bytes.to_vec(),
+        }
+    }
+}
+
+/// Read and parse a program. `spec` is a filename, optionally followed by `@addr` to give
+/// the load address of a raw binary.
+pub fn load(spec: &str, format: Format) -> Result<Image, ReadFileError> {
+    let (path, load_addr) = split_load_address(spec);
+    let contents = fs::read(path)?;
+    let format = match format {
+        Format::Auto => Format::detect(path, &contents),
+        f => f,
+    };
+    format.parse(path, &contents, load_addr)
+}
+
+/// An extra file given with `--load FILE[@ADDR][:ro]`. With an address the file is copied into
+/// memory byte for byte, without one it is loaded like a program.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoadSpec {
+    pub path: String,
+    pub addr: Option<u32>,
+    pub read_only: bool,
+}
+
+impl LoadSpec {
+    pub fn parse(s: &str) -> Result<LoadSpec, String> {
+        let (rest, read_only) = match s.strip_suffix(":ro") {
+            Some(rest) => (rest, true),
+            None => (s, false),
+        };
+        let (path, addr) = split_load_address(rest);
+        if path.is_empty() {
+            return Err(format!("expected FILE[@ADDR][:ro], found `{s}`"));
+        }
+        Ok(LoadSpec {
+            path: path.to_string(),
+            addr,
+            read_only,
+        })
+    }
+
+    pub fn load(&self) -> Result<Image, ReadFileError> {
+        let contents = fs::read(&self.path)?;
+        match self.addr {
+            Some(addr) => Format::Bin.parse(&self.path, &contents, Some(addr)),
+            None => Format::detect(&self.path, &contents).parse(&self.path, &contents, None),
+        }
+    }
+}
+
+/// Split `file.bin@0x1000` into the filename and address. If whatever is after the `@` is not a
+/// number the whole thing is treated as a filename.
+pub fn split_load_address(spec: &str) -> (&str, Option<u32>) {
+    match spec.rsplit_once('@') {
+        Some((path, addr)) => match parse_number(addr) {
+            Ok(addr) => (path, Some(addr)),
+            Err(_) => (spec, None),
+        },
+        None => (spec, None),
+    }
+}
+
+/// Parse an inclusive range of addresses written as `START-END`
+pub fn parse_range(s: &str) -> Result<RangeInclusive<u32>, String> {
+    let (start, end) = s
+        .split_once('-')
+        .ok_or(format!("expected a range like 0x1000-0x1fff, found `{s}`"))?;
+    let start = parse_number(start).map_err(|e| format!("invalid start address `{start}`: {e}"))?;
+    let end = parse_number(end).map_err(|e| format!("invalid end address `{end}`: {e}"))?;
+    if end < start {
+        return Err(format!("range `{s}` ends before it starts"));
+    }
+    Ok(start..=end)
+}
+
+/// Parse a number as hex if it starts with 0x, or decimal otherwise
+pub fn parse_number(s: &str) -> Result<u32, num::ParseIntError> {
+    let s = s.trim();
+    match s.strip_prefix("0x").or(s.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+}
+
+fn as_text(contents: &[u8]) -> Result<&str, ReadFileError> {
+    std::str::from_utf8(contents)
+        .map_err(|e| ReadFileError::ParseError(format!("file is not valid text: {e}")))
+}
+
+/// Decode a string of hex digit pairs, as used by Intel HEX and S-records
+fn decode_hex(s: &str, file: &str, line: usize) -> Result<Vec<u8>, ReadFileError> {
+    if !s.len().is_multiple_of(2) || !s.is_ascii() {
+        return Err(malformed(file, line, "record has an odd number of hex digits"));
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&s[i..i + 2], 16)
+                .map_err(|_| malformed(file, line, &format!("invalid hex `{}`", &s[i..i + 2])))
+        })
+        .collect()
+}
+
+fn malformed(file: &str, line: usize, message: &str) -> ReadFileError {
+    ReadFileError::MalformedRecord {
+        file: file.to_string(),
+        line,
+        message: message.to_string(),
+    }
+}
+
+/// A contiguous run of bytes to be placed in guest memory at `addr`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+    pub addr: u32,
+    pub data: Vec<u8>,
+    /// How much memory the segment takes up, at least `data.len()`. Anything past the data, like
+    /// an ELF's `.bss`, is zeros, which are never stored.
+    pub size: usize,
+}
+
+impl Segment {
+    /// A segment that is exactly its data
+    pub fn new(addr: u32, data: Vec<u8>) -> Self {
+        let size = data.len();
+        Segment { addr, data, size }
+    }
+}
+
+/// A program as read from disk, before it gets copied into the machine's memory
+#[derive(Debug, Default)]
+pub struct Image {
+    pub segments: Vec<Segment>,
+    /// Where execution should start, if the file format specifies it
+    pub entry: Option<u32>,
+    pub symbols: Vec<Symbol>,
+}
+
+impl Image {
+    /// Append bytes at an address, growing the last segment if they are contiguous with it
+    pub fn push_bytes(&mut self, addr: u32, data: &[u8]) {
+        match self.segments.last_mut() {
+            Some(last) if last.size == last.data.len() && last.addr.wrapping_add(last.size as u32) == addr => {
+                last.data.extend_from_slice(data);
+                last.size = last.data.len();
+            }
+            _ => self.segments.push(Segment::new(addr, data.to_vec())),
+        }
+    }
+    /// The inclusive address ranges covered by the image, empty segments are skipped
+    pub fn ranges(&self) -> impl Iterator<Item = RangeInclusive<u32>> + '_ {
+        self.segments
+            .iter()
+            .filter(|segment| segment.size != 0)
+            .map(|segment| segment.addr..=segment.addr.wrapping_add(segment.size as u32 - 1))
+    }
+    /// Copy every segment into memory, failing if any of them do not fit
+    pub fn write_to(&self, memory: &mut Memory) -> Result<(), ReadFileError> {
+        for segment in self.segments.iter() {
+            let end = segment.addr as usize + segment.size;
+            if end > memory.size() {
+                return Err(ReadFileError::AddressOutOfRange {
+                    addr: segment.addr,
+                    len: segment.size,
+                    memory_size: memory.size(),
+                });
+            }
+            memory.write(segment.addr, &segment.data);
+            // Only pages that something was loaded into earlier need clearing
+            let zeros = segment.addr.wrapping_add(segment.data.len() as u32);
+            memory.zero(zeros, segment.size - segment.data.len());
+        }
+        Ok(())
+    }
+}
+
+/// Check the first few bytes of a file to see if it is an ELF
+pub fn is_elf(bytes: &[u8]) -> bool {
+    bytes.starts_with(&elf::MAGIC)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_format() {
+        assert_eq!(Format::detect("a.out", &[0x7F, b'E', b'L', b'F']), Format::Elf);
+        assert_eq!(Format::detect("prog.txt", b":00000001FF\n"), Format::Ihex);
+        assert_eq!(Format::detect("prog.txt", b"S00600004844521B\n"), Format::Srec);
+        assert_eq!(Format::detect("prog", b"0: 00000013\n"), Format::Mem);
+        assert_eq!(Format::detect("blob.BIN", b"S1"), Format::Bin);
+        assert_eq!(Format::detect("test.S", b"li a0, 1"), Format::Asm);
+    }
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("0x1000-0x1fff"), Ok(0x1000..=0x1fff));
+        assert_eq!(parse_range("16-16"), Ok(16..=16));
+        assert!(parse_range("0x20-0x10").is_err());
+        assert!(parse_range("0x20").is_err());
+    }
+
+    #[test]
+    fn test_export_round_trip() {
+        let bytes: Vec<u8> = (0..=255u8).cycle().take(0x1234).collect();
+        for format in [ExportFormat::Mem, ExportFormat::Ihex] {
+            let exported = format.export(0xFFF3, &bytes);
+            let image = Format::detect("dump", &exported).parse("dump", &exported, None).unwrap();
+            let mut memory = Memory::new(0x20000);
+            image.write_to(&mut memory).unwrap();
+            assert_eq!(&memory.slice(0xFFF3, bytes.len())[..], &bytes[..], "{format:?}");
+        }
+    }
+
+    #[test]
+    fn test_export_format_from_path() {
+        assert_eq!(ExportFormat::from_path("dump.hex"), Ok(ExportFormat::Ihex));
+        assert_eq!(ExportFormat::from_path("dump.bin"), Ok(ExportFormat::Bin));
+        assert_eq!(ExportFormat::from_path("dump"), Ok(ExportFormat::Mem));
+        assert!(ExportFormat::from_path("dump.srec").is_err());
+        assert!(ExportFormat::from_path("dump.s").is_err());
+    }
+
+    #[test]
+    fn test_parse_load_spec() {
+        assert_eq!(
+            LoadSpec::parse("photo.jpg@0x8000:ro"),
+            Ok(LoadSpec { path: "photo.jpg".to_string(), addr: Some(0x8000), read_only: true })
+        );
+        assert_eq!(
+            LoadSpec::parse("table.bin@4096"),
+            Ok(LoadSpec { path: "table.bin".to_string(), addr: Some(4096), read_only: false })
+        );
+        assert_eq!(
+            LoadSpec::parse("extra.mem"),
+            Ok(LoadSpec { path: "extra.mem".to_string(), addr: None, read_only: false })
+        );
+        assert!(LoadSpec::parse(":ro").is_err());
+    }
+
+    #[test]
+    fn test_split_load_address() {
+        assert_eq!(split_load_address("file.bin@0x1000"), ("file.bin", Some(0x1000)));
+        assert_eq!(split_load_address("file.bin@64"), ("file.bin", Some(64)));
+        assert_eq!(split_load_address("me@host.bin"), ("me@host.bin", None));
+        assert_eq!(split_load_address("file.bin"), ("file.bin", None));
+    }
+}
@@ -0,0 +1,103 @@
+use super::{decode_hex, malformed, Image};
+use crate::ReadFileError;
+
+/// Parse a Motorola S-record file.
+/// S1/S2/S3 data records and S7/S8/S9 start addresses are used. S5/S6 count records must match
+/// the number of data records before them, and headers are checked but otherwise ignored.
+pub fn parse(text: &str, file: &str) -> Result<Image, ReadFileError> {
+    let mut image = Image::default();
+    let mut data_records = 0;
+    for (index, line) in text.lines().enumerate() {
+        let line_num = index + 1;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let mut chars = line.chars();
+        if chars.next() != Some('S') {
+            return Err(malformed(file, line_num, "record does not start with `S`"));
+        }
+        let record_type = chars
+            .next()
+            .and_then(|c| c.to_digit(10))
+            .ok_or(malformed(file, line_num, "missing record type"))?;
+        let bytes = decode_hex(chars.as_str(), file, line_num)?;
+        let count = *bytes
+            .first()
+            .ok_or(malformed(file, line_num, "record is too short"))? as usize;
+        if bytes.len() != count + 1 {
+            let message = format!("byte count is {count} but the record holds {} bytes", bytes.len() - 1);
+            return Err(malformed(file, line_num, &message));
+        }
+        // The checksum is the ones complement of the sum of everything before it
+        let (checksum, body) = bytes.split_last().unwrap();
+        if !body.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != *checksum {
+            return Err(malformed(file, line_num, "bad checksum"));
+        }
+        let address_len = match record_type {
+            0 | 1 | 5 | 9 => 2,
+            2 | 6 | 8 => 3,
+            3 | 7 => 4,
+            t => return Err(malformed(file, line_num, &format!("unknown record type S{t}"))),
+        };
+        let body = &body[1..];
+        if body.len() < address_len {
+            return Err(malformed(file, line_num, "address is truncated"));
+        }
+        let addr = body[..address_len]
+            .iter()
+            .fold(0u32, |addr, b| (addr << 8) | *b as u32);
+        let data = &body[address_len..];
+        match record_type {
+            1..=3 => {
+                image.push_bytes(addr, data);
+                data_records += 1;
+            }
+            5 | 6 if addr != data_records => {
+                let message = format!("count record says {addr} data records but there are {data_records}");
+                return Err(malformed(file, line_num, &message));
+            }
+            7..=9 => image.entry = Some(addr),
+            _ => {}
+        }
+    }
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::Segment;
+
+    #[test]
+    fn test_parse_srec() {
+        let text = "S00600004844521B\nS107000013050000E0\nS30900001000AABBCCDDD8\nS9030000FC\n";
+        let image = parse(text, "test").unwrap();
+        assert_eq!(
+            image.segments,
+            vec![
+                Segment::new(0, vec![0x13, 0x05, 0x00, 0x00]),
+                Segment::new(0x1000, vec![0xAA, 0xBB, 0xCC, 0xDD]),
+            ]
+        );
+        assert_eq!(image.entry, Some(0));
+    }
+
+    #[test]
+    fn test_bad_checksum() {
+        assert!(matches!(
+            parse("S00600004844521B\nS107000013050000E1\n", "test"),
+            Err(ReadFileError::MalformedRecord { line: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn test_count_record() {
+        let data = "S107000013050000E0\nS30900001000AABBCCDDD8\n";
+        assert!(parse(&format!("{data}S5030002FA\n"), "test").is_ok());
+        assert!(matches!(
+            parse(&format!("{data}S5030003F9\n"), "test"),
+            Err(ReadFileError::MalformedRecord { line: 3, .. })
+        ));
+    }
+}
@@ -1,15 +1,28 @@
 use crate::debugger::{DebugCommand,self};
-use crate::decode::ParseError;
+use crate::compressed::is_compressed;
+use crate::decode::{bytes_to_u32, ParseError};
+use crate::csr::{self, CsrFile};
+use crate::breakpoint::{Breakpoint, Watch, WatchEvent, WatchHit, Watchpoint};
+use crate::bus::{Bus, Region};
 use crate::devices::{Device};
-use crate::opcode::Operation;
-use crate::register::Register;
+use crate::icache::InstructionCache;
+use crate::isa::{Extension, Isa};
+use crate::journal::{Change, Entry, Journal};
+use crate::memory::{self, Memory};
+use crate::opcode::{Instruction, Operation};
+use crate::register::{FRegister, Register};
+use crate::softfloat::{self, Format, RoundingMode, DOUBLE, SINGLE};
+use crate::symbols::SymbolTable;
 
+use std::borrow::Cow;
+use std::cell::Cell;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use rustyline::error::ReadlineError;
 use serde::Serialize;
 use std::fmt::Write;
 use std::io::{self,Stdin};
+use std::ops::RangeInclusive;
 use single_value_channel::Updater as SvcSender;
 use crossbeam_channel::Receiver as CbReceiver;
 use educe::Educe;
@@ -21,19 +34,17 @@ use crate::environment::{self, Environment};
 
 #[derive(Serialize)]
 pub struct Machine {
-    // Maybe this should be  on the heap
-    // How is memory mapped? Is there max 64K? Or is that just the size of program
-    // we can load?
-    //
-    // NOTE: This is a boxed slice, while it could well be a Vec for simplicity
-    // we also really don't want to have someone resizing the mmap
+    // Pages are only allocated once they are written to, so this can cover all of the address
+    // space below the devices
     #[serde(skip_serializing)]
-    memory: Box<[u8]>,
+    memory: Memory,
     // The top of memory, points right above the last usable address
     memory_top: u32,
     // Store x1-x31
     // x0 is always 0, no reason to store
     registers: [u32; 31],
+    // f0-f31, singles are NaN-boxed into the low half
+    fregisters: [u64; 32],
     pc: u32,
     // Whether we should step over a breakpoint or not
     pass_breakpoint: bool,
@@ -41,10 +52,24 @@ pub struct Machine {
     //       index/breakpoint number to breakpoint which is nice for ui
     //
     //       Might be way slow though to iterate through this every cycle though
-    breakpoints: Vec<u32>,
+    breakpoints: Vec<Breakpoint>,
+    #[serde(skip_serializing)]
+    watchpoints: Vec<Watchpoint>,
+    // The first watchpoint the current instruction set off. Loads only borrow the machine, so
+    // they record it here for `step` to pick up.
+    #[serde(skip_serializing)]
+    watch_hit: Cell<Option<WatchHit>>,
+    // What the last few instructions changed, for running backwards. This is only kept once
+    // someone is debugging, so plain runs go at full speed.
+    #[serde(skip_serializing)]
+    journal: Journal,
+    // How many instructions the journal holds
+    #[serde(skip_serializing)]
+    history: usize,
     // store the current instruction as a string, for display
     cur_inst: String,
-    // store the memory locations that changed since last instruction
+    // store the memory locations that changed since last instruction, as addresses and values.
+    // Only running backwards fills this in, with what was put back
     memory_changes: Vec<(u32,u32)>,
     // is the machine running?
     web_runfullspeed: bool,
@@ -52,35 +77,106 @@ pub struct Machine {
     web_step: bool,
     verbose: bool,
     cycle: u128,
+    csrs: CsrFile,
+    // Extensions that decode, anything else is an illegal instruction
+    #[serde(skip_serializing)]
+    isa: Isa,
+    // Instructions that have already been decoded, by pc
     #[serde(skip_serializing)]
-    devices: Vec<Device>,
+    icache: InstructionCache,
+    // The word reserved by the last LR.W, cleared by any store that touches it
+    #[serde(skip_serializing)]
+    reservation: Option<u32>,
+    // RAM and the devices
+    #[serde(skip_serializing)]
+    bus: Bus,
     #[serde(skip_serializing)]
     env: Environment,
+    // Symbols from the loaded image and any map file, empty if there were none
+    #[serde(skip_serializing)]
+    symbols: SymbolTable,
+    // Regions loaded with `:ro`, stores into these fault
+    #[serde(skip_serializing)]
+    read_only: Vec<RangeInclusive<u32>>,
 }
 impl Machine {
-    pub fn new(starting_addr: u32, stack_addr: Option<u32>, memory_top: u32, memmap: Box<[u8]>,verbose:bool,devices:Vec<Device>) -> Self{
+    pub fn new(starting_addr: u32, stack_addr: Option<u32>, memmap: Memory,verbose:bool,bus:Bus) -> Self{
+        let memory_top = bus.memory_top();
         let mut m = Machine {
                     memory: memmap,
                     registers: [0;31],
+                    fregisters: [0;32],
                     memory_top,
-                    devices,
+                    bus,
                     pc: starting_addr,
                     pass_breakpoint: false,
                     breakpoints: Vec::new(),
+                    watchpoints: Vec::new(),
+                    watch_hit: Cell::new(None),
+                    journal: Journal::new(0),
+                    history: 0,
                     env:Environment::new(),
                     cur_inst: "".to_string(),
                     memory_changes: Vec::new(),
                     web_runfullspeed: true,
                     web_step: false,
                     verbose: verbose,
-                    cycle: 0
-                    
+                    cycle: 0,
+                    csrs: CsrFile::default(),
+                    isa: Isa::default(),
+                    icache: InstructionCache::default(),
+                    reservation: None,
+                    symbols: SymbolTable::default(),
+                    read_only: Vec::new(),
+
         };
         // Set the stack pointer to the lowest invalid memory address by default, aligning down to
         // nearest 16 bytes
         m.set_reg(Register::SP, stack_addr.unwrap_or(memory_top & !(0xF)));
         m
     }
+    /// Build the initial stack that a program's `_start` expects, below the current stack pointer.
+    /// From SP upwards this is argc, the argv pointers, a null, the envp pointers, a null and an
+    /// empty auxiliary vector, with the strings themselves above that. `a0`, `a1` and `a2` are set
+    /// to argc, argv and envp so startup code that just calls `main` can forward them untouched.
+    pub fn setup_args(&mut self, args: &[String], env: &[String]) -> Result<(), ExecutionError> {
+        let mut top = self.registers[Register::SP];
+        let mut push_string = |machine: &mut Machine, s: &str| -> Result<u32, ExecutionError> {
+            top = top.wrapping_sub(s.len() as u32 + 1);
+            for (i, byte) in s.bytes().chain([0]).enumerate() {
+                machine.store_byte(byte, top.wrapping_add(i as u32))?;
+            }
+            Ok(top)
+        };
+        let mut argv = Vec::new();
+        for arg in args {
+            argv.push(push_string(self, arg)?);
+        }
+        let mut envp = Vec::new();
+        for var in env {
+            envp.push(push_string(self, var)?);
+        }
+
+        // argc, argv and its null, envp and its null, then the AT_NULL auxv entry
+        let words = 1 + (argv.len() + 1) + (envp.len() + 1) + 2;
+        let sp = top.wrapping_sub(words as u32 * 4) & !0xF;
+        let argv_addr = sp.wrapping_add(4);
+        let envp_addr = argv_addr.wrapping_add((argv.len() as u32 + 1) * 4);
+        let mut table = vec![argv.len() as u32];
+        table.extend(argv.iter());
+        table.push(0);
+        table.extend(envp.iter());
+        table.extend([0, 0, 0]);
+        for (i, word) in table.iter().enumerate() {
+            self.store_word(*word, sp.wrapping_add(i as u32 * 4))?;
+        }
+
+        self.set_reg(Register::SP, sp);
+        self.set_reg(Register::A0, argv.len() as u32);
+        self.set_reg(Register::A1, argv_addr);
+        self.set_reg(Register::A2, envp_addr);
+        Ok(())
+    }
     /// Run the machine til completion, either running silently until an error is hit or bringing
     /// up the debugger after every step
     pub fn run(&mut self, single_step: bool, _stdin: &Stdin, commands_rx: Option<CbReceiver<statetransfer::ControlCode>>, state_tx: Option<SvcSender<statetransfer::MachineState>>) -> Result<(),ExecutionError> {
@@ -98,16 +194,35 @@ impl Machine {
         let mut watchlist: Vec<DebugCommand> = Vec::new();
         if ! commands_rx.is_none() {
             self.web_runfullspeed = false;
+            self.record_history();
+        } else {
+            // Ctrl-C drops into the debugger rather than killing the simulator. The web UI has its
+            // own stop button.
+            let interrupt = should_trigger_cmd.clone();
+            let _ = ctrlc::set_handler(move || interrupt.store(true, Ordering::SeqCst));
         };
         loop {
-            // Stringify the current instruction
-            self.cur_inst = match self.read_instruction_bytes(self.pc) {
-                Ok(bytes) => match Operation::from_bytes(bytes) {
-                    Ok(op) => format!("{}",op),
-                    _ => "invalid".to_owned()
-                },
-                _ => "invalid".to_owned()
-            };
+            // With nobody watching, run flat out until something needs the debugger
+            if commands_rx.is_none() && should_step.is_none() && !self.verbose
+                && !should_trigger_cmd.load(Ordering::SeqCst) {
+                match self.run_fast(&should_trigger_cmd) {
+                    // Interrupted, the debugger comes up next time round
+                    Ok(()) => {},
+                    Err(e@ ExecutionError::Breakpoint(_)) => {
+                        should_trigger_cmd.store(true,Ordering::SeqCst);
+                        self.pass_breakpoint = true;
+                        status.push(format!("{}",e));
+                    },
+                    // The instruction that set off the watchpoint has already run
+                    Err(e@ ExecutionError::Watchpoint(_)) => {
+                        should_trigger_cmd.store(true,Ordering::SeqCst);
+                        self.cycle += 1;
+                        status.push(format!("{}",e));
+                    },
+                    Err(e) => return Err(e)
+                }
+                continue;
+            }
             /*
                 At the start of each cycle, if the web server is running, we want to communicate with it.
                 We exchange information - we send the current state of the machine, and we read commands sent from the web interface.
@@ -115,6 +230,14 @@ impl Machine {
              */
             match state_tx {
                 Some(ref tx) => {
+                    // Stringify the current instruction
+                    self.cur_inst = match self.read_instruction_bytes(self.pc) {
+                        Ok(bytes) => match Instruction::decode(&bytes) {
+                            Ok(op) => format!("{}",op.display_with(self.pc,&self.symbols)),
+                            _ => "invalid".to_owned()
+                        },
+                        _ => "invalid".to_owned()
+                    };
                     // send machine state to web ui
                     let m = MachineState {
                         pc: self.pc,
@@ -139,14 +262,29 @@ impl Machine {
                     ControlCode::STOP => { self.web_runfullspeed = false; }
                     ControlCode::RUN => { self.web_runfullspeed = true; }
                     ControlCode::STEP => { self.web_step = true }
+                    // These stop wherever they end up, the web UI has no way to show why
+                    ControlCode::STEPBACK => {
+                        self.web_runfullspeed = false;
+                        let _ = self.run_backwards(Some(1));
+                    }
+                    ControlCode::RCONTINUE => {
+                        self.web_runfullspeed = false;
+                        let _ = self.run_backwards(None);
+                    }
+                    // Undoing past any of these would rebuild a state that never existed, so the
+                    // history is thrown away
                     ControlCode::POKEREG { register, value } => {
                         self.registers[register.to_num()] = *value;
+                        self.forget_history();
                     },
                     ControlCode::POKE { address, value } => {
-                        self.memory[*address as usize] = *value as u8;
+                        self.memory.write_byte(*address, *value as u8);
+                        self.icache.invalidate(*address, 1);
+                        self.forget_history();
                     }
                     ControlCode::JMP { address } => {
                         self.pc = *address;
+                        self.forget_history();
                     },
                     _ => {}
                 }
@@ -164,6 +302,7 @@ impl Machine {
             };
 
             if should_trigger_cmd.load(Ordering::SeqCst) {
+                self.record_history();
                 // print debug state
                 environment::clear_term();
                 environment::write_stdout(&self.display_info());
@@ -214,7 +353,7 @@ impl Machine {
                 // parse and handle debug command
                 let command = match read_value.as_str() {
                     "" => last_cmd.clone(),
-                    read_value => match DebugCommand::from_string(read_value) {
+                    read_value => match DebugCommand::from_string_with_symbols(read_value,&self.symbols) {
                         Ok(val) => val,
                         Err(e) => {
                             status.push(format!("{}",e));
@@ -253,25 +392,36 @@ impl Machine {
 
             }
             if !(self.web_runfullspeed || self.web_step) { continue };
-            match self.step() {
-                Ok(()) => {},
+            self.memory_changes.clear();
+            // Whether an instruction ran, which counts as a cycle
+            let ran = match self.step() {
+                Ok(()) => true,
                 // Should errors bail? Or bring up the debugger to explore program state?
                 // Bail for now probably, its easier (though worse)
+                // The breakpoint stops the instruction from running, just as in run_fast
                 Err(e@ ExecutionError::Breakpoint(_)) => {
                     should_trigger_cmd.store(true,Ordering::SeqCst);
                     // Give a pass so the next step of execution can make it past the breakpoint
                     self.pass_breakpoint = true;
                     status.push(format!("{}",e));
+                    false
+                },
+                Err(e@ ExecutionError::Watchpoint(_)) => {
+                    should_trigger_cmd.store(true,Ordering::SeqCst);
+                    status.push(format!("{}",e));
+                    true
                 },
                 Err(e) => return Err(e)
 
-            }
+            };
             if self.verbose {
                 println!("              cycle {}", self.cycle);
                 print!("{}", self.dump_state_txt());
             }
             self.web_step = false;
-            self.cycle+=1;
+            if ran {
+                self.cycle+=1;
+            }
             // Check if the user is pressing ctrl-c, and if they are, drop back into the debugger
             // oops this needs a bonus thread, this is going to suck
             // the thread can get spun up whenever we are running and spun down, or paused, when we
@@ -280,37 +430,43 @@ impl Machine {
         }
         
     }
+    /// Step until a breakpoint, an error, or `interrupt` is set. This is `run` without the
+    /// debugger and web UI, which cost more than the instructions themselves.
+    fn run_fast(&mut self, interrupt: &AtomicBool) -> Result<(), ExecutionError> {
+        while !interrupt.load(Ordering::Relaxed) {
+            self.step()?;
+            self.cycle += 1;
+        }
+        Ok(())
+    }
     // String formatting should never fail, it's likely safe to unwrap here
     pub fn display_info(&self) -> String {
         let mut buf = String::new();
         // Why am I doing this crazy nonsense? To ensure we only print terminal control characters if the output is a terminal.
         if environment::which_new_line() == "\r\n" { write!(buf,"{}","\r").unwrap(); };
         write!(buf,"PC:\t  {:#010x}", self.pc).unwrap();
+        if let Some(name) = self.symbols.symbolize(self.pc) {
+            write!(buf," <{name}>").unwrap();
+        }
+        let context = self.context_addresses();
         for i in 0 .. 31 {
             write!(buf,"{}",environment::which_new_line()).unwrap();
             write!(buf,"{1:?}:\t{0:>12}\t{0:#010x}",self.registers[i],Register::from_num((i as u32)+1).unwrap()).unwrap();
             if i < 16 {
-                let context: i32 = (i as i32-8)*4;
-                let to_fetch = self.pc.overflowing_add_signed(context);
-                match to_fetch {
-                    (addr, false) => {
-                        let display_me = match self.read_instruction_bytes(addr) {
-                            Ok(bytes) => match Operation::from_bytes(bytes) {
-                                Ok(op) => format!("{}",op),
-                                Err(e) => format!("{}",e)
-                            },
+                if let Some(addr) = context[i] {
+                    let display_me = match self.read_instruction_bytes(addr) {
+                        Ok(bytes) => match Instruction::decode(&bytes) {
+                            Ok(op) => format!("{}",op.display_with(addr,&self.symbols)),
                             Err(e) => format!("{}",e)
-                        };
-                        if i == 8 {
-                            write!(buf,"\t PC ->  {addr:#010x}: {}",display_me).unwrap();
-                        } else {
-                            write!(buf,"\t\t{addr:#010x}: {}",display_me).unwrap();
-                        }
-                    },
-                    (_num, true) => {
-
+                        },
+                        Err(e) => format!("{}",e)
+                    };
+                    if i == 8 {
+                        write!(buf,"\t PC ->  {addr:#010x}: {}",display_me).unwrap();
+                    } else {
+                        write!(buf,"\t\t{addr:#010x}: {}",display_me).unwrap();
                     }
-                };
+                }
             } else if i > 16 {
                 // Offset to fetch
                 let context = (i-17)*4;
@@ -323,6 +479,15 @@ impl Machine {
                 
             }
         }
+        // The floating point registers go underneath, four to a line
+        for (i, bits) in self.fregisters.iter().enumerate() {
+            if i % 4 == 0 {
+                write!(buf,"{}",environment::which_new_line()).unwrap();
+            }
+            let name = format!("{:?}:",FRegister::from_num(i as u32).unwrap());
+            write!(buf,"{name:<6}{:<16}",softfloat::describe(*bits)).unwrap();
+        }
+        write!(buf,"{}fcsr:   {:#04x}",environment::which_new_line(),self.csrs.read(csr::FCSR,self.cycle).unwrap()).unwrap();
         // TODO: Print a little bit of memory context, around where the stack is
         // And some instruction context as well
         environment::write_newline();
@@ -337,155 +502,512 @@ impl Machine {
         for i in 0 .. 31 { 
             write!(bytes,"{1:?}:{0:#010x}\n",self.registers[i],Register::from_num((i as u32)+1).unwrap()).unwrap();
         }
+        for (i, bits) in self.fregisters.iter().enumerate() {
+            writeln!(bytes,"{:?}:{bits:#018x}",FRegister::from_num(i as u32).unwrap()).unwrap();
+        }
+        for (num, name) in csr::CSRS {
+            writeln!(bytes,"{name}:{:#010x}",self.csrs.read(num,self.cycle).unwrap()).unwrap();
+        }
         bytes
 
     }
+    pub fn set_symbols(&mut self, symbols: SymbolTable) {
+        self.symbols = symbols;
+    }
+    /// Limit the instructions that decode to the extensions in `isa`, and report them in misa
+    pub fn set_isa(&mut self, isa: Isa) {
+        self.isa = isa;
+        self.csrs.misa = isa.misa();
+        self.icache.flush();
+    }
+    /// Decode every instruction each time it runs rather than caching them, if `enabled` is false
+    pub fn set_icache(&mut self, enabled: bool) {
+        self.icache = if enabled { InstructionCache::default() } else { InstructionCache::disabled() };
+    }
+    /// Keep the last `limit` instructions so that they can be undone, or none if it is 0. This
+    /// starts when the debugger or web UI is first used.
+    pub fn set_history(&mut self, limit: usize) {
+        self.history = limit;
+    }
+    /// Start keeping history, if it isn't already
+    fn record_history(&mut self) {
+        if !self.journal.is_on() {
+            self.journal = Journal::new(self.history);
+        }
+    }
+    /// Drop everything that could be undone, keeping history from here on if it was being kept
+    fn forget_history(&mut self) {
+        if self.journal.is_on() {
+            self.journal = Journal::new(self.history);
+        }
+    }
+    pub fn symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+    /// Make a region of memory read-only, so that any store into it faults
+    pub fn protect(&mut self, range: RangeInclusive<u32>) {
+        self.read_only.push(range);
+    }
+    /// Fault if a store of `len` bytes at `addr` would touch a read-only region
+    fn check_writable(&self, addr: u32, len: u32) -> Result<(), ExecutionError> {
+        let last = addr.saturating_add(len - 1);
+        if self.read_only.iter().any(|r| addr <= *r.end() && last >= *r.start()) {
+            Err(ExecutionError::StoreAccessFault(addr))
+        } else {
+            Ok(())
+        }
+    }
+    /// Check that all `len` bytes at `addr` are RAM that can be stored to, for stores made in
+    /// pieces that have to either all happen or fault before any of them do
+    fn check_storable(&self, addr: u32, len: u32) -> Result<(), ExecutionError> {
+        if !matches!(self.bus.decode(addr, len), Region::Ram) {
+            return Err(ExecutionError::StoreAccessFault(addr));
+        }
+        self.check_writable(addr, len)
+    }
+    /// Drop the LR.W reservation if a store of `len` bytes at `addr` overlaps the reserved word
+    fn invalidate_reservation(&mut self, addr: u32, len: u32) {
+        if let Some(reserved) = self.reservation {
+            if addr <= reserved.saturating_add(3) && addr.saturating_add(len - 1) >= reserved {
+                self.reservation = None;
+            }
+        }
+    }
+    /// Run an AMO, storing `op` of the word at the address in `rs1` and the value of `rs2`, and
+    /// putting the old word in `rd`
+    fn atomic(&mut self, rd: Register, rs1: Register, rs2: Register, op: impl FnOnce(u32, u32) -> u32) -> Result<(), ExecutionError> {
+        let addr = self.registers[rs1];
+        if addr & 0b11 != 0 {
+            return Err(ExecutionError::StoreAddressMisaligned(addr));
+        }
+        // Faults in either half are reported as store faults
+        let old = match self.read_word(addr) {
+            Err(ExecutionError::LoadAccessFault(addr)) => return Err(ExecutionError::StoreAccessFault(addr)),
+            result => result?,
+        };
+        self.store_word(op(old, self.registers[rs2]), addr)?;
+        self.set_reg(rd, old);
+        Ok(())
+    }
     /// Return a modifiable list of breakpoints
-    pub fn breakpoints(&mut self) -> &mut Vec<u32> {
+    pub fn breakpoints(&mut self) -> &mut Vec<Breakpoint> {
         &mut self.breakpoints
     }
-    pub fn get_reg(&mut self,reg: Register) -> u32 {
+    /// Return a modifiable list of watchpoints
+    pub fn watchpoints(&mut self) -> &mut Vec<Watchpoint> {
+        &mut self.watchpoints
+    }
+    pub fn get_pc(&self) -> u32 {
+        self.pc
+    }
+    pub fn get_reg(&self,reg: Register) -> u32 {
         self.registers[reg]
     }
+    pub fn get_freg(&self, reg: FRegister) -> u64 {
+        self.fregisters[reg.to_num()]
+    }
+    pub fn set_freg(&mut self, reg: FRegister, bits: u64) {
+        let index = reg.to_num();
+        self.journal.record(|| Change::FRegister(index, self.fregisters[index]));
+        self.fregisters[index] = bits;
+    }
+    /// Read a value in the given format from a floating point register. A single that isn't
+    /// properly NaN-boxed reads as the canonical NaN.
+    fn read_float(&self, format: Format, reg: FRegister) -> u64 {
+        let bits = self.fregisters[reg.to_num()];
+        if format == DOUBLE {
+            bits
+        } else if softfloat::is_boxed(bits) {
+            bits & 0xFFFF_FFFF
+        } else {
+            SINGLE.canonical_nan()
+        }
+    }
+    /// Write a value in the given format to a floating point register, NaN-boxing singles
+    fn write_float(&mut self, format: Format, reg: FRegister, bits: u64) {
+        self.set_freg(reg, if format == DOUBLE { bits } else { 0xFFFF_FFFF_0000_0000 | bits });
+    }
+    /// Run a floating point operation in the given rounding mode, or the one in `frm` for the
+    /// dynamic mode, and accrue the exceptions it raises into `fflags`
+    fn float_op<T>(&mut self, rm: RoundingMode, op: impl FnOnce(RoundingMode, &mut u32) -> T) -> Result<T, ExecutionError> {
+        let rm = match rm {
+            RoundingMode::DYN => RoundingMode::from_bits(self.csrs.frm)
+                .filter(|rm| *rm != RoundingMode::DYN)
+                .ok_or(ExecutionError::InvalidRoundingMode(self.csrs.frm))?,
+            rm => rm,
+        };
+        let mut flags = 0;
+        let result = op(rm, &mut flags);
+        if flags & !self.csrs.fflags != 0 {
+            self.journal.record(|| Change::Csrs(Box::new(self.csrs.clone())));
+            self.csrs.fflags |= flags;
+        }
+        Ok(result)
+    }
+    /// Read a CSR into `rd`, then write back whatever `update` makes of the old value
+    fn update_csr(&mut self, rd: Register, csr: u16, update: Option<impl FnOnce(u32) -> u32>) -> Result<(), ExecutionError> {
+        let old = self.csrs.read(csr, self.cycle)?;
+        if let Some(update) = update {
+            self.journal.record(|| Change::Csrs(Box::new(self.csrs.clone())));
+            self.csrs.write(csr, update(old))?;
+        }
+        self.set_reg(rd, old);
+        Ok(())
+    }
     pub fn set_reg(&mut self,reg: Register, value: u32) {
         let reg_num = reg.to_num();
         // Writes to the zero register are NOPs
         if reg_num == 0 {
             return;
         } else {
+            self.journal.record(|| Change::Register(reg_num - 1, self.registers[reg_num - 1]));
             self.registers[reg_num - 1] = value;
         }
     }
 
+    /// Bytes of host memory holding guest memory, which only counts the pages that were touched
+    pub fn memory_in_use(&self) -> usize {
+        self.memory.pages_in_use() * memory::PAGE_SIZE
+    }
+
+    /// Copy a region of RAM out. This doesn't go through loads, so it never sets off watchpoints,
+    /// and device registers are refused since reading them can change them.
+    pub fn read_memory(&self, range: RangeInclusive<u32>) -> Result<Vec<u8>, ExecutionError> {
+        let (start, end) = (*range.start(), *range.end());
+        if range.is_empty() {
+            return Ok(Vec::new());
+        }
+        // RAM is one block from address 0, so both ends being in it means all of it is
+        for addr in [start, end] {
+            if !matches!(self.bus.decode(addr, 1), Region::Ram) {
+                return Err(ExecutionError::LoadAccessFault(addr));
+            }
+        }
+        Ok(self.memory.slice(start, (end - start) as usize + 1).into_owned())
+    }
+
+    /// Addresses of the 8 instructions before the pc, the pc, and the 7 after it, for showing
+    /// the code around the pc. Addresses that would wrap around are left out.
+    fn context_addresses(&self) -> Vec<Option<u32>> {
+        let inst_len = |addr| {
+            self.read_instruction_bytes(addr)
+                .ok()
+                .and_then(|bytes| Instruction::decode(&bytes).ok())
+                .map_or(4, |inst| inst.len())
+        };
+        // Instructions can be 2 or 4 bytes long, so there's no telling where the ones before the
+        // pc start. Decoding forward from further back lines up with the pc for nearly all real
+        // code, and if it doesn't, assume they are all 4 bytes.
+        let mut before = Vec::new();
+        let mut addr = self.pc.saturating_sub(32);
+        while addr < self.pc {
+            before.push(addr);
+            addr += inst_len(addr);
+        }
+        if addr != self.pc {
+            before = (1 ..= 8).rev().filter_map(|n| self.pc.checked_sub(n * 4)).collect();
+        }
+        let mut context: Vec<Option<u32>> = vec![None; 8usize.saturating_sub(before.len())];
+        context.extend(before.iter().skip(before.len().saturating_sub(8)).map(|&addr| Some(addr)));
+
+        let mut addr = Some(self.pc);
+        for _ in 0 .. 8 {
+            context.push(addr);
+            addr = addr.and_then(|a| a.checked_add(inst_len(a)));
+        }
+        context
+    }
+
     // These 4 functions could probably be more modular ...
-    pub fn read_instruction_bytes(&self, addr: u32) -> Result<&[u8], ExecutionError> {
-        // Error out if the address is not aligned on a 32-bit boundary
-        if addr & 0b11 != 0 {
+    pub fn read_instruction_bytes(&self, addr: u32) -> Result<Cow<'_, [u8]>, ExecutionError> {
+        // Error out if the address is not aligned on a 16-bit boundary, compressed instructions
+        // mean that 32-bit instructions only need 16-bit alignment too. Without them it has to
+        // be a 32-bit boundary.
+        let alignment = if self.isa.has(Extension::C) { 0b1 } else { 0b11 };
+        if addr & alignment != 0 {
             Err(ExecutionError::InstructionAddressMisaligned(addr))
         // If the memory top is zero then assume we are using the full 4GB address space as memory
-        } else if self.memory_top == 0 || addr.overflowing_add(4).0 <= self.memory_top {
-            Ok(&self.memory[addr as usize .. addr as usize + 4])
+        } else if self.memory_top == 0 || addr.saturating_add(2) <= self.memory_top {
+            // Hand back up to 4 bytes, a compressed instruction right at the top of memory only
+            // has 2. Decoding checks that there are enough for the instruction that is there.
+            let mut end = (addr as usize + 4).min(self.memory.size());
+            if self.memory_top != 0 {
+                end = end.min(self.memory_top as usize);
+            }
+            Ok(self.memory.slice(addr, end - addr as usize))
         } else {
             Err(ExecutionError::InstructionAccessFault(addr))
         }
     }
     pub fn read_byte(&self, addr: u32) -> Result<i8, ExecutionError> {
-        // Check to see if the addr points to a memory mapped device
-        // The device space starts with 0xF
-        if addr >> 28 == 0xF {
-            let device_addr = addr & 0xFFFFFFF;
-            for device in self.devices.iter() {
-                if device.memory_range().contains(&device_addr) {
-                    return Ok(device.read_byte(device_addr).map_err(|e| ExecutionError::DeviceError(e))?)
-                }
-            }
-            Err(ExecutionError::LoadAccessFault(addr))
-        } else if addr < self.memory_top || self.memory_top == 0 {
-            Ok(self.memory[addr as usize] as i8)
-        } else {
-            Err(ExecutionError::LoadAccessFault(addr))
-        }
+        let value = match self.bus.decode(addr, 1) {
+            Region::Ram => self.memory.read_byte(addr) as i8,
+            Region::Device(device, offset) => device.read_byte(offset).map_err(ExecutionError::DeviceError)?,
+            Region::Unmapped => return Err(ExecutionError::LoadAccessFault(addr)),
+        };
+        self.watch_memory(addr, 1, false, || WatchEvent::Read { addr, value: value as u8 as u32 });
+        Ok(value)
     }
     pub fn read_word(&self, addr: u32) -> Result<u32, ExecutionError> {
-        if addr >> 28 == 0xF {
-            let device_addr = addr & 0xFFFFFFF;
-            for device in self.devices.iter() {
-                if device.memory_range().contains(&device_addr) {
-                    return Ok(device.read_word(device_addr).map_err(|e| ExecutionError::DeviceError(e))? as u32)
-                }
-            }
-            Err(ExecutionError::LoadAccessFault(addr))
-        } else if addr.saturating_add(4) <= self.memory_top || self.memory_top == 0 {
-            Ok(self.memory[addr as usize] as u32
-                + ((self.memory[addr.overflowing_add(1).0 as usize] as u32) << 8)
-                + ((self.memory[addr.overflowing_add(2).0 as usize] as u32) << 16)
-                + ((self.memory[addr.overflowing_add(3).0 as usize] as u32) << 24))
-        } else {
-            Err(ExecutionError::LoadAccessFault(addr))
-        }
+        let value = match self.bus.decode(addr, 4) {
+            Region::Ram => {
+                let mut bytes = [0; 4];
+                self.memory.read(addr, &mut bytes);
+                u32::from_le_bytes(bytes)
+            }
+            Region::Device(device, offset) => device.read_word(offset).map_err(ExecutionError::DeviceError)? as u32,
+            Region::Unmapped => return Err(ExecutionError::LoadAccessFault(addr)),
+        };
+        self.watch_memory(addr, 4, false, || WatchEvent::Read { addr, value });
+        Ok(value)
     }
     pub fn read_halfword(&self, addr: u32) -> Result<i16, ExecutionError> {
-        if addr >> 28 == 0xF {
-            let device_addr = addr & 0xFFFFFFF;
-            for device in self.devices.iter() {
-                if device.memory_range().contains(&device_addr) {
-                    return Ok(device.read_halfword(device_addr).map_err(|e| ExecutionError::DeviceError(e))?)
-                }
+        let value = match self.bus.decode(addr, 2) {
+            Region::Ram => {
+                let mut bytes = [0; 2];
+                self.memory.read(addr, &mut bytes);
+                i16::from_le_bytes(bytes)
             }
-            Err(ExecutionError::LoadAccessFault(addr))
-        } else if addr.saturating_add(2) <= self.memory_top || self.memory_top == 0 {
-            Ok((self.memory[addr as usize] as u16
-                + ((self.memory[addr.overflowing_add(1).0 as usize] as u16) << 8)) as i16)
-        } else {
-            Err(ExecutionError::LoadAccessFault(addr))
+            Region::Device(device, offset) => device.read_halfword(offset).map_err(ExecutionError::DeviceError)?,
+            Region::Unmapped => return Err(ExecutionError::LoadAccessFault(addr)),
+        };
+        self.watch_memory(addr, 2, false, || WatchEvent::Read { addr, value: value as u16 as u32 });
+        Ok(value)
+    }
+    /// Note an access for `step` to stop on if it sets off a watchpoint. A write takes the place
+    /// of a read from the same instruction, so an AMO reports what it stored.
+    #[inline(always)]
+    fn watch_memory(&self, addr: u32, len: u32, is_write: bool, event: impl FnOnce() -> WatchEvent) {
+        // Checked here so that loads and stores cost nothing extra without any watchpoints
+        if !self.watchpoints.is_empty() {
+            self.watch_memory_slow(addr, len, is_write, event);
         }
     }
-    pub fn store_byte(&mut self, data: u8, addr: u32) -> Result<(), ExecutionError> {
-        if addr >> 28 == 0xF {
-            let device_addr = addr & 0xFFFFFFF;
-            for device in self.devices.iter_mut() {
-                if device.memory_range().contains(&device_addr) {
-                    return Ok(device.store_byte(device_addr,data).map_err(|e| ExecutionError::DeviceError(e))?)
-                }
+    #[cold]
+    #[inline(never)]
+    fn watch_memory_slow(&self, addr: u32, len: u32, is_write: bool, event: impl FnOnce() -> WatchEvent) {
+        if let Some(hit) = self.watch_hit.get() {
+            if !is_write || matches!(hit.event, WatchEvent::Write { .. }) {
+                return;
             }
-            Err(ExecutionError::LoadAccessFault(addr))
-        } else if addr < self.memory_top || self.memory_top == 0 {
-            self.memory[addr as usize] = data;
-            Ok(())
-        } else {
-            Err(ExecutionError::LoadAccessFault(addr))
+        }
+        if let Some(index) = self.watchpoints.iter().position(|w| w.matches(addr, len, is_write)) {
+            self.watch_hit.set(Some(WatchHit { index, pc: self.pc, event: event() }));
         }
     }
+    /// The first register watchpoint on a register that is different from `before`
+    fn watch_registers(&self, pc: u32, before: &[u32; 31]) -> Option<WatchHit> {
+        self.watchpoints.iter().enumerate().find_map(|(index, w)| match w.watch {
+            Watch::Register(reg) if before[reg] != self.registers[reg] => Some(WatchHit {
+                index,
+                pc,
+                event: WatchEvent::Register { reg, old: before[reg], new: self.registers[reg] },
+            }),
+            _ => None,
+        })
+    }
+    pub fn store_byte(&mut self, data: u8, addr: u32) -> Result<(), ExecutionError> {
+        self.store(addr, &[data], |device, offset| device.store_byte(offset, data))
+    }
     pub fn store_halfword(&mut self, data: u16, addr: u32) -> Result<(), ExecutionError> {
-        if addr >> 28 == 0xF {
-            let device_addr = addr & 0xFFFFFFF;
-            for device in self.devices.iter_mut() {
-                if device.memory_range().contains(&device_addr) {
-                    return Ok(device.store_halfword(device_addr,data).map_err(|e| ExecutionError::DeviceError(e))?)
-                }
-            }
-            Err(ExecutionError::LoadAccessFault(addr))
-        } else if addr.saturating_add(2) <= self.memory_top || self.memory_top == 0 {
-            self.memory[addr as usize] = data as u8;
-            self.memory[addr.overflowing_add(1).0 as usize] = (data >> 8) as u8;
-            Ok(())
-        } else {
-            Err(ExecutionError::LoadAccessFault(addr))
-        }
+        self.store(addr, &data.to_le_bytes(), |device, offset| device.store_halfword(offset, data))
     }
-
     pub fn store_word(&mut self, data: u32, addr: u32) -> Result<(), ExecutionError> {
-        let device_addr = addr & 0xFFFFFFF;
-        if addr >> 28 == 0xF {
-            for device in self.devices.iter_mut() {
-                if device.memory_range().contains(&device_addr) {
-                    return Ok(device.store_word(device_addr,data).map_err(|e| ExecutionError::DeviceError(e))?)
-                }
-            }
-            Err(ExecutionError::LoadAccessFault(addr))
-        } else if addr.saturating_add(4) <= self.memory_top || self.memory_top == 0 {
-            self.memory[addr as usize] = data as u8;
-            self.memory[addr.overflowing_add(1).0 as usize] = (data >> 8) as u8;
-            self.memory[addr.overflowing_add(2).0 as usize] = (data >> 16) as u8;
-            self.memory[addr.overflowing_add(3).0 as usize] = (data >> 24) as u8;
-            Ok(())
-        } else {
-            Err(ExecutionError::LoadAccessFault(addr))
+        self.store(addr, &data.to_le_bytes(), |device, offset| device.store_word(offset, data))
+    }
+    /// Store `bytes` to RAM, or hand the store to whichever device is at `addr`
+    fn store(&mut self, addr: u32, bytes: &[u8], to_device: impl FnOnce(&Device, u32) -> Result<(), Box<dyn std::error::Error>>) -> Result<(), ExecutionError> {
+        let len = bytes.len() as u32;
+        let value = |bytes: &[u8]| bytes.iter().rev().fold(0, |value, &byte| value << 8 | byte as u32);
+        match self.bus.decode(addr, len) {
+            Region::Ram => {
+                self.check_writable(addr,len)?;
+                self.watch_memory(addr, len, true, || {
+                    let old = self.memory.slice(addr, bytes.len());
+                    WatchEvent::Write { addr, old: Some(value(&old)), new: value(bytes) }
+                });
+                self.journal.record(|| Change::Memory { addr, len, old: value(&self.memory.slice(addr, bytes.len())) });
+                self.invalidate_reservation(addr,len);
+                self.icache.invalidate(addr,len);
+                self.memory.write(addr, bytes);
+                Ok(())
+            }
+            Region::Device(device, offset) => {
+                self.watch_memory(addr, len, true, || WatchEvent::Write { addr, old: None, new: value(bytes) });
+                to_device(device, offset).map_err(ExecutionError::DeviceError)
+            }
+            Region::Unmapped => Err(ExecutionError::StoreAccessFault(addr)),
         }
     }
     // Fetch, decode, and execute an instruction
     pub fn step(&mut self) -> Result<(), ExecutionError> {
-        use Operation::*;
         // First, check if we're at a breakpoint, and cannot pass over it
-        if self.breakpoints.contains(&self.pc) && !self.pass_breakpoint {
-            return Err(ExecutionError::Breakpoint(self.pc));
+        let passing_breakpoint = self.pass_breakpoint;
+        if !self.pass_breakpoint {
+            if let Some(index) = self.breakpoints.iter().position(|b| b.addr == self.pc) {
+                // Taken out while the condition is checked, since that needs the whole machine
+                let mut breakpoints = std::mem::take(&mut self.breakpoints);
+                let stop = breakpoints[index].hit(self);
+                self.breakpoints = breakpoints;
+                if stop {
+                    return Err(ExecutionError::Breakpoint(self.pc));
+                }
+            }
         } else {
             // Unset breakpoint pass
             self.pass_breakpoint = false;
 
         }
+        if self.watchpoints.is_empty() && !self.journal.is_on() {
+            self.execute_or_trap(passing_breakpoint)
+        } else {
+            self.step_recorded(passing_breakpoint)
+        }
+    }
+    /// Execute the instruction at the pc, logging what it changes to the journal, then stop if it
+    /// set off a watchpoint. This is kept out of `step` so that it costs nothing when neither is
+    /// in use.
+    #[inline(never)]
+    fn step_recorded(&mut self, passing_breakpoint: bool) -> Result<(), ExecutionError> {
+        // Anything a breakpoint condition read doesn't count
+        let pc = self.pc;
+        let registers = (!self.watchpoints.is_empty()).then_some(self.registers);
+        self.watch_hit.set(None);
+        self.journal.begin(pc, self.reservation);
+        if let Err(e) = self.execute_or_trap(passing_breakpoint) {
+            // Nothing to go back over if it didn't run
+            self.journal.cancel();
+            return Err(e);
+        }
+        self.journal.end();
+        let hit = self.watch_hit.take().or_else(|| registers.and_then(|registers| self.watch_registers(pc, &registers)));
+        if let Some(hit) = hit {
+            self.watchpoints[hit.index].hits += 1;
+            return Err(ExecutionError::Watchpoint(hit));
+        }
+        Ok(())
+    }
+    #[inline(always)]
+    fn execute_or_trap(&mut self, passing_breakpoint: bool) -> Result<(), ExecutionError> {
+        match self.execute(passing_breakpoint) {
+            // Exceptions go to the program's trap handler if it has installed one, otherwise they
+            // stop the simulation like any other error
+            Err(e) if self.csrs.has_trap_handler() => match self.exception(&e) {
+                Some((cause, tval)) => {
+                    self.trap(cause, tval);
+                    Ok(())
+                }
+                None => Err(e),
+            },
+            result => result,
+        }
+    }
+    /// Run backwards for up to `count` instructions, or as far as the history goes if it is None,
+    /// and return how many were undone. This stops early at a breakpoint whose condition holds,
+    /// or after undoing an instruction that set off a store or register watchpoint, and returns
+    /// that as the same error running forwards would have.
+    pub fn run_backwards(&mut self, count: Option<usize>) -> Result<usize, ExecutionError> {
+        self.memory_changes.clear();
+        let mut undone = 0;
+        while count.is_none_or(|count| undone < count) {
+            let Some(entry) = self.journal.pop() else { break };
+            let hit = self.undo(entry);
+            undone += 1;
+            self.cycle = self.cycle.saturating_sub(1);
+            // Running forwards again should go straight past a breakpoint here
+            let breakpoint = self.breakpoints.iter().find(|b| b.addr == self.pc);
+            self.pass_breakpoint = breakpoint.is_some();
+            if let Some(hit) = hit {
+                return Err(ExecutionError::Watchpoint(hit));
+            }
+            if breakpoint.is_some_and(|b| b.holds(self)) {
+                return Err(ExecutionError::Breakpoint(self.pc));
+            }
+        }
+        Ok(undone)
+    }
+    /// Put back everything an instruction changed, returning the first watchpoint the changes
+    /// would have set off
+    fn undo(&mut self, entry: Entry) -> Option<WatchHit> {
+        let mut hit = None;
+        for change in entry.changes {
+            let event = match change {
+                Change::Step { .. } => unreachable!("entries don't contain other steps"),
+                Change::Register(index, old) => {
+                    let new = std::mem::replace(&mut self.registers[index], old);
+                    let reg = Register::from_num(index as u32 + 1).unwrap();
+                    self.watchpoints.iter().position(|w| matches!(w.watch, Watch::Register(r) if r.to_num() == reg.to_num()))
+                        .map(|index| (index, WatchEvent::Register { reg, old, new }))
+                }
+                Change::FRegister(index, old) => {
+                    self.fregisters[index] = old;
+                    None
+                }
+                Change::Memory { addr, len, old } => {
+                    let new = self.memory.slice(addr, len as usize).iter().rev().fold(0, |value, &byte| value << 8 | byte as u32);
+                    self.memory.write(addr, &old.to_le_bytes()[..len as usize]);
+                    self.icache.invalidate(addr, len);
+                    self.memory_changes.push((addr, old));
+                    self.watchpoints.iter().position(|w| w.matches(addr, len, true))
+                        .map(|index| (index, WatchEvent::Write { addr, old: Some(old), new }))
+                }
+                Change::Csrs(csrs) => {
+                    self.csrs = *csrs;
+                    None
+                }
+            };
+            if let Some((index, event)) = event {
+                hit = hit.or(Some(WatchHit { index, pc: entry.pc, event }));
+            }
+        }
+        self.pc = entry.pc;
+        self.reservation = entry.reservation;
+        hit
+    }
+    /// The mcause and mtval for an error that is a RISC-V exception, or None for errors that are
+    /// only to do with the simulator
+    fn exception(&self, e: &ExecutionError) -> Option<(u32, u32)> {
+        use ExecutionError::*;
+        Some(match *e {
+            InstructionAddressMisaligned(addr) => (csr::CAUSE_MISALIGNED_FETCH, addr),
+            InstructionAccessFault(addr) => (csr::CAUSE_FETCH_ACCESS, addr),
+            LoadAddressMisaligned(addr) => (csr::CAUSE_MISALIGNED_LOAD, addr),
+            LoadAccessFault(addr) => (csr::CAUSE_LOAD_ACCESS, addr),
+            StoreAddressMisaligned(addr) => (csr::CAUSE_MISALIGNED_STORE, addr),
+            StoreAccessFault(addr) => (csr::CAUSE_STORE_ACCESS, addr),
+            // mtval holds the instruction itself
+            ParseError(_) | IllegalCsrAccess(_) | InvalidRoundingMode(_) => {
+                let bits = self.read_instruction_bytes(self.pc).map_or(0, |bytes| match *bytes {
+                    [first, second, ..] if is_compressed(first) => u16::from_le_bytes([first, second]) as u32,
+                    [_, _, _, _, ..] => bytes_to_u32(&bytes),
+                    _ => 0,
+                });
+                (csr::CAUSE_ILLEGAL_INSTRUCTION, bits)
+            }
+            _ => return None,
+        })
+    }
+    /// Take an exception at the current pc, jumping to the trap handler
+    fn trap(&mut self, cause: u32, tval: u32) {
+        self.journal.record(|| Change::Csrs(Box::new(self.csrs.clone())));
+        self.pc = self.csrs.trap(self.pc, cause, tval);
+    }
+    /// The operation at the pc and its length, decoded only the first time it is run.
+    /// Compressed instructions come back as the full size instruction they expand to, the only
+    /// difference is how far the pc moves and what gets saved as the return address
+    fn fetch(&mut self) -> Result<(Operation, u32), ExecutionError> {
+        if let Some((op, len)) = self.icache.get(self.pc) {
+            return Ok((op.clone(), len));
+        }
+        let inst = Instruction::decode_for(&self.read_instruction_bytes(self.pc)?, &self.isa)?;
+        let (op, len) = (inst.operation(), inst.len());
+        self.icache.insert(self.pc, op.clone(), len);
+        Ok((op, len))
+    }
+    fn execute(&mut self, passing_breakpoint: bool) -> Result<(), ExecutionError> {
+        use Operation::*;
         //Fetch and decode
-        let op = Operation::from_bytes(self.read_instruction_bytes(self.pc)?)?;
+        let (op, len) = self.fetch()?;
 
         // Branches and jumps will set this to false
         let mut increment_pc = true;
@@ -564,7 +1086,7 @@ impl Machine {
             // Control transfer instructions
             // Normal, unconditional jumps use x0 as the register
             JAL(rd, imm) => {
-                self.set_reg(rd, self.pc.overflowing_add(4).0);
+                self.set_reg(rd, self.pc.overflowing_add(len).0);
                 // Set the pc, clearing the last bit
                 self.pc = self.pc.overflowing_add(imm as u32).0 & (! 0x1); 
                 increment_pc = false;
@@ -575,9 +1097,10 @@ impl Machine {
                 if rs1 == Register::RA && self.registers[Register::RA] == 0 {
                     return Err(ExecutionError::FinishedExecution(self.registers[Register::A0] as u8))
                 }
-                self.set_reg(rd,self.pc.overflowing_add(4).0);
-                // Add imm to rs1 and zero out lowest bit
-                self.pc = self.registers[rs1].overflowing_add(imm as u32).0 & (!1);
+                // Add imm to rs1 and zero out lowest bit, before rd is written in case they're the same
+                let target = self.registers[rs1].overflowing_add(imm as u32).0 & (!1);
+                self.set_reg(rd,self.pc.overflowing_add(len).0);
+                self.pc = target;
                 increment_pc = false;
             }
 
@@ -662,27 +1185,72 @@ impl Machine {
 
 
             // Evironment call/syscall
+            // With a trap handler installed, ECALL and EBREAK go to it instead of the simulator
+            ECALL if self.csrs.has_trap_handler() => {
+                self.trap(csr::CAUSE_MACHINE_ECALL, 0);
+                increment_pc = false;
+            }
+            EBREAK if self.csrs.has_trap_handler() => {
+                self.trap(csr::CAUSE_BREAKPOINT, self.pc);
+                increment_pc = false;
+            }
             ECALL => {
                 /* Fun with system calls! I think this is technically a BIOS? */
                 // this should definitely be its own module I feel
                 // a7: syscall, a0-a2: arguments
-                match self.env.syscall(self.registers[Register::A7], self.registers[Register::A0],self.registers[Register::A1],self.registers[Register::A2], &mut self.memory) {
-                    Ok(result) => {self.set_reg(Register::A0, result as u32)},
-                    Err(e) => { return Err(e) }
+                let [a0, a1, a2, a7] = [Register::A0, Register::A1, Register::A2, Register::A7].map(|r| self.registers[r]);
+                // read() fills a buffer, which is checked first so that nothing is read if it
+                // can't all be stored
+                if a7 == 63 && a2 != 0 {
+                    self.check_storable(a1, a2)?;
                 }
-                
+                let mut read = Vec::new();
+                let result = self.env.syscall(a7, a0, a1, a2, &self.memory, &mut read)?;
+                // What was read is stored the same way the program's own stores are, so it
+                // respects read-only memory, sets off watchpoints and can be undone
+                for (i, chunk) in read.chunks(4).enumerate() {
+                    self.store(a1.wrapping_add(i as u32 * 4), chunk, |_, _| Ok(()))?;
+                }
+                self.set_reg(Register::A0, result as u32);
+
             }
 
             // Breakpoint for us
-            EBREAK => return Err(ExecutionError::Breakpoint(self.pc)),
+            // Once the debugger has been entered the EBREAK is stepped over like a NOP
+            EBREAK if !passing_breakpoint => return Err(ExecutionError::Breakpoint(self.pc)),
+            EBREAK => {}
+            MRET => {
+                self.journal.record(|| Change::Csrs(Box::new(self.csrs.clone())));
+                self.pc = self.csrs.mret();
+                increment_pc = false;
+            }
 
             // Does this actually need an opcode? It's the same as ADDI zero, zero, 0
             NOP => {}
 
-            // Generic performance hint, we don't need to store any information for them
-            // and they are effectively NOPs
-            // Same with FENCE
-            HINT | FENCE => {}
+            // There is only one hart and no cache to order memory around
+            FENCE => {}
+            // Stores already keep the decoded instructions up to date, but this is what
+            // FENCE.I is for
+            FENCEI => self.icache.flush(),
+
+            // Control and status registers. CSRRS and CSRRC don't write at all when the source is
+            // x0 or the immediate is 0, which is what lets read-only CSRs be read
+            CSRRW(rd, rs1, csr) => {
+                let value = self.registers[rs1];
+                self.update_csr(rd, csr, Some(|_| value))?
+            }
+            CSRRS(rd, rs1, csr) => {
+                let value = self.registers[rs1];
+                self.update_csr(rd, csr, (rs1 != Register::Zero).then_some(|old| old | value))?
+            }
+            CSRRC(rd, rs1, csr) => {
+                let value = self.registers[rs1];
+                self.update_csr(rd, csr, (rs1 != Register::Zero).then_some(|old| old & !value))?
+            }
+            CSRRWI(rd, uimm, csr) => self.update_csr(rd, csr, Some(|_| uimm as u32))?,
+            CSRRSI(rd, uimm, csr) => self.update_csr(rd, csr, (uimm != 0).then_some(|old| old | uimm as u32))?,
+            CSRRCI(rd, uimm, csr) => self.update_csr(rd, csr, (uimm != 0).then_some(|old| old & !(uimm as u32)))?,
 
             /* Multiplication / Division */ 
             MUL(rd, rs1, rs2) => {
@@ -761,11 +1329,234 @@ impl Machine {
                 )
             },
 
+            // Bit manipulation
+            SH1ADD(rd, rs1, rs2) => self.set_reg(rd, (self.registers[rs1] << 1).wrapping_add(self.registers[rs2])),
+            SH2ADD(rd, rs1, rs2) => self.set_reg(rd, (self.registers[rs1] << 2).wrapping_add(self.registers[rs2])),
+            SH3ADD(rd, rs1, rs2) => self.set_reg(rd, (self.registers[rs1] << 3).wrapping_add(self.registers[rs2])),
+            ANDN(rd, rs1, rs2) => self.set_reg(rd, self.registers[rs1] & !self.registers[rs2]),
+            ORN(rd, rs1, rs2) => self.set_reg(rd, self.registers[rs1] | !self.registers[rs2]),
+            XNOR(rd, rs1, rs2) => self.set_reg(rd, !(self.registers[rs1] ^ self.registers[rs2])),
+            CLZ(rd, rs1) => self.set_reg(rd, self.registers[rs1].leading_zeros()),
+            CTZ(rd, rs1) => self.set_reg(rd, self.registers[rs1].trailing_zeros()),
+            CPOP(rd, rs1) => self.set_reg(rd, self.registers[rs1].count_ones()),
+            MAX(rd, rs1, rs2) => self.set_reg(rd, (self.registers[rs1] as i32).max(self.registers[rs2] as i32) as u32),
+            MAXU(rd, rs1, rs2) => self.set_reg(rd, self.registers[rs1].max(self.registers[rs2])),
+            MIN(rd, rs1, rs2) => self.set_reg(rd, (self.registers[rs1] as i32).min(self.registers[rs2] as i32) as u32),
+            MINU(rd, rs1, rs2) => self.set_reg(rd, self.registers[rs1].min(self.registers[rs2])),
+            SEXTB(rd, rs1) => self.set_reg(rd, self.registers[rs1] as i8 as u32),
+            SEXTH(rd, rs1) => self.set_reg(rd, self.registers[rs1] as i16 as u32),
+            ZEXTH(rd, rs1) => self.set_reg(rd, self.registers[rs1] & 0xFFFF),
+            // Only the low 5 bits of the shift amount count
+            ROL(rd, rs1, rs2) => self.set_reg(rd, self.registers[rs1].rotate_left(self.registers[rs2] & 0x1F)),
+            ROR(rd, rs1, rs2) => self.set_reg(rd, self.registers[rs1].rotate_right(self.registers[rs2] & 0x1F)),
+            RORI(rd, rs1, shamt) => self.set_reg(rd, self.registers[rs1].rotate_right(shamt as u32)),
+            // Every non-zero byte becomes 0xFF
+            ORCB(rd, rs1) => {
+                let bytes = self.registers[rs1].to_le_bytes().map(|b| if b == 0 { 0 } else { 0xFF });
+                self.set_reg(rd, u32::from_le_bytes(bytes))
+            }
+            REV8(rd, rs1) => self.set_reg(rd, self.registers[rs1].swap_bytes()),
+            // The three carry-less multiplies are different 32-bit windows of the 64-bit product
+            CLMUL(rd, rs1, rs2) => self.set_reg(rd, carryless_multiply(self.registers[rs1], self.registers[rs2]) as u32),
+            CLMULH(rd, rs1, rs2) => {
+                self.set_reg(rd, (carryless_multiply(self.registers[rs1], self.registers[rs2]) >> 32) as u32)
+            }
+            CLMULR(rd, rs1, rs2) => {
+                self.set_reg(rd, (carryless_multiply(self.registers[rs1], self.registers[rs2]) >> 31) as u32)
+            }
+            BCLR(rd, rs1, rs2) => self.set_reg(rd, self.registers[rs1] & !(1 << (self.registers[rs2] & 0x1F))),
+            BCLRI(rd, rs1, shamt) => self.set_reg(rd, self.registers[rs1] & !(1 << shamt)),
+            BEXT(rd, rs1, rs2) => self.set_reg(rd, (self.registers[rs1] >> (self.registers[rs2] & 0x1F)) & 1),
+            BEXTI(rd, rs1, shamt) => self.set_reg(rd, (self.registers[rs1] >> shamt) & 1),
+            BINV(rd, rs1, rs2) => self.set_reg(rd, self.registers[rs1] ^ (1 << (self.registers[rs2] & 0x1F))),
+            BINVI(rd, rs1, shamt) => self.set_reg(rd, self.registers[rs1] ^ (1 << shamt)),
+            BSET(rd, rs1, rs2) => self.set_reg(rd, self.registers[rs1] | (1 << (self.registers[rs2] & 0x1F))),
+            BSETI(rd, rs1, shamt) => self.set_reg(rd, self.registers[rs1] | (1 << shamt)),
+
+            // Atomics. There is only one hart, so the only thing that can break a reservation is a
+            // store from this one
+            LRW(rd, rs1) => {
+                let addr = self.registers[rs1];
+                if addr & 0b11 != 0 {
+                    return Err(ExecutionError::LoadAddressMisaligned(addr));
+                }
+                let value = self.read_word(addr)?;
+                self.reservation = Some(addr);
+                self.set_reg(rd, value);
+            }
+            SCW(rd, rs1, rs2) => {
+                let addr = self.registers[rs1];
+                if addr & 0b11 != 0 {
+                    return Err(ExecutionError::StoreAddressMisaligned(addr));
+                }
+                // Success or failure, the reservation is used up
+                if self.reservation.take() == Some(addr) {
+                    self.store_word(self.registers[rs2], addr)?;
+                    self.set_reg(rd, 0);
+                } else {
+                    self.set_reg(rd, 1);
+                }
+            }
+            AMOSWAPW(rd, rs1, rs2) => self.atomic(rd, rs1, rs2, |_, value| value)?,
+            AMOADDW(rd, rs1, rs2) => self.atomic(rd, rs1, rs2, |old, value| old.wrapping_add(value))?,
+            AMOXORW(rd, rs1, rs2) => self.atomic(rd, rs1, rs2, |old, value| old ^ value)?,
+            AMOANDW(rd, rs1, rs2) => self.atomic(rd, rs1, rs2, |old, value| old & value)?,
+            AMOORW(rd, rs1, rs2) => self.atomic(rd, rs1, rs2, |old, value| old | value)?,
+            AMOMINW(rd, rs1, rs2) => self.atomic(rd, rs1, rs2, |old, value| (old as i32).min(value as i32) as u32)?,
+            AMOMAXW(rd, rs1, rs2) => self.atomic(rd, rs1, rs2, |old, value| (old as i32).max(value as i32) as u32)?,
+            AMOMINUW(rd, rs1, rs2) => self.atomic(rd, rs1, rs2, |old, value| old.min(value))?,
+            AMOMAXUW(rd, rs1, rs2) => self.atomic(rd, rs1, rs2, |old, value| old.max(value))?,
+
+            // Floating point. Loads, stores and moves copy the bits untouched, everything else
+            // treats a single that isn't NaN-boxed as the canonical NaN. Each single precision
+            // instruction shares its arm with the double precision one, with `format` telling them
+            // apart.
+            FLW(rd, rs1, imm) => {
+                let value = self.read_word(self.registers[rs1].overflowing_add_signed(imm).0)?;
+                self.write_float(SINGLE, rd, value as u64);
+            }
+            FSW(rs1, rs2, imm) => self.store_word(
+                self.fregisters[rs2.to_num()] as u32,
+                self.registers[rs1].overflowing_add_signed(imm).0,
+            )?,
+            // Doubles are moved as two word accesses, low word first
+            FLD(rd, rs1, imm) => {
+                let addr = self.registers[rs1].overflowing_add_signed(imm).0;
+                let low = self.read_word(addr)?;
+                let high = self.read_word(addr.overflowing_add(4).0)?;
+                self.write_float(DOUBLE, rd, ((high as u64) << 32) | low as u64);
+            }
+            FSD(rs1, rs2, imm) => {
+                let addr = self.registers[rs1].overflowing_add_signed(imm).0;
+                let value = self.fregisters[rs2.to_num()];
+                let high = addr.overflowing_add(4).0;
+                // Both halves are checked first, so that a fault on the second doesn't leave the
+                // first half stored
+                if matches!(self.bus.decode(addr, 4), Region::Unmapped)
+                    || matches!(self.bus.decode(high, 4), Region::Unmapped) {
+                    return Err(ExecutionError::StoreAccessFault(addr));
+                }
+                self.check_writable(addr, 8)?;
+                self.store_word(value as u32, addr)?;
+                self.store_word((value >> 32) as u32, high)?;
+            }
+            FMVXW(rd, rs1) => self.set_reg(rd, self.fregisters[rs1.to_num()] as u32),
+            FMVWX(rd, rs1) => self.write_float(SINGLE, rd, self.registers[rs1] as u64),
+
+            // The other three forms of FMA negate the product, the addend or both
+            FMADDS(rd, rs1, rs2, rs3, rm) | FMSUBS(rd, rs1, rs2, rs3, rm)
+            | FNMSUBS(rd, rs1, rs2, rs3, rm) | FNMADDS(rd, rs1, rs2, rs3, rm)
+            | FMADDD(rd, rs1, rs2, rs3, rm) | FMSUBD(rd, rs1, rs2, rs3, rm)
+            | FNMSUBD(rd, rs1, rs2, rs3, rm) | FNMADDD(rd, rs1, rs2, rs3, rm) => {
+                let format = float_format(&op);
+                let negate_product = matches!(op, FNMSUBS(..) | FNMADDS(..) | FNMSUBD(..) | FNMADDD(..)) as u64 * format.sign_bit();
+                let negate_addend = matches!(op, FMSUBS(..) | FNMADDS(..) | FMSUBD(..) | FNMADDD(..)) as u64 * format.sign_bit();
+                let (a, b, c) = (
+                    self.read_float(format, rs1) ^ negate_product,
+                    self.read_float(format, rs2),
+                    self.read_float(format, rs3) ^ negate_addend,
+                );
+                let result = self.float_op(rm, |rm, flags| format.fma(a, b, c, rm, flags))?;
+                self.write_float(format, rd, result);
+            }
+            FADDS(rd, rs1, rs2, rm) | FADDD(rd, rs1, rs2, rm) => {
+                let format = float_format(&op);
+                let (a, b) = (self.read_float(format, rs1), self.read_float(format, rs2));
+                let result = self.float_op(rm, |rm, flags| format.add(a, b, rm, flags))?;
+                self.write_float(format, rd, result);
+            }
+            FSUBS(rd, rs1, rs2, rm) | FSUBD(rd, rs1, rs2, rm) => {
+                let format = float_format(&op);
+                let (a, b) = (self.read_float(format, rs1), self.read_float(format, rs2));
+                let result = self.float_op(rm, |rm, flags| format.sub(a, b, rm, flags))?;
+                self.write_float(format, rd, result);
+            }
+            FMULS(rd, rs1, rs2, rm) | FMULD(rd, rs1, rs2, rm) => {
+                let format = float_format(&op);
+                let (a, b) = (self.read_float(format, rs1), self.read_float(format, rs2));
+                let result = self.float_op(rm, |rm, flags| format.mul(a, b, rm, flags))?;
+                self.write_float(format, rd, result);
+            }
+            FDIVS(rd, rs1, rs2, rm) | FDIVD(rd, rs1, rs2, rm) => {
+                let format = float_format(&op);
+                let (a, b) = (self.read_float(format, rs1), self.read_float(format, rs2));
+                let result = self.float_op(rm, |rm, flags| format.div(a, b, rm, flags))?;
+                self.write_float(format, rd, result);
+            }
+            FSQRTS(rd, rs1, rm) | FSQRTD(rd, rs1, rm) => {
+                let format = float_format(&op);
+                let a = self.read_float(format, rs1);
+                let result = self.float_op(rm, |rm, flags| format.sqrt(a, rm, flags))?;
+                self.write_float(format, rd, result);
+            }
+            // Sign injection never raises anything, and doesn't care what the value is
+            FSGNJS(rd, rs1, rs2) | FSGNJNS(rd, rs1, rs2) | FSGNJXS(rd, rs1, rs2)
+            | FSGNJD(rd, rs1, rs2) | FSGNJND(rd, rs1, rs2) | FSGNJXD(rd, rs1, rs2) => {
+                let format = float_format(&op);
+                let (a, b) = (self.read_float(format, rs1), self.read_float(format, rs2));
+                let sign = format.sign_bit();
+                let sign = match op {
+                    FSGNJS(..) | FSGNJD(..) => b & sign,
+                    FSGNJNS(..) | FSGNJND(..) => !b & sign,
+                    _ => (a ^ b) & sign,
+                };
+                self.write_float(format, rd, (a & !format.sign_bit()) | sign);
+            }
+            // These don't round, so the rounding mode they run in doesn't matter
+            FMINS(rd, rs1, rs2) | FMAXS(rd, rs1, rs2) | FMIND(rd, rs1, rs2) | FMAXD(rd, rs1, rs2) => {
+                let format = float_format(&op);
+                let (a, b) = (self.read_float(format, rs1), self.read_float(format, rs2));
+                let max = matches!(op, FMAXS(..) | FMAXD(..));
+                let result = self.float_op(RoundingMode::RNE, |_, flags| format.min_max(a, b, max, flags))?;
+                self.write_float(format, rd, result);
+            }
+            FEQS(rd, rs1, rs2) | FLTS(rd, rs1, rs2) | FLES(rd, rs1, rs2)
+            | FEQD(rd, rs1, rs2) | FLTD(rd, rs1, rs2) | FLED(rd, rs1, rs2) => {
+                let format = float_format(&op);
+                let (a, b) = (self.read_float(format, rs1), self.read_float(format, rs2));
+                // Only FEQ is a quiet comparison
+                let quiet = matches!(op, FEQS(..) | FEQD(..));
+                let ordering = self.float_op(RoundingMode::RNE, |_, flags| format.compare(a, b, quiet, flags))?;
+                let result = match op {
+                    FEQS(..) | FEQD(..) => ordering == Some(std::cmp::Ordering::Equal),
+                    FLTS(..) | FLTD(..) => ordering == Some(std::cmp::Ordering::Less),
+                    _ => matches!(ordering, Some(std::cmp::Ordering::Less | std::cmp::Ordering::Equal)),
+                };
+                self.set_reg(rd, result as u32);
+            }
+            FCLASSS(rd, rs1) | FCLASSD(rd, rs1) => {
+                let format = float_format(&op);
+                self.set_reg(rd, format.classify(self.read_float(format, rs1)));
+            }
+            FCVTWS(rd, rs1, rm) | FCVTWUS(rd, rs1, rm) | FCVTWD(rd, rs1, rm) | FCVTWUD(rd, rs1, rm) => {
+                let format = float_format(&op);
+                let a = self.read_float(format, rs1);
+                let signed = matches!(op, FCVTWS(..) | FCVTWD(..));
+                let result = self.float_op(rm, |rm, flags| format.float_to_int(a, signed, rm, flags))?;
+                self.set_reg(rd, result);
+            }
+            FCVTSW(rd, rs1, rm) | FCVTSWU(rd, rs1, rm) | FCVTDW(rd, rs1, rm) | FCVTDWU(rd, rs1, rm) => {
+                let format = float_format(&op);
+                let value = self.registers[rs1];
+                let signed = matches!(op, FCVTSW(..) | FCVTDW(..));
+                let result = self.float_op(rm, |rm, flags| format.int_to_float(value, signed, rm, flags))?;
+                self.write_float(format, rd, result);
+            }
+            FCVTSD(rd, rs1, rm) => {
+                let a = self.read_float(DOUBLE, rs1);
+                let result = self.float_op(rm, |rm, flags| SINGLE.convert(DOUBLE, a, rm, flags))?;
+                self.write_float(SINGLE, rd, result);
+            }
+            FCVTDS(rd, rs1, rm) => {
+                let a = self.read_float(SINGLE, rs1);
+                let result = self.float_op(rm, |rm, flags| DOUBLE.convert(SINGLE, a, rm, flags))?;
+                self.write_float(DOUBLE, rd, result);
+            }
 
         }
 
         if increment_pc {
-            self.pc = self.pc.overflowing_add(4).0;
+            self.pc = self.pc.overflowing_add(len).0;
         }
 
         
@@ -773,6 +1564,26 @@ impl Machine {
     }
 }
 
+/// Multiply without carries, XORing the partial products together instead of adding them
+fn carryless_multiply(a: u32, b: u32) -> u64 {
+    (0..32)
+        .filter(|i| b >> i & 1 != 0)
+        .fold(0, |product, i| product ^ ((a as u64) << i))
+}
+
+/// The format a floating point operation works in, double precision for the D extension and
+/// single precision for everything else
+fn float_format(op: &Operation) -> Format {
+    use Operation::*;
+    match op {
+        FMADDD(..) | FMSUBD(..) | FNMSUBD(..) | FNMADDD(..) | FADDD(..) | FSUBD(..) | FMULD(..)
+        | FDIVD(..) | FSQRTD(..) | FSGNJD(..) | FSGNJND(..) | FSGNJXD(..) | FMIND(..) | FMAXD(..)
+        | FEQD(..) | FLTD(..) | FLED(..) | FCLASSD(..) | FCVTWD(..) | FCVTWUD(..) | FCVTDW(..)
+        | FCVTDWU(..) => DOUBLE,
+        _ => SINGLE,
+    }
+}
+
 #[derive(Error, Debug, Educe)]
 // Educe is needed here as some nested error types are not comparable. Comparison is very important
 // here for matching and for tests in general, so we skip checking the uncomparable inner fields
@@ -784,12 +1595,20 @@ pub enum ExecutionError {
     ParseError(#[from] ParseError),
     #[error("Exception while loading value at address {0:#x}")]
     LoadAccessFault(u32),
+    #[error("Exception while storing value at address {0:#x}")]
+    StoreAccessFault(u32),
     #[error("Exception while loading instruction at address {0:#x}")]
     InstructionAccessFault(u32),
     #[error("Tried to read misaligned instruction at {0:#x}")]
     InstructionAddressMisaligned(u32),
+    #[error("Misaligned load from address {0:#x}")]
+    LoadAddressMisaligned(u32),
+    #[error("Misaligned store to address {0:#x}")]
+    StoreAddressMisaligned(u32),
     #[error("Breakpoint hit at address {0:#x}")]
     Breakpoint(u32),
+    #[error("{0}")]
+    Watchpoint(WatchHit),
     // This isn't really an error, but it is an exceptional condition
     // maybe could be represented a different way but this is easy
     #[error("Successfully finished execution")]
@@ -805,26 +1624,31 @@ pub enum ExecutionError {
     #[error("Problem with device: {0}")]
     DeviceError(#[educe(PartialEq(ignore))] Box<dyn std::error::Error>),
     #[error("Invalid system call: {0}")]
-    InvalidSyscall(u32)
+    InvalidSyscall(u32),
+    #[error("Illegal access to CSR {}", csr::describe(*.0))]
+    IllegalCsrAccess(u16),
+    #[error("Dynamic rounding with an invalid rounding mode {0} in frm")]
+    InvalidRoundingMode(u32),
 }
 
 #[cfg(test)]
 mod tests {
     // Note this useful idiom: importing names from outer (for mod tests) scope.
     use super::*;
+    use crate::breakpoint::Condition;
 
     #[test]
     fn test_write_u32() {
-        let mut machine = Machine::new(0,Some(0),8,vec![0;4].into_boxed_slice(),false,Vec::new());
+        let mut machine = Machine::new(0, Some(0), Memory::new(8), false, Bus::new(8, Vec::new()).unwrap());
         machine.store_word(0xBEE5AA11,0).unwrap();
-        for (&mem_value,test_value) in machine.memory.iter().zip([0x11,0xAA,0xE5,0xBE]) {
-            assert_eq!(mem_value,test_value);
+        for (addr,test_value) in (0..4).zip([0x11,0xAA,0xE5,0xBE]) {
+            assert_eq!(machine.memory.read_byte(addr),test_value);
         }
     }
 
     #[test]
     fn test_program_completion() {
-        let mut machine = Machine::new(0, Some(0), 32, vec![0; 32].into_boxed_slice(),false, Vec::new());
+        let mut machine = Machine::new(0, Some(0), Memory::new(32), false, Bus::new(32, Vec::new()).unwrap());
         let store_a0_42 = 0b0010011 | (Register::A0.to_num() << 7) | (42 << 20);
         let _ = machine.store_word(store_a0_42 as u32,0);
         // JALR to RA
@@ -835,12 +1659,473 @@ mod tests {
         assert_eq!(machine.step(),Err(ExecutionError::FinishedExecution(42)))
 
 
+    }
+    #[test]
+    fn test_ebreak() {
+        let mut machine = Machine::new(0, Some(0), Memory::new(32), false, Bus::new(32, Vec::new()).unwrap());
+        machine.store_word(u32::from_le_bytes(Operation::EBREAK.to_bytes()),0).unwrap();
+        machine.store_word(u32::from_le_bytes(Operation::JALR(Register::Zero,Register::RA,0).to_bytes()),4).unwrap();
+        assert_eq!(machine.step(),Err(ExecutionError::Breakpoint(0)));
+        // This is what the run loop does after dropping into the debugger
+        machine.pass_breakpoint = true;
+        assert_eq!(machine.step(),Ok(()));
+        assert_eq!(machine.step(),Err(ExecutionError::FinishedExecution(0)));
+    }
+    #[test]
+    fn test_conditional_breakpoint() {
+        let mut machine = Machine::new(0, Some(0), Memory::new(32), false, Bus::new(32, Vec::new()).unwrap());
+        // An endless loop counting up in a0
+        machine.store_word(u32::from_le_bytes(Operation::ADDI(Register::A0,Register::A0,1).to_bytes()),0).unwrap();
+        machine.store_word(u32::from_le_bytes(Operation::JAL(Register::Zero,-4).to_bytes()),4).unwrap();
+        let condition = Condition::parse("a0 >= 10", &SymbolTable::default()).unwrap();
+        machine.breakpoints().push(Breakpoint::new(0, Some(condition)));
+        machine.breakpoints()[0].ignore = 2;
+        let mut steps = 0;
+        while machine.step().is_ok() {
+            steps += 1;
+        }
+        // The condition first holds on the 11th time round, and two hits are ignored
+        assert_eq!(machine.get_reg(Register::A0), 12);
+        assert_eq!(steps, 24);
+        assert_eq!((machine.breakpoints()[0].hits, machine.breakpoints()[0].ignore), (3, 0));
+        // Passing over it doesn't count as another hit
+        machine.pass_breakpoint = true;
+        machine.step().unwrap();
+        assert_eq!(machine.breakpoints()[0].hits, 3);
+    }
+    #[test]
+    fn test_watchpoints() {
+        let mut machine = Machine::new(0, Some(0), Memory::new(64), false, Bus::new(64, Vec::new()).unwrap());
+        let program = [
+            Operation::ADDI(Register::A0,Register::Zero,5),
+            Operation::SB(Register::Zero,Register::A0,0x21),
+            Operation::LW(Register::A1,Register::Zero,0x20),
+            Operation::ADDI(Register::A2,Register::Zero,0),
+            Operation::ADDI(Register::A2,Register::Zero,1),
+        ];
+        for (i, op) in program.iter().enumerate() {
+            machine.store_word(u32::from_le_bytes(op.to_bytes()), i as u32 * 4).unwrap();
+        }
+        machine.store_word(0x1122_3344, 0x20).unwrap();
+        machine.watchpoints().push(Watchpoint::new(Watch::Memory { range: 0x21..=0x21, read: false, write: true }));
+        machine.watchpoints().push(Watchpoint::new(Watch::Memory { range: 0x23..=0x30, read: true, write: false }));
+        machine.watchpoints().push(Watchpoint::new(Watch::Register(Register::A2)));
+        // Poking memory from outside of an instruction doesn't count
+        machine.store_byte(0x33, 0x21).unwrap();
+
+        machine.step().unwrap();
+        assert_eq!(machine.step(), Err(ExecutionError::Watchpoint(WatchHit {
+            index: 0, pc: 4, event: WatchEvent::Write { addr: 0x21, old: Some(0x33), new: 5 },
+        })));
+        // The instruction has still run
+        assert_eq!(machine.pc, 8);
+        assert_eq!(machine.step(), Err(ExecutionError::Watchpoint(WatchHit {
+            index: 1, pc: 8, event: WatchEvent::Read { addr: 0x20, value: 0x1122_0544 },
+        })));
+        // Writing the value a register already has isn't a change
+        machine.step().unwrap();
+        assert_eq!(machine.step(), Err(ExecutionError::Watchpoint(WatchHit {
+            index: 2, pc: 16, event: WatchEvent::Register { reg: Register::A2, old: 0, new: 1 },
+        })));
+        assert_eq!(machine.watchpoints().iter().map(|w| w.hits).collect::<Vec<_>>(), vec![1, 1, 1]);
+    }
+    #[test]
+    fn test_run_backwards() {
+        let mut machine = Machine::new(0, Some(0), Memory::new(64), false, Bus::new(64, Vec::new()).unwrap());
+        let program = [
+            Operation::ADDI(Register::A0,Register::Zero,5),
+            Operation::SW(Register::Zero,Register::A0,0x20),
+            Operation::CSRRW(Register::A1,Register::A0,csr::MSCRATCH),
+            Operation::ADDI(Register::A0,Register::A0,1),
+            Operation::SW(Register::Zero,Register::A0,0x20),
+            Operation::ADDI(Register::A2,Register::Zero,7),
+        ];
+        for (i, op) in program.iter().enumerate() {
+            machine.store_word(u32::from_le_bytes(op.to_bytes()), i as u32 * 4).unwrap();
+        }
+        machine.set_history(10);
+        machine.record_history();
+        for _ in 0 .. 6 {
+            machine.step().unwrap();
+        }
+        assert_eq!(machine.run_backwards(Some(1)), Ok(1));
+        assert_eq!((machine.pc, machine.get_reg(Register::A2)), (20, 0));
+
+        machine.breakpoints().push(Breakpoint::new(8, None));
+        machine.watchpoints().push(Watchpoint::new(Watch::Memory { range: 0x20..=0x23, read: false, write: true }));
+        // Undoing a store sets off a watchpoint on it, with the values the way round they went forwards
+        assert_eq!(machine.run_backwards(None), Err(ExecutionError::Watchpoint(WatchHit {
+            index: 0, pc: 16, event: WatchEvent::Write { addr: 0x20, old: Some(5), new: 6 },
+        })));
+        assert_eq!(machine.read_word(0x20), Ok(5));
+        // What was put back is passed on to the web UI
+        assert_eq!(machine.memory_changes, vec![(0x20, 5)]);
+        assert_eq!(machine.run_backwards(None), Err(ExecutionError::Breakpoint(8)));
+        // Going forwards again shouldn't stop at the breakpoint that was just stepped back onto
+        assert!(machine.pass_breakpoint);
+        assert_eq!((machine.get_reg(Register::A0), machine.get_reg(Register::A1)), (5, 0));
+        assert!(machine.dump_state_txt().contains("mscratch:0x00000000\n"));
+        assert!(matches!(machine.run_backwards(None), Err(ExecutionError::Watchpoint(_))));
+        assert_eq!(machine.read_word(0x20), Ok(0));
+        // Running out of history isn't an error
+        assert_eq!(machine.run_backwards(None), Ok(1));
+        assert_eq!((machine.pc, machine.get_reg(Register::A0)), (0, 0));
+    }
+    #[test]
+    fn test_compressed() {
+        let mut memory = Memory::new(32);
+        // c.li a0, 12
+        memory.write(0, &0x4531u16.to_le_bytes());
+        // A full size instruction only needs to be 2-byte aligned
+        memory.write(2, &Operation::ADDI(Register::A0,Register::A0,6).to_bytes());
+        // c.jal 8
+        memory.write(6, &0x2021u16.to_le_bytes());
+        // c.jalr a0
+        memory.write(14, &0x9502u16.to_le_bytes());
+        let mut machine = Machine::new(0, Some(0), memory, false, Bus::new(32, Vec::new()).unwrap());
+        machine.step().unwrap();
+        assert_eq!((machine.pc, machine.get_reg(Register::A0)), (2, 12));
+        machine.step().unwrap();
+        assert_eq!((machine.pc, machine.get_reg(Register::A0)), (6, 18));
+        // Compressed jumps link to the next instruction, which is 2 bytes on
+        machine.step().unwrap();
+        assert_eq!((machine.pc, machine.get_reg(Register::RA)), (14, 8));
+        machine.step().unwrap();
+        assert_eq!((machine.pc, machine.get_reg(Register::RA)), (18, 16));
+        assert_eq!(machine.step(),Err(ExecutionError::ParseError(ParseError::InvalidInstruction(0))));
+    }
+    #[test]
+    fn test_csr_instructions() {
+        let mut machine = Machine::new(0, Some(0), Memory::new(32), false, Bus::new(32, Vec::new()).unwrap());
+        let program = [
+            Operation::CSRRS(Register::A0, Register::Zero, csr::MISA),
+            Operation::CSRRW(Register::A1, Register::T0, csr::MSCRATCH),
+            Operation::CSRRSI(Register::A2, 1, csr::MSCRATCH),
+            // Reading a counter is fine, writing one isn't
+            Operation::CSRRS(Register::A3, Register::Zero, csr::CYCLE),
+            Operation::CSRRW(Register::Zero, Register::A0, csr::CYCLE),
+        ];
+        for (i, op) in program.iter().enumerate() {
+            machine.store_word(u32::from_le_bytes(op.to_bytes()), i as u32 * 4).unwrap();
+        }
+        machine.set_reg(Register::T0, 0x100);
+        for _ in 0 .. 4 {
+            machine.step().unwrap();
+        }
+        assert_eq!(machine.get_reg(Register::A0), csr::misa_for("imafdc"));
+        assert_eq!(machine.get_reg(Register::A1), 0);
+        assert_eq!(machine.get_reg(Register::A2), 0x100);
+        assert_eq!(machine.step(), Err(ExecutionError::IllegalCsrAccess(csr::CYCLE)));
+        assert!(machine.dump_state_txt().contains("mscratch:0x00000101\n"));
+    }
+    #[test]
+    fn test_traps() {
+        let run = |source: &str| {
+            let image = crate::assembler::assemble(source, "trap.s").unwrap();
+            let mut memory = Memory::new(256);
+            image.write_to(&mut memory).unwrap();
+            let mut machine = Machine::new(0, None, memory, false, Bus::new(256, Vec::new()).unwrap());
+            let result = loop {
+                if let Err(e) = machine.step() {
+                    break e;
+                }
+            };
+            (machine, result)
+        };
+        // The handler counts the traps and adds up their causes, then skips the instruction
+        let (machine, result) = run(
+            "main:\n\
+                 la t0, handler\n\
+                 csrw mtvec, t0\n\
+                 csrsi mstatus, 8\n\
+                 ecall\n\
+                 .word 0\n\
+                 lui t2, 0x10\n\
+                 lw a0, 0(t2)\n\
+                 sw a0, 0(t2)\n\
+                 ebreak\n\
+                 csrr a0, mstatus\n\
+                 ret\n\
+             handler:\n\
+                 addi s1, s1, 1\n\
+                 csrr t1, mcause\n\
+                 add s2, s2, t1\n\
+                 csrr t1, mtval\n\
+                 add s3, s3, t1\n\
+                 csrr t1, mepc\n\
+                 addi t1, t1, 4\n\
+                 csrw mepc, t1\n\
+                 mret\n",
+        );
+        assert_eq!(machine.get_reg(Register::S1), 5);
+        assert_eq!(machine.get_reg(Register::S2), 11 + 2 + 5 + 7 + 3);
+        // The bad address twice, plus the pc of the EBREAK
+        assert_eq!(machine.get_reg(Register::S3), 0x10000 * 2 + 0x24);
+        // MRET turned interrupts back on
+        assert_eq!(result, ExecutionError::FinishedExecution((csr::MSTATUS_MPP | csr::MSTATUS_MIE | csr::MSTATUS_MPIE) as u8));
+        assert_eq!(machine.csrs.mstatus, csr::MSTATUS_MPP | csr::MSTATUS_MIE | csr::MSTATUS_MPIE);
+
+        // Without a handler the simulation stops like it always has
+        let (machine, result) = run("main:\n .word 0\n");
+        assert_eq!(result, ExecutionError::ParseError(ParseError::InvalidInstruction(0)));
+        assert_eq!(machine.pc, 0);
+    }
+    #[test]
+    fn test_isa() {
+        let mut memory = Memory::new(32);
+        memory.write(0, &Operation::MUL(Register::A0, Register::A0, Register::A0).to_bytes());
+        // c.li a0, 12
+        memory.write(4, &0x4531u16.to_le_bytes());
+        let mut machine = Machine::new(0, Some(0), memory, false, Bus::new(32, Vec::new()).unwrap());
+        machine.set_isa(Isa::parse("rv32i").unwrap());
+        assert_eq!(machine.csrs.misa, csr::misa_for("i"));
+        let mul = u32::from_le_bytes(Operation::MUL(Register::A0, Register::A0, Register::A0).to_bytes());
+        assert_eq!(machine.step(), Err(ExecutionError::ParseError(ParseError::MissingExtension(mul, Extension::M))));
+        machine.set_isa(Isa::parse("rv32im").unwrap());
+        machine.step().unwrap();
+        assert_eq!(machine.step(), Err(ExecutionError::ParseError(ParseError::MissingExtension(0x4531, Extension::C))));
+        // Without C the pc has to stay 4-byte aligned
+        machine.pc = 6;
+        assert_eq!(machine.step(), Err(ExecutionError::InstructionAddressMisaligned(6)));
+    }
+    #[test]
+    fn test_self_modifying_code() {
+        let image = crate::assembler::assemble(
+            "main:\n\
+                 li s0, 2\n\
+             patch:\n\
+                 addi a0, a0, 1\n\
+                 la t0, patch\n\
+                 la t1, replacement\n\
+                 lw t1, 0(t1)\n\
+                 sw t1, 0(t0)\n\
+                 addi s0, s0, -1\n\
+                 bnez s0, patch\n\
+                 fence.i\n\
+                 ret\n\
+             replacement:\n\
+                 addi a0, a0, 10\n",
+            "patch.s",
+        ).unwrap();
+        let mut memory = Memory::new(256);
+        image.write_to(&mut memory).unwrap();
+        let mut machine = Machine::new(0, None, memory, false, Bus::new(256, Vec::new()).unwrap());
+        // The first time round runs the original, the second what was stored over it
+        let result = loop {
+            if let Err(e) = machine.step() {
+                break e;
+            }
+        };
+        assert_eq!(result, ExecutionError::FinishedExecution(11));
+    }
+    #[test]
+    fn test_atomics() {
+        let image = crate::assembler::assemble(
+            "main:\n\
+                 li a1, 0x80\n\
+                 li t0, 5\n\
+                 sw t0, 0(a1)\n\
+                 lr.w a0, (a1)\n\
+                 addi t1, a0, 1\n\
+                 sc.w t2, t1, (a1)\n\
+                 sc.w t3, t1, (a1)\n\
+                 lr.w a0, (a1)\n\
+                 sb zero, 3(a1)\n\
+                 sc.w t4, t1, (a1)\n\
+                 li t5, -3\n\
+                 amomin.w s1, t5, (a1)\n\
+                 amomaxu.w s2, t0, (a1)\n\
+                 amoadd.w s3, t0, (a1)\n\
+                 lw s4, 0(a1)\n\
+                 addi a1, a1, 2\n\
+                 amoswap.w zero, t0, (a1)\n",
+            "atomics.s",
+        )
+        .unwrap();
+        let mut memory = Memory::new(256);
+        image.write_to(&mut memory).unwrap();
+        let mut machine = Machine::new(0, None, memory, false, Bus::new(256, Vec::new()).unwrap());
+        let result = loop {
+            if let Err(e) = machine.step() {
+                break e;
+            }
+        };
+        assert_eq!(result, ExecutionError::StoreAddressMisaligned(0x82));
+        // The first SC works, the second has no reservation, and the third lost it to the store
+        assert_eq!(machine.get_reg(Register::T2), 0);
+        assert_eq!(machine.get_reg(Register::T3), 1);
+        assert_eq!(machine.get_reg(Register::T4), 1);
+        assert_eq!(machine.get_reg(Register::S1), 6);
+        assert_eq!(machine.get_reg(Register::S2), -3i32 as u32);
+        assert_eq!(machine.get_reg(Register::S3), -3i32 as u32);
+        assert_eq!(machine.get_reg(Register::S4), 2);
+    }
+    #[test]
+    fn test_floating_point() {
+        let image = crate::assembler::assemble(
+            "main:\n\
+                 li a1, 0x80\n\
+                 li t0, 7\n\
+                 fcvt.d.w fa0, t0\n\
+                 li t0, 2\n\
+                 fcvt.d.w fa1, t0\n\
+                 fdiv.d fa2, fa0, fa1\n\
+                 fsd fa2, 0(a1)\n\
+                 fld fa3, 0(a1)\n\
+                 fcvt.s.d fa4, fa3\n\
+                 fadd.s fa5, fa3, fa4\n\
+                 fclass.s s1, fa5\n\
+                 lw s2, 4(a1)\n\
+                 fcvt.w.d a0, fa3, rtz\n\
+                 frflags s3\n\
+                 ret\n",
+            "float.s",
+        )
+        .unwrap();
+        let mut memory = Memory::new(256);
+        image.write_to(&mut memory).unwrap();
+        let mut machine = Machine::new(0, None, memory, false, Bus::new(256, Vec::new()).unwrap());
+        let result = loop {
+            if let Err(e) = machine.step() {
+                break e;
+            }
+        };
+        assert_eq!(result, ExecutionError::FinishedExecution(3));
+        assert_eq!(machine.get_freg(FRegister::FA3), 3.5f64.to_bits());
+        // Singles are NaN-boxed, and a double read as a single is the canonical NaN
+        assert_eq!(machine.get_freg(FRegister::FA4), 0xFFFF_FFFF_0000_0000 | 3.5f32.to_bits() as u64);
+        assert_eq!(machine.get_reg(Register::S1), 1 << 9);
+        assert_eq!(machine.get_reg(Register::S2), (3.5f64.to_bits() >> 32) as u32);
+        // Only the conversion to an integer was inexact
+        assert_eq!(machine.get_reg(Register::S3), softfloat::NX);
+    }
+    #[test]
+    fn test_setup_args() {
+        let mut machine = Machine::new(0, None, Memory::new(256), false, Bus::new(256, Vec::new()).unwrap());
+        let args = ["prog".to_string(), "hi".to_string()];
+        machine.setup_args(&args, &["A=1".to_string()]).unwrap();
+        let sp = machine.get_reg(Register::SP);
+        assert_eq!(sp % 16, 0);
+        assert_eq!(machine.read_word(sp), Ok(2));
+        assert_eq!(machine.get_reg(Register::A0), 2);
+        assert_eq!(machine.get_reg(Register::A1), sp + 4);
+        let read_string = |machine: &Machine, addr: u32| {
+            let mut s = String::new();
+            let mut addr = addr;
+            while machine.read_byte(addr).unwrap() != 0 {
+                s.push(machine.read_byte(addr).unwrap() as u8 as char);
+                addr += 1;
+            }
+            s
+        };
+        assert_eq!(read_string(&machine, machine.read_word(sp + 4).unwrap()), "prog");
+        assert_eq!(read_string(&machine, machine.read_word(sp + 8).unwrap()), "hi");
+        assert_eq!(machine.read_word(sp + 12), Ok(0));
+        assert_eq!(machine.get_reg(Register::A2), sp + 16);
+        assert_eq!(read_string(&machine, machine.read_word(sp + 16).unwrap()), "A=1");
+        assert_eq!(machine.read_word(sp + 20), Ok(0));
+    }
+    #[test]
+    fn test_startup_args() {
+        // The startup code for programs that take arguments, with a main that returns argc
+        let source = format!("{}\nmain:\n ret\n", include_str!("../programs/lib/libstartup_args.s"));
+        let image = crate::assembler::assemble(&source, "args.s").unwrap();
+        let mut memory = Memory::new(256);
+        image.write_to(&mut memory).unwrap();
+        let mut machine = Machine::new(image.entry.unwrap(), None, memory, false, Bus::new(256, Vec::new()).unwrap());
+        // Run with no arguments, which still passes the program's own name
+        machine.setup_args(&["args.mem".to_string()], &[]).unwrap();
+        let result = loop {
+            if let Err(e) = machine.step() {
+                break e;
+            }
+        };
+        assert_eq!(result, ExecutionError::FinishedExecution(1));
+    }
+    #[test]
+    fn test_read_syscall() {
+        let path = std::env::temp_dir().join(format!("remu-read-{}.txt", std::process::id()));
+        std::fs::write(&path, "WXYZ").unwrap();
+        let mut machine = Machine::new(0, Some(0), Memory::new(256), false, Bus::new(256, Vec::new()).unwrap());
+        for addr in [0, 4, 8] {
+            machine.store_word(u32::from_le_bytes(Operation::ECALL.to_bytes()), addr).unwrap();
+        }
+        for (i, byte) in path.to_str().unwrap().bytes().chain([0]).enumerate() {
+            machine.store_byte(byte, 0x80 + i as u32).unwrap();
+        }
+        machine.protect(0x40..=0x4f);
+        machine.set_reg(Register::A7, 56);
+        machine.set_reg(Register::A0, 0x80);
+        machine.step().unwrap();
+        // Reading into read-only memory faults like a store would, without reading anything
+        let fd = machine.get_reg(Register::A0);
+        machine.set_reg(Register::A7, 63);
+        machine.set_reg(Register::A1, 0x4e);
+        machine.set_reg(Register::A2, 4);
+        assert_eq!(machine.step(), Err(ExecutionError::StoreAccessFault(0x4e)));
+        assert_eq!(machine.read_word(0x4c), Ok(0));
+        machine.pc = 8;
+        machine.set_reg(Register::A0, fd);
+        machine.set_reg(Register::A1, 0x51);
+        machine.set_reg(Register::A2, 8);
+        machine.set_history(10);
+        machine.record_history();
+        machine.step().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        // Only the bytes that were read are written
+        assert_eq!(machine.get_reg(Register::A0), 4);
+        assert_eq!(machine.read_memory(0x50..=0x56), Ok(vec![0, b'W', b'X', b'Y', b'Z', 0, 0]));
+        // and stepping back over the read puts the buffer back the way it was
+        assert_eq!(machine.run_backwards(Some(1)), Ok(1));
+        assert_eq!((machine.pc, machine.get_reg(Register::A0)), (8, fd));
+        assert_eq!(machine.read_memory(0x50..=0x56), Ok(vec![0; 7]));
+    }
+    #[test]
+    fn test_read_memory() {
+        let mut machine = Machine::new(0, Some(0), Memory::new(64), false, Bus::new(64, Vec::new()).unwrap());
+        machine.store_word(0x1122_3344, 0x20).unwrap();
+        machine.watchpoints().push(Watchpoint::new(Watch::Memory { range: 0x20..=0x23, read: true, write: false }));
+        assert_eq!(machine.read_memory(0x21..=0x22), Ok(vec![0x33, 0x22]));
+        assert!(machine.watch_hit.get().is_none());
+        // Anything past RAM, devices included, is refused rather than read
+        assert_eq!(machine.read_memory(0x3e..=0x40), Err(ExecutionError::LoadAccessFault(0x40)));
+        assert_eq!(machine.read_memory(crate::bus::DEVICE_BASE..=crate::bus::DEVICE_BASE + 3),
+                   Err(ExecutionError::LoadAccessFault(crate::bus::DEVICE_BASE)));
+    }
+    #[test]
+    fn test_read_only_store() {
+        let mut machine = Machine::new(0, Some(0), Memory::new(32), false, Bus::new(32, Vec::new()).unwrap());
+        machine.protect(8..=15);
+        assert_eq!(machine.store_word(0xFFFFFFFF,4),Ok(()));
+        assert_eq!(machine.store_halfword(0xFFFF,7),Err(ExecutionError::StoreAccessFault(7)));
+        assert_eq!(machine.store_byte(0xFF,15),Err(ExecutionError::StoreAccessFault(15)));
+        assert_eq!(machine.store_word(0xFFFFFFFF,16),Ok(()));
+        assert_eq!(machine.read_word(8),Ok(0));
+    }
+    #[test]
+    fn test_fsd_fault() {
+        let mut machine = Machine::new(0, Some(0), Memory::new(32), false, Bus::new(32, Vec::new()).unwrap());
+        machine.store_word(u32::from_le_bytes(Operation::FSD(Register::A0, FRegister::FA0, 0).to_bytes()), 0).unwrap();
+        machine.set_freg(FRegister::FA0, 0x1122_3344_5566_7788);
+        machine.protect(12..=15);
+        // Neither half is stored when the upper one is read-only or past the end of RAM, and the
+        // fault is on the address of the whole double
+        for addr in [8, 28] {
+            machine.pc = 0;
+            machine.set_reg(Register::A0, addr);
+            assert_eq!(machine.step(), Err(ExecutionError::StoreAccessFault(addr)));
+            assert_eq!(machine.read_word(addr), Ok(0));
+        }
+        machine.pc = 0;
+        machine.set_reg(Register::A0, 4);
+        machine.step().unwrap();
+        assert_eq!((machine.read_word(4), machine.read_word(8)), (Ok(0x5566_7788), Ok(0x1122_3344)));
     }
     use proptest::prelude::*;
     proptest! {
         #[test]
         fn load_store_byte_asm(data: u8, s in 16u32..(1<<11)) {
-            let mut machine = Machine::new(0, Some(0), s+4, vec![0; s as usize+4].into_boxed_slice(),false, Vec::new());
+            let mut machine = Machine::new(0, Some(0), Memory::new(s as usize+4), false, Bus::new(s+4, Vec::new()).unwrap());
             let store_a0_42: u32 = 0b0010011 | ((Register::T1.to_num()as u32) << 7) | ((data as u32) << 20);
             let _ = machine.store_word(store_a0_42 as u32,0);
             println!("S: {}",s);
@@ -860,7 +2145,7 @@ mod tests {
             let _ = machine.store_word(ret as u32,12);
 
             for i in 0 .. 4 {
-                println!("{:?}",Operation::from_bytes(machine.read_instruction_bytes(i*4)?));
+                println!("{:?}",Operation::from_bytes(&machine.read_instruction_bytes(i*4)?));
             }
             machine.step().unwrap();
             machine.step().unwrap();
@@ -869,4 +2154,171 @@ mod tests {
         }
     }
 
+    /// Run a single instruction with `a` in A1 and `b` in A2, returning what it leaves in A0
+    fn run_asm(op: Operation, a: u32, b: u32) -> u32 {
+        let mut machine = Machine::new(0, Some(0), Memory::new(32), false, Bus::new(32, Vec::new()).unwrap());
+        machine.store_word(u32::from_le_bytes(op.to_bytes()), 0).unwrap();
+        machine.set_reg(Register::A1, a);
+        machine.set_reg(Register::A2, b);
+        machine.step().unwrap();
+        machine.get_reg(Register::A0)
+    }
+    fn clmul_reference(a: u32, b: u32) -> u64 {
+        let mut product = 0u64;
+        for i in 0 .. 32 {
+            if (a >> i) & 1 == 1 {
+                product ^= (b as u64) << i;
+            }
+        }
+        product
+    }
+    use Register::{A0, A1, A2};
+    proptest! {
+        #[test]
+        fn sh1add_asm(a: u32, b: u32) {
+            prop_assert_eq!(run_asm(Operation::SH1ADD(A0, A1, A2), a, b), a.wrapping_mul(2).wrapping_add(b));
+        }
+        #[test]
+        fn sh2add_asm(a: u32, b: u32) {
+            prop_assert_eq!(run_asm(Operation::SH2ADD(A0, A1, A2), a, b), a.wrapping_mul(4).wrapping_add(b));
+        }
+        #[test]
+        fn sh3add_asm(a: u32, b: u32) {
+            prop_assert_eq!(run_asm(Operation::SH3ADD(A0, A1, A2), a, b), a.wrapping_mul(8).wrapping_add(b));
+        }
+        #[test]
+        fn andn_asm(a: u32, b: u32) {
+            prop_assert_eq!(run_asm(Operation::ANDN(A0, A1, A2), a, b), a & !b);
+        }
+        #[test]
+        fn orn_asm(a: u32, b: u32) {
+            prop_assert_eq!(run_asm(Operation::ORN(A0, A1, A2), a, b), a | !b);
+        }
+        #[test]
+        fn xnor_asm(a: u32, b: u32) {
+            prop_assert_eq!(run_asm(Operation::XNOR(A0, A1, A2), a, b), !a ^ b);
+        }
+        #[test]
+        fn clz_asm(a: u32) {
+            let expected = (0 .. 32).take_while(|i| (a >> (31 - i)) & 1 == 0).count() as u32;
+            prop_assert_eq!(run_asm(Operation::CLZ(A0, A1), a, 0), expected);
+        }
+        #[test]
+        fn ctz_asm(a: u32) {
+            let expected = (0 .. 32).take_while(|i| (a >> i) & 1 == 0).count() as u32;
+            prop_assert_eq!(run_asm(Operation::CTZ(A0, A1), a, 0), expected);
+        }
+        #[test]
+        fn cpop_asm(a: u32) {
+            let expected = (0 .. 32).filter(|i| (a >> i) & 1 == 1).count() as u32;
+            prop_assert_eq!(run_asm(Operation::CPOP(A0, A1), a, 0), expected);
+        }
+        #[test]
+        fn max_asm(a: i32, b: i32) {
+            let expected = if a > b { a } else { b };
+            prop_assert_eq!(run_asm(Operation::MAX(A0, A1, A2), a as u32, b as u32), expected as u32);
+        }
+        #[test]
+        fn maxu_asm(a: u32, b: u32) {
+            let expected = if a > b { a } else { b };
+            prop_assert_eq!(run_asm(Operation::MAXU(A0, A1, A2), a, b), expected);
+        }
+        #[test]
+        fn min_asm(a: i32, b: i32) {
+            let expected = if a < b { a } else { b };
+            prop_assert_eq!(run_asm(Operation::MIN(A0, A1, A2), a as u32, b as u32), expected as u32);
+        }
+        #[test]
+        fn minu_asm(a: u32, b: u32) {
+            let expected = if a < b { a } else { b };
+            prop_assert_eq!(run_asm(Operation::MINU(A0, A1, A2), a, b), expected);
+        }
+        #[test]
+        fn sext_b_asm(a: u32) {
+            prop_assert_eq!(run_asm(Operation::SEXTB(A0, A1), a, 0), (((a << 24) as i32) >> 24) as u32);
+        }
+        #[test]
+        fn sext_h_asm(a: u32) {
+            prop_assert_eq!(run_asm(Operation::SEXTH(A0, A1), a, 0), (((a << 16) as i32) >> 16) as u32);
+        }
+        #[test]
+        fn zext_h_asm(a: u32) {
+            prop_assert_eq!(run_asm(Operation::ZEXTH(A0, A1), a, 0), (a << 16) >> 16);
+        }
+        #[test]
+        fn rol_asm(a: u32, b: u32) {
+            // Rotating is shifting a window over two copies of the value side by side
+            let doubled = ((a as u64) << 32) | a as u64;
+            let expected = (doubled >> (32 - b % 32)) as u32;
+            prop_assert_eq!(run_asm(Operation::ROL(A0, A1, A2), a, b), expected);
+        }
+        #[test]
+        fn ror_asm(a: u32, b: u32) {
+            let doubled = ((a as u64) << 32) | a as u64;
+            let expected = (doubled >> (b % 32)) as u32;
+            prop_assert_eq!(run_asm(Operation::ROR(A0, A1, A2), a, b), expected);
+        }
+        #[test]
+        fn rori_asm(a: u32, shamt in 0 .. 32) {
+            let doubled = ((a as u64) << 32) | a as u64;
+            let expected = (doubled >> shamt) as u32;
+            prop_assert_eq!(run_asm(Operation::RORI(A0, A1, shamt), a, 0), expected);
+        }
+        #[test]
+        fn orc_b_asm(a: u32) {
+            let expected = (0 .. 4)
+                .filter(|i| (a >> (i * 8)) & 0xFF != 0)
+                .fold(0, |result, i| result | (0xFF << (i * 8)));
+            prop_assert_eq!(run_asm(Operation::ORCB(A0, A1), a, 0), expected);
+        }
+        #[test]
+        fn rev8_asm(a: u32) {
+            prop_assert_eq!(run_asm(Operation::REV8(A0, A1), a, 0), u32::from_be_bytes(a.to_le_bytes()));
+        }
+        #[test]
+        fn clmul_asm(a: u32, b: u32) {
+            prop_assert_eq!(run_asm(Operation::CLMUL(A0, A1, A2), a, b), clmul_reference(a, b) as u32);
+        }
+        #[test]
+        fn clmulh_asm(a: u32, b: u32) {
+            prop_assert_eq!(run_asm(Operation::CLMULH(A0, A1, A2), a, b), (clmul_reference(a, b) >> 32) as u32);
+        }
+        #[test]
+        fn clmulr_asm(a: u32, b: u32) {
+            prop_assert_eq!(run_asm(Operation::CLMULR(A0, A1, A2), a, b), (clmul_reference(a, b) >> 31) as u32);
+        }
+        #[test]
+        fn bclr_asm(a: u32, b: u32) {
+            prop_assert_eq!(run_asm(Operation::BCLR(A0, A1, A2), a, b), a & !(1 << (b % 32)));
+        }
+        #[test]
+        fn bclri_asm(a: u32, shamt in 0 .. 32) {
+            prop_assert_eq!(run_asm(Operation::BCLRI(A0, A1, shamt), a, 0), a & !(1 << shamt));
+        }
+        #[test]
+        fn bext_asm(a: u32, b: u32) {
+            prop_assert_eq!(run_asm(Operation::BEXT(A0, A1, A2), a, b), (a >> (b % 32)) & 1);
+        }
+        #[test]
+        fn bexti_asm(a: u32, shamt in 0 .. 32) {
+            prop_assert_eq!(run_asm(Operation::BEXTI(A0, A1, shamt), a, 0), (a >> shamt) & 1);
+        }
+        #[test]
+        fn binv_asm(a: u32, b: u32) {
+            prop_assert_eq!(run_asm(Operation::BINV(A0, A1, A2), a, b), a ^ (1 << (b % 32)));
+        }
+        #[test]
+        fn binvi_asm(a: u32, shamt in 0 .. 32) {
+            prop_assert_eq!(run_asm(Operation::BINVI(A0, A1, shamt), a, 0), a ^ (1 << shamt));
+        }
+        #[test]
+        fn bset_asm(a: u32, b: u32) {
+            prop_assert_eq!(run_asm(Operation::BSET(A0, A1, A2), a, b), a | (1 << (b % 32)));
+        }
+        #[test]
+        fn bseti_asm(a: u32, shamt in 0 .. 32) {
+            prop_assert_eq!(run_asm(Operation::BSETI(A0, A1, shamt), a, 0), a | (1 << shamt));
+        }
+    }
+
 }
@@ -1,25 +1,44 @@
 mod api;
+mod assembler;
+mod breakpoint;
+mod bus;
+mod compressed;
+mod csr;
 mod debugger;
 mod decode;
 #[allow(dead_code)]
 mod devices;
+mod disasm;
 #[allow(dead_code)]
 mod environment;
+mod icache;
+mod isa;
+mod journal;
+mod loader;
 mod machine;
+mod memory;
 mod opcode;
 mod register;
+mod softfloat;
 #[allow(dead_code)]
 mod statetransfer;
+mod symbols;
 
+use bus::Bus;
 use machine::{ExecutionError, Machine};
 use devices::DeviceConfig;
+use isa::Isa;
+use loader::{ExportFormat, Format, Image, LoadSpec};
+use memory::Memory;
+use symbols::SymbolTable;
 
 use thiserror::Error;
 
-use clap::{Parser, ValueEnum};
+use clap::{Parser, Subcommand, ValueEnum};
 use std::fs::File;
-use std::io::{stdout, stdin, Write, BufReader, BufRead, IsTerminal};
+use std::io::{stdout, stdin, Write, IsTerminal};
 use std::num;
+use std::ops::RangeInclusive;
 use std::process::ExitCode;
 use std::thread;
 
@@ -36,18 +55,33 @@ enum DumpFmt {
 
 // TODO: memory top maybe could be a string? For 1GB? Etc
 #[derive(Parser)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, args_conflicts_with_subcommands = true)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     #[arg(short, long)]
     verbose: bool,
     #[arg(short, long, alias="silent")]
     quiet: bool,
     #[arg(long)]
     single_step: bool,
+    /// Program to run. Raw binaries can be given a load address with `FILE@ADDR`
     #[arg(value_name = "FILE", default_value = "program.mem")]
     filename: String,
-    #[arg(short = 'a', long, default_value_t = 0)]
-    starting_addr: u32,
+    /// Format of FILE, detected from its contents and extension by default
+    #[arg(long, value_enum, default_value_t = Format::Auto)]
+    format: Format,
+    /// Load another file into memory after FILE, can be repeated. With `@ADDR` the file is copied
+    /// in as raw bytes, and with `:ro` stores into it will fault
+    #[arg(long, value_name = "FILE[@ADDR][:ro]", value_parser = LoadSpec::parse)]
+    load: Vec<LoadSpec>,
+    /// Read extra symbols from an `nm`-style map file, for use in the debugger
+    #[arg(long, value_name = "MAP")]
+    symbols: Option<String>,
+    /// Address to start execution at. Defaults to the ELF entry point, or 0 for other formats
+    #[arg(short = 'a', long)]
+    starting_addr: Option<u32>,
     #[arg(short = 's', long)]
     stack_addr: Option<u32>,
     #[arg(short = 'm', long, default_value_t = 64*1024)]
@@ -55,6 +89,21 @@ struct Cli {
     #[arg(short = 'W')]
     web_ui: bool,
 
+    /// Extensions the simulated hart implements, like `rv32im` or `rv32gc_zba_zbb`. Instructions
+    /// from any others are illegal. Defaults to everything the simulator supports
+    #[arg(long, value_name = "ISA", value_parser = Isa::parse)]
+    isa: Option<Isa>,
+
+    /// How many instructions to remember so the debugger can step backwards over them, once it
+    /// or the web UI has been used. 0 turns this off
+    #[arg(long, value_name = "INSTRUCTIONS", default_value_t = 100_000)]
+    history: usize,
+
+    /// Decode every instruction each time it runs instead of caching them, to measure what the
+    /// cache saves
+    #[arg(long)]
+    no_icache: bool,
+
     /// Dump machine state to filename DUMP_TO when finished
     #[arg(short, long)]
     dump_to: Option<String>,
@@ -62,18 +111,55 @@ struct Cli {
     #[arg(long, value_enum, default_value_t = DumpFmt::Txt)]
     dump_fmt: DumpFmt,
 
+    /// Write the memory in RANGE (`START-END`, inclusive) to DUMP_MEM_TO when finished
+    #[arg(long, value_name = "RANGE", value_parser = loader::parse_range, requires = "dump_mem_to")]
+    dump_mem: Option<RangeInclusive<u32>>,
+
+    #[arg(long, value_name = "FILE", requires = "dump_mem")]
+    dump_mem_to: Option<String>,
+
+    /// Format for --dump-mem, picked from the extension of DUMP_MEM_TO by default
+    #[arg(long, value_enum)]
+    dump_mem_fmt: Option<ExportFormat>,
+
     /// Suppress exit code returned from emulated program
     #[arg(long)]
     suppress_status: bool,
 
+    /// Set an environment variable for the guest program, can be repeated
+    #[arg(long, value_name = "KEY=VALUE")]
+    env: Vec<String>,
+
+    /// Arguments passed to the guest program after `--`. FILE is always argv[0]
+    #[arg(last = true, value_name = "ARGS")]
+    args: Vec<String>,
+
     // These have to be parsed later, clap isnt smart enough to parse them
     /// Enable a specific device. Format is `--device NAME,opt=foo,opt2=foo2`.
     #[arg(long)]
     device: Vec<String>
 }
 
+#[derive(Subcommand)]
+enum Command {
+    /// Print an objdump-style listing of a program without running it
+    Disasm {
+        #[arg(value_name = "FILE")]
+        filename: String,
+        /// Format of FILE, detected from its contents and extension by default
+        #[arg(long, value_enum, default_value_t = Format::Auto)]
+        format: Format,
+        /// Read extra symbols from an `nm`-style map file
+        #[arg(long, value_name = "MAP")]
+        symbols: Option<String>,
+    },
+}
+
 fn main() -> std::io::Result<ExitCode> {
     let cli = Cli::parse();
+    if let Some(Command::Disasm { filename, format, symbols }) = cli.command {
+        return disassemble(&filename, format, symbols.as_deref());
+    }
     if cli.single_step && ! stdout().is_terminal() {
         println!("Cannot enter interactive mode when stdout is not a terminal.");
         return Ok(ExitCode::FAILURE);
@@ -146,27 +232,84 @@ fn run_simulator(cli: Cli, commands_rx: Option<CbReceiver<statetransfer::Control
         Some(f) => Some(File::create(f)?),
         None => None,
     };
+    // Work out the format before running, so a bad file name doesn't waste the run
+    let dump_mem_fmt = match (cli.dump_mem_fmt, &cli.dump_mem_to) {
+        (Some(format), _) => format,
+        (None, Some(path)) => match ExportFormat::from_path(path) {
+            Ok(format) => format,
+            Err(e) => {
+                eprintln!("Can't dump memory to {path}: {e}");
+                return Ok(ExitCode::FAILURE);
+            }
+        },
+        (None, None) => ExportFormat::Mem,
+    };
+    let dump_mem_to = match cli.dump_mem_to {
+        Some(ref f) => Some(File::create(f)?),
+        None => None,
+    };
 
-    let mut mmap = vec![0; capacity];
+    let mut mmap = Memory::new(capacity);
 
     // TODO: set up machine mmap in a real way instead of this jank
-    match parse_file(&mut mmap, &cli.filename) {
-        Ok(()) => {}
+    let (image, read_only) = match load_images(&cli.filename, cli.format, &cli.load, &mut mmap) {
+        Ok(loaded) => loaded,
         Err(ReadFileError::IoError(e)) => return Err(e),
         Err(e) => {
             eprintln!("{}", e);
             return Ok(ExitCode::FAILURE);
         }
+    };
+
+    let mut symbols = image.symbols;
+    if let Some(ref map) = cli.symbols {
+        match std::fs::read_to_string(map)
+            .map_err(ReadFileError::from)
+            .and_then(|text| SymbolTable::parse_map(&text, map)) {
+            Ok(mut extra) => symbols.append(&mut extra),
+            Err(e) => {
+                eprintln!("{}", e);
+                return Ok(ExitCode::FAILURE);
+            }
+        }
     }
 
+    // An explicit starting address always wins over the one in the file
+    let starting_addr = cli.starting_addr.or(image.entry).unwrap_or(0);
+
+    let bus = match Bus::new(cli.memory_top, devices) {
+        Ok(bus) => bus,
+        Err(e) => {
+            eprintln!("{}", e);
+            return Ok(ExitCode::FAILURE);
+        }
+    };
     let mut machine = Machine::new(
-        cli.starting_addr,
+        starting_addr,
         cli.stack_addr,
-        cli.memory_top,
-        mmap.into_boxed_slice(),
+        mmap,
         cli.verbose,
-        devices
+        bus
     );
+    machine.set_symbols(SymbolTable::new(symbols));
+    if let Some(isa) = cli.isa {
+        machine.set_isa(isa);
+    }
+    machine.set_history(cli.history);
+    machine.set_icache(!cli.no_icache);
+    for range in read_only {
+        machine.protect(range);
+    }
+    // argv[0] is always passed, so startup code can count on finding argc at the stack pointer
+    let mut argv = vec![cli.filename.clone()];
+    argv.extend(cli.args.iter().cloned());
+    if let Err(e) = machine.setup_args(&argv, &cli.env) {
+        // A stack with no room under it is only a problem for programs that were given arguments
+        if !cli.args.is_empty() || !cli.env.is_empty() {
+            eprintln!("Failed to set up program arguments: {}", e);
+            return Ok(ExitCode::FAILURE);
+        }
+    }
 
     // Run the machine to completion
     let result = machine.run(cli.single_step, &stdin, commands_rx,state_tx);
@@ -192,6 +335,12 @@ fn run_simulator(cli: Cli, commands_rx: Option<CbReceiver<statetransfer::Control
         // Note: if this fails it will override the status code spit out by the child program
         file.write_all(bytes.as_bytes())?;
     }
+    if let (Some(mut file), Some(range)) = (dump_mem_to, cli.dump_mem) {
+        match machine.read_memory(range.clone()) {
+            Ok(bytes) => file.write_all(&dump_mem_fmt.export(*range.start(), &bytes))?,
+            Err(e) => error_message = Some(format!("Failed to dump memory: {e}")),
+        }
+    }
 
     // Print the registers one last time
     // this is silly, but to explain: in normal mode, it prints a real-time display. in quiet mode, it prints nothing. in verbose mode, it dumps in a basic format at every instruction.
@@ -199,6 +348,8 @@ fn run_simulator(cli: Cli, commands_rx: Option<CbReceiver<statetransfer::Control
 
         environment::clear_term();
         environment::write_stdout(&machine.display_info());
+        environment::write_newline();
+        environment::write_stdout(&format!("Memory used: {} KiB", machine.memory_in_use() / 1024));
 
     }
 
@@ -218,35 +369,55 @@ fn run_simulator(cli: Cli, commands_rx: Option<CbReceiver<statetransfer::Control
     }
 }
 
+fn disassemble(filename: &str, format: Format, map: Option<&str>) -> std::io::Result<ExitCode> {
+    let result = loader::load(filename, format).and_then(|image| {
+        let mut symbols = image.symbols.clone();
+        if let Some(map) = map {
+            symbols.append(&mut SymbolTable::parse_map(&std::fs::read_to_string(map)?, map)?);
+        }
+        Ok(disasm::disassemble(&image, &SymbolTable::new(symbols)))
+    });
+    match result {
+        Ok(listing) => {
+            print!("{listing}");
+            Ok(ExitCode::SUCCESS)
+        }
+        Err(ReadFileError::IoError(e)) => Err(e),
+        Err(e) => {
+            eprintln!("{}", e);
+            Ok(ExitCode::FAILURE)
+        }
+    }
+}
+
+/// Load the program and then every `--load` file into memory in order, warning about any that
+/// overwrite each other. Returns the program image, with symbols from all the files, and the
+/// ranges that should be read-only.
+fn load_images(filename: &str, format: Format, extra_files: &[LoadSpec], mmap: &mut Memory) -> Result<(Image, Vec<RangeInclusive<u32>>), ReadFileError> {
+    let mut image = loader::load(filename, format)?;
+    image.write_to(mmap)?;
+    let mut loaded: Vec<(RangeInclusive<u32>, &str)> =
+        image.ranges().map(|range| (range, filename)).collect();
+    let mut read_only = Vec::new();
 
-fn parse_file(bytes: &mut Vec<u8>, filename: &str) -> Result<(), ReadFileError> {
-    let f = File::open(filename)?;
-    let reader = BufReader::new(f);
-    for line in reader.lines() {
-        let line = line?;
-        let (addr, data) = (&line)
-            .split_once(":")
-            .ok_or(ReadFileError::ParseError(line.clone()))?;
-        let addr: usize = u32::from_str_radix(addr.trim(), 16)? as usize;
-        // TODO: can have byte and word strings
-        // look for number of characters
-        let mut offset = 0;
-        let data_chunks = data.trim().split(' ');
-        for data in data_chunks {
-            let len = data.len();
-            let data = u32::from_str_radix(data.trim(), 16)?;
-            bytes[addr+offset] = data as u8;
-            if len >= 4 {
-                bytes[addr + offset + 1 as usize] = (data >> 8) as u8;
+    for spec in extra_files {
+        let mut extra = spec.load()?;
+        extra.write_to(mmap)?;
+        for range in extra.ranges() {
+            for (other, name) in loaded.iter() {
+                if range.start() <= other.end() && range.end() >= other.start() {
+                    eprintln!("Warning: {} at {:#x}-{:#x} overwrites {} at {:#x}-{:#x}",
+                              spec.path, range.start(), range.end(), name, other.start(), other.end());
+                }
             }
-            if len >= 8 {
-                bytes[addr + offset + 2 as usize] = (data >> 16) as u8;
-                bytes[addr + offset + 3 as usize] = (data >> 24) as u8;
+            if spec.read_only {
+                read_only.push(range.clone());
             }
-            offset += len/2;
+            loaded.push((range, spec.path.as_str()));
         }
+        image.symbols.append(&mut extra.symbols);
     }
-    Ok(())
+    Ok((image, read_only))
 }
 
 #[derive(Error, Debug)]
@@ -257,4 +428,17 @@ pub enum ReadFileError {
     IoError(#[from] std::io::Error),
     #[error("Failed to parse number: {0}")]
     ParseIntError(#[from] num::ParseIntError),
+    #[error("{file}:{line}: {message}")]
+    MalformedRecord { file: String, line: usize, message: String },
+    #[error("{file}:{line}:{column}: {message}")]
+    MemSyntax { file: String, line: usize, column: usize, message: String },
+    #[error("{file}:{line}:{column}: write to {addr:#x} overlaps data from line {previous_line}")]
+    OverlappingWrite { file: String, line: usize, column: usize, addr: u32, previous_line: usize },
+    #[error("{file}:{line}: {message}")]
+    AsmSyntax { file: String, line: usize, message: String },
+    #[error("Invalid ELF file: {0}")]
+    InvalidElf(String),
+    #[error("{len} bytes at address {addr:#x} do not fit in {memory_size:#x} bytes of memory, \
+             the program may have been built for a different --memory-top")]
+    AddressOutOfRange { addr: u32, len: usize, memory_size: usize },
 }
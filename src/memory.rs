@@ -0,0 +1,170 @@
+//! Guest memory, split into 4 KiB pages that are only allocated once something is written to
+//! them. Untouched memory reads as zero, so the whole 32-bit address space can be given to a
+//! program without the host having to find 4 GiB for it.
+
+use std::borrow::Cow;
+
+pub const PAGE_SIZE: usize = 4096;
+
+type Page = Box<[u8; PAGE_SIZE]>;
+
+static ZERO_PAGE: [u8; PAGE_SIZE] = [0; PAGE_SIZE];
+
+pub struct Memory {
+    pages: Vec<Option<Page>>,
+    size: usize,
+}
+
+impl Memory {
+    /// Memory covering addresses `0..size`. Accesses past the end panic like slice indexing
+    /// does, so the machine has to check its bounds first.
+    pub fn new(size: usize) -> Self {
+        Memory {
+            pages: std::iter::repeat_with(|| None).take(size.div_ceil(PAGE_SIZE)).collect(),
+            size,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    fn check(&self, addr: u32, len: usize) {
+        assert!(addr as usize + len <= self.size, "{len} bytes at {addr:#x} are outside of memory");
+    }
+
+    fn page_mut(&mut self, addr: u32) -> &mut [u8; PAGE_SIZE] {
+        self.pages[addr as usize / PAGE_SIZE].get_or_insert_with(|| Box::new([0; PAGE_SIZE]))
+    }
+
+    pub fn read_byte(&self, addr: u32) -> u8 {
+        self.check(addr, 1);
+        match self.pages[addr as usize / PAGE_SIZE] {
+            Some(ref page) => page[addr as usize % PAGE_SIZE],
+            None => 0,
+        }
+    }
+
+    pub fn write_byte(&mut self, addr: u32, value: u8) {
+        self.check(addr, 1);
+        self.page_mut(addr)[addr as usize % PAGE_SIZE] = value;
+    }
+
+    /// `len` bytes starting at `addr`. This only copies if they cross into another page.
+    pub fn slice(&self, addr: u32, len: usize) -> Cow<'_, [u8]> {
+        self.check(addr, len);
+        let offset = addr as usize % PAGE_SIZE;
+        if offset + len > PAGE_SIZE {
+            let mut buf = vec![0; len];
+            self.read(addr, &mut buf);
+            return Cow::Owned(buf);
+        }
+        let page = match self.pages[addr as usize / PAGE_SIZE] {
+            Some(ref page) => &page[..],
+            None => &ZERO_PAGE[..],
+        };
+        Cow::Borrowed(&page[offset..offset + len])
+    }
+
+    /// Fill `buf` from memory starting at `addr`
+    pub fn read(&self, addr: u32, buf: &mut [u8]) {
+        self.check(addr, buf.len());
+        let mut addr = addr as usize;
+        let mut buf = buf;
+        while !buf.is_empty() {
+            let offset = addr % PAGE_SIZE;
+            let len = buf.len().min(PAGE_SIZE - offset);
+            let (chunk, rest) = buf.split_at_mut(len);
+            match self.pages[addr / PAGE_SIZE] {
+                Some(ref page) => chunk.copy_from_slice(&page[offset..offset + len]),
+                None => chunk.fill(0),
+            }
+            addr += len;
+            buf = rest;
+        }
+    }
+
+    /// Copy `data` into memory starting at `addr`
+    pub fn write(&mut self, addr: u32, data: &[u8]) {
+        self.check(addr, data.len());
+        let mut addr = addr as usize;
+        let mut data = data;
+        while !data.is_empty() {
+            let offset = addr % PAGE_SIZE;
+            let len = data.len().min(PAGE_SIZE - offset);
+            self.page_mut(addr as u32)[offset..offset + len].copy_from_slice(&data[..len]);
+            addr += len;
+            data = &data[len..];
+        }
+    }
+
+    /// Zero `len` bytes starting at `addr`. Pages that were never written already read as zero,
+    /// so they are left unallocated, and pages that are zeroed completely are freed.
+    pub fn zero(&mut self, addr: u32, len: usize) {
+        self.check(addr, len);
+        let mut addr = addr as usize;
+        let end = addr + len;
+        while addr < end {
+            let offset = addr % PAGE_SIZE;
+            let len = (end - addr).min(PAGE_SIZE - offset);
+            let page = &mut self.pages[addr / PAGE_SIZE];
+            if len == PAGE_SIZE {
+                *page = None;
+            } else if let Some(page) = page {
+                page[offset..offset + len].fill(0);
+            }
+            addr += len;
+        }
+    }
+
+    /// The number of pages that have actually been allocated
+    pub fn pages_in_use(&self) -> usize {
+        self.pages.iter().filter(|page| page.is_some()).count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pages() {
+        let mut memory = Memory::new(1 << 32);
+        assert_eq!(memory.pages_in_use(), 0);
+        assert_eq!(memory.read_byte(0xDEAD_BEEF), 0);
+        assert_eq!(&memory.slice(0x1000, 4)[..], &[0; 4]);
+        // Reading doesn't allocate anything
+        assert_eq!(memory.pages_in_use(), 0);
+
+        memory.write_byte(0xFFFF_FFFF, 0xAB);
+        assert_eq!(memory.read_byte(0xFFFF_FFFF), 0xAB);
+        // A write across a page boundary touches both pages
+        memory.write(0x1FFE, &[1, 2, 3, 4]);
+        assert_eq!(memory.pages_in_use(), 3);
+        assert!(matches!(memory.slice(0x1FFE, 4), Cow::Owned(ref bytes) if bytes == &[1, 2, 3, 4]));
+        assert!(matches!(memory.slice(0x2000, 2), Cow::Borrowed(&[3, 4])));
+        let mut buf = [0xFF; PAGE_SIZE + 8];
+        memory.read(0x1FFC, &mut buf);
+        assert_eq!(&buf[..8], &[0, 0, 1, 2, 3, 4, 0, 0]);
+    }
+
+    #[test]
+    fn test_zero() {
+        let mut memory = Memory::new(4 * PAGE_SIZE);
+        memory.write(PAGE_SIZE as u32 - 2, &[1; PAGE_SIZE + 4]);
+        memory.zero(PAGE_SIZE as u32 - 1, PAGE_SIZE + 2);
+        assert_eq!(&memory.slice(PAGE_SIZE as u32 - 2, 4)[..], &[1, 0, 0, 0]);
+        assert_eq!(memory.read_byte(2 * PAGE_SIZE as u32), 0);
+        assert_eq!(memory.read_byte(2 * PAGE_SIZE as u32 + 1), 1);
+        // The page that was cleared completely is freed, and untouched pages stay unallocated
+        assert_eq!(memory.pages_in_use(), 2);
+        memory.zero(0, 4 * PAGE_SIZE);
+        assert_eq!(memory.pages_in_use(), 0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_out_of_range() {
+        Memory::new(32).read_byte(32);
+    }
+}
@@ -1,5 +1,10 @@
+use crate::compressed::{is_compressed, CompressedOp};
+use crate::csr;
 use crate::decode::{bytes_to_u32, InstructionType, ParseError, bitrange};
-use crate::register::Register;
+use crate::isa::{Extension, Isa};
+use crate::register::{FRegister, Register};
+use crate::softfloat::RoundingMode;
+use crate::symbols::SymbolTable;
 
 use std::fmt;
 
@@ -15,7 +20,7 @@ pub fn sign_extend(num: u32, bits: u8) -> Immediate {
     res
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Operation {
     // Immediate, register, register instructions
     // RD is first
@@ -77,12 +82,23 @@ pub enum Operation {
     // Breakpoint for us
     EBREAK,
 
+    // Return from a machine-mode trap handler
+    MRET,
+
     // Fence is treated as a NOP
     FENCE,
 
-    // Generic performance hint, we don't need to store any information for them
-    // and they are effectively NOPs
-    HINT,
+    // Instruction fence, which throws away any predecoded instructions
+    FENCEI,
+
+    // Control and status register instructions, RD first, then the source register or 5-bit
+    // immediate, then the CSR number
+    CSRRW(Register, Register, u16),
+    CSRRS(Register, Register, u16),
+    CSRRC(Register, Register, u16),
+    CSRRWI(Register, Immediate, u16),
+    CSRRSI(Register, Immediate, u16),
+    CSRRCI(Register, Immediate, u16),
 
     // Multiplication and division extensions
 
@@ -94,8 +110,118 @@ pub enum Operation {
     DIVU(Register, Register, Register),
     REM(Register, Register, Register),
     REMU(Register, Register, Register),
-    
-       
+
+    // Bit manipulation extensions. The register forms go RD, SRC1, SRC2 like the base
+    // instructions, the immediate forms take a shift amount or bit number, and the unary ones
+    // only have RD and SRC1
+    // Zba, address generation
+    SH1ADD(Register, Register, Register),
+    SH2ADD(Register, Register, Register),
+    SH3ADD(Register, Register, Register),
+    // Zbb, basic bit manipulation
+    ANDN(Register, Register, Register),
+    ORN(Register, Register, Register),
+    XNOR(Register, Register, Register),
+    CLZ(Register, Register),
+    CTZ(Register, Register),
+    CPOP(Register, Register),
+    MAX(Register, Register, Register),
+    MAXU(Register, Register, Register),
+    MIN(Register, Register, Register),
+    MINU(Register, Register, Register),
+    SEXTB(Register, Register),
+    SEXTH(Register, Register),
+    ZEXTH(Register, Register),
+    ROL(Register, Register, Register),
+    ROR(Register, Register, Register),
+    RORI(Register, Register, Immediate),
+    ORCB(Register, Register),
+    REV8(Register, Register),
+    // Zbc, carry-less multiplication
+    CLMUL(Register, Register, Register),
+    CLMULH(Register, Register, Register),
+    CLMULR(Register, Register, Register),
+    // Zbs, single bit instructions
+    BCLR(Register, Register, Register),
+    BCLRI(Register, Register, Immediate),
+    BEXT(Register, Register, Register),
+    BEXTI(Register, Register, Immediate),
+    BINV(Register, Register, Register),
+    BINVI(Register, Register, Immediate),
+    BSET(Register, Register, Register),
+    BSETI(Register, Register, Immediate),
+
+    // Atomic extension, RD first, then the address in SRC1, then the value in SRC2. The acquire
+    // and release bits don't mean anything with a single hart, so they aren't kept
+    LRW(Register, Register),
+    SCW(Register, Register, Register),
+    AMOSWAPW(Register, Register, Register),
+    AMOADDW(Register, Register, Register),
+    AMOXORW(Register, Register, Register),
+    AMOANDW(Register, Register, Register),
+    AMOORW(Register, Register, Register),
+    AMOMINW(Register, Register, Register),
+    AMOMAXW(Register, Register, Register),
+    AMOMINUW(Register, Register, Register),
+    AMOMAXUW(Register, Register, Register),
+
+    // Single precision floating point. Loads and stores are laid out like the integer ones, and
+    // the rest go RD first, then the sources, then the rounding mode for those that round
+    FLW(FRegister, Register, Immediate),
+    FSW(Register, FRegister, Immediate),
+    FMADDS(FRegister, FRegister, FRegister, FRegister, RoundingMode),
+    FMSUBS(FRegister, FRegister, FRegister, FRegister, RoundingMode),
+    FNMSUBS(FRegister, FRegister, FRegister, FRegister, RoundingMode),
+    FNMADDS(FRegister, FRegister, FRegister, FRegister, RoundingMode),
+    FADDS(FRegister, FRegister, FRegister, RoundingMode),
+    FSUBS(FRegister, FRegister, FRegister, RoundingMode),
+    FMULS(FRegister, FRegister, FRegister, RoundingMode),
+    FDIVS(FRegister, FRegister, FRegister, RoundingMode),
+    FSQRTS(FRegister, FRegister, RoundingMode),
+    FSGNJS(FRegister, FRegister, FRegister),
+    FSGNJNS(FRegister, FRegister, FRegister),
+    FSGNJXS(FRegister, FRegister, FRegister),
+    FMINS(FRegister, FRegister, FRegister),
+    FMAXS(FRegister, FRegister, FRegister),
+    FCVTWS(Register, FRegister, RoundingMode),
+    FCVTWUS(Register, FRegister, RoundingMode),
+    FMVXW(Register, FRegister),
+    FEQS(Register, FRegister, FRegister),
+    FLTS(Register, FRegister, FRegister),
+    FLES(Register, FRegister, FRegister),
+    FCLASSS(Register, FRegister),
+    FCVTSW(FRegister, Register, RoundingMode),
+    FCVTSWU(FRegister, Register, RoundingMode),
+    FMVWX(FRegister, Register),
+
+    // Double precision floating point, in the same order. There are no moves to and from the
+    // integer registers on RV32, as a double doesn't fit in one.
+    FLD(FRegister, Register, Immediate),
+    FSD(Register, FRegister, Immediate),
+    FMADDD(FRegister, FRegister, FRegister, FRegister, RoundingMode),
+    FMSUBD(FRegister, FRegister, FRegister, FRegister, RoundingMode),
+    FNMSUBD(FRegister, FRegister, FRegister, FRegister, RoundingMode),
+    FNMADDD(FRegister, FRegister, FRegister, FRegister, RoundingMode),
+    FADDD(FRegister, FRegister, FRegister, RoundingMode),
+    FSUBD(FRegister, FRegister, FRegister, RoundingMode),
+    FMULD(FRegister, FRegister, FRegister, RoundingMode),
+    FDIVD(FRegister, FRegister, FRegister, RoundingMode),
+    FSQRTD(FRegister, FRegister, RoundingMode),
+    FSGNJD(FRegister, FRegister, FRegister),
+    FSGNJND(FRegister, FRegister, FRegister),
+    FSGNJXD(FRegister, FRegister, FRegister),
+    FMIND(FRegister, FRegister, FRegister),
+    FMAXD(FRegister, FRegister, FRegister),
+    FCVTSD(FRegister, FRegister, RoundingMode),
+    FCVTDS(FRegister, FRegister, RoundingMode),
+    FEQD(Register, FRegister, FRegister),
+    FLTD(Register, FRegister, FRegister),
+    FLED(Register, FRegister, FRegister),
+    FCLASSD(Register, FRegister),
+    FCVTWD(Register, FRegister, RoundingMode),
+    FCVTWUD(Register, FRegister, RoundingMode),
+    FCVTDW(FRegister, Register, RoundingMode),
+    FCVTDWU(FRegister, Register, RoundingMode),
 }
 
 impl Operation {
@@ -103,8 +229,12 @@ impl Operation {
         use InstructionType::*;
         use Operation::*;
         let combined = bytes_to_u32(bytes);
+        // Register fields are decoded as integer registers, floating point ones number the same
+        let f = |reg: Register| FRegister::from_num(reg.to_num() as u32).unwrap();
+        // Rounding modes 5 and 6 are reserved
+        let rm = |funct3: u8| RoundingMode::from_bits(funct3 as u32).ok_or(ParseError::InvalidInstruction(combined));
         // NOTE: Sign extension should happen here for immediates
-        Ok(match InstructionType::from_bytes(bytes) {
+        let op = match InstructionType::from_bytes(bytes) {
             Ok(RType {
                 rd,
                 rs1,
@@ -135,9 +265,90 @@ impl Operation {
                             (0b101,0b0000001) => DIVU(rd,rs1,rs2),
                             (0b110,0b0000001) => REM(rd,rs1,rs2),
                             (0b111,0b0000001) => REMU(rd,rs1,rs2),
+                            (0b010, 0b0010000) => SH1ADD(rd, rs1, rs2),
+                            (0b100, 0b0010000) => SH2ADD(rd, rs1, rs2),
+                            (0b110, 0b0010000) => SH3ADD(rd, rs1, rs2),
+                            (0b111, 0b0100000) => ANDN(rd, rs1, rs2),
+                            (0b110, 0b0100000) => ORN(rd, rs1, rs2),
+                            (0b100, 0b0100000) => XNOR(rd, rs1, rs2),
+                            (0b110, 0b0000101) => MAX(rd, rs1, rs2),
+                            (0b111, 0b0000101) => MAXU(rd, rs1, rs2),
+                            (0b100, 0b0000101) => MIN(rd, rs1, rs2),
+                            (0b101, 0b0000101) => MINU(rd, rs1, rs2),
+                            // zext.h is pack with x0 on RV32, which is only valid with rs2 as x0
+                            (0b100, 0b0000100) if rs2 == Register::Zero => ZEXTH(rd, rs1),
+                            (0b001, 0b0110000) => ROL(rd, rs1, rs2),
+                            (0b101, 0b0110000) => ROR(rd, rs1, rs2),
+                            (0b001, 0b0000101) => CLMUL(rd, rs1, rs2),
+                            (0b011, 0b0000101) => CLMULH(rd, rs1, rs2),
+                            (0b010, 0b0000101) => CLMULR(rd, rs1, rs2),
+                            (0b001, 0b0100100) => BCLR(rd, rs1, rs2),
+                            (0b101, 0b0100100) => BEXT(rd, rs1, rs2),
+                            (0b001, 0b0110100) => BINV(rd, rs1, rs2),
+                            (0b001, 0b0010100) => BSET(rd, rs1, rs2),
                             _ => return Err(ParseError::InvalidInstruction(combined)),
                         }
                     }
+                    // The low two bits of funct7 are the acquire and release bits
+                    0b0101111 => match (funct3, funct7 >> 2, rs2) {
+                        (0b010, 0b00010, Register::Zero) => LRW(rd, rs1),
+                        (0b010, 0b00011, _) => SCW(rd, rs1, rs2),
+                        (0b010, 0b00001, _) => AMOSWAPW(rd, rs1, rs2),
+                        (0b010, 0b00000, _) => AMOADDW(rd, rs1, rs2),
+                        (0b010, 0b00100, _) => AMOXORW(rd, rs1, rs2),
+                        (0b010, 0b01100, _) => AMOANDW(rd, rs1, rs2),
+                        (0b010, 0b01000, _) => AMOORW(rd, rs1, rs2),
+                        (0b010, 0b10000, _) => AMOMINW(rd, rs1, rs2),
+                        (0b010, 0b10100, _) => AMOMAXW(rd, rs1, rs2),
+                        (0b010, 0b11000, _) => AMOMINUW(rd, rs1, rs2),
+                        (0b010, 0b11100, _) => AMOMAXUW(rd, rs1, rs2),
+                        _ => return Err(ParseError::InvalidInstruction(combined)),
+                    },
+                    // Some of these use the rs2 field to pick between variants instead
+                    0b1010011 => match (funct7, funct3, rs2.to_num()) {
+                        (0b0000000, ..) => FADDS(f(rd), f(rs1), f(rs2), rm(funct3)?),
+                        (0b0000100, ..) => FSUBS(f(rd), f(rs1), f(rs2), rm(funct3)?),
+                        (0b0001000, ..) => FMULS(f(rd), f(rs1), f(rs2), rm(funct3)?),
+                        (0b0001100, ..) => FDIVS(f(rd), f(rs1), f(rs2), rm(funct3)?),
+                        (0b0101100, _, 0) => FSQRTS(f(rd), f(rs1), rm(funct3)?),
+                        (0b0010000, 0b000, _) => FSGNJS(f(rd), f(rs1), f(rs2)),
+                        (0b0010000, 0b001, _) => FSGNJNS(f(rd), f(rs1), f(rs2)),
+                        (0b0010000, 0b010, _) => FSGNJXS(f(rd), f(rs1), f(rs2)),
+                        (0b0010100, 0b000, _) => FMINS(f(rd), f(rs1), f(rs2)),
+                        (0b0010100, 0b001, _) => FMAXS(f(rd), f(rs1), f(rs2)),
+                        (0b1100000, _, 0) => FCVTWS(rd, f(rs1), rm(funct3)?),
+                        (0b1100000, _, 1) => FCVTWUS(rd, f(rs1), rm(funct3)?),
+                        (0b1110000, 0b000, 0) => FMVXW(rd, f(rs1)),
+                        (0b1110000, 0b001, 0) => FCLASSS(rd, f(rs1)),
+                        (0b1010000, 0b010, _) => FEQS(rd, f(rs1), f(rs2)),
+                        (0b1010000, 0b001, _) => FLTS(rd, f(rs1), f(rs2)),
+                        (0b1010000, 0b000, _) => FLES(rd, f(rs1), f(rs2)),
+                        (0b1101000, _, 0) => FCVTSW(f(rd), rs1, rm(funct3)?),
+                        (0b1101000, _, 1) => FCVTSWU(f(rd), rs1, rm(funct3)?),
+                        (0b1111000, 0b000, 0) => FMVWX(f(rd), rs1),
+                        (0b0000001, ..) => FADDD(f(rd), f(rs1), f(rs2), rm(funct3)?),
+                        (0b0000101, ..) => FSUBD(f(rd), f(rs1), f(rs2), rm(funct3)?),
+                        (0b0001001, ..) => FMULD(f(rd), f(rs1), f(rs2), rm(funct3)?),
+                        (0b0001101, ..) => FDIVD(f(rd), f(rs1), f(rs2), rm(funct3)?),
+                        (0b0101101, _, 0) => FSQRTD(f(rd), f(rs1), rm(funct3)?),
+                        (0b0010001, 0b000, _) => FSGNJD(f(rd), f(rs1), f(rs2)),
+                        (0b0010001, 0b001, _) => FSGNJND(f(rd), f(rs1), f(rs2)),
+                        (0b0010001, 0b010, _) => FSGNJXD(f(rd), f(rs1), f(rs2)),
+                        (0b0010101, 0b000, _) => FMIND(f(rd), f(rs1), f(rs2)),
+                        (0b0010101, 0b001, _) => FMAXD(f(rd), f(rs1), f(rs2)),
+                        // For conversions between formats, rs2 is the format being converted from
+                        (0b0100000, _, 1) => FCVTSD(f(rd), f(rs1), rm(funct3)?),
+                        (0b0100001, _, 0) => FCVTDS(f(rd), f(rs1), rm(funct3)?),
+                        (0b1010001, 0b010, _) => FEQD(rd, f(rs1), f(rs2)),
+                        (0b1010001, 0b001, _) => FLTD(rd, f(rs1), f(rs2)),
+                        (0b1010001, 0b000, _) => FLED(rd, f(rs1), f(rs2)),
+                        (0b1110001, 0b001, 0) => FCLASSD(rd, f(rs1)),
+                        (0b1100001, _, 0) => FCVTWD(rd, f(rs1), rm(funct3)?),
+                        (0b1100001, _, 1) => FCVTWUD(rd, f(rs1), rm(funct3)?),
+                        (0b1101001, _, 0) => FCVTDW(f(rd), rs1, rm(funct3)?),
+                        (0b1101001, _, 1) => FCVTDWU(f(rd), rs1, rm(funct3)?),
+                        _ => return Err(ParseError::InvalidInstruction(combined)),
+                    },
                     _ => return Err(ParseError::InvalidOpcode(opcode)), // use `op` instead of _?
                 }
             }
@@ -166,19 +377,27 @@ impl Operation {
                         _ => return Err(ParseError::InvalidInstruction(combined)),
                     },
                     0b0010011 => match funct3 {
-                        // this may need refactoring...
+                        // The shifts, and the bit manipulation instructions that share their
+                        // encoding, use the top 7 bits of the immediate as a funct7. The unary
+                        // ones pick an operation with the shift amount.
                         0b001 | 0b101 => {
                             let shamt = bitrange(imm,0,4) as i32;
-                            match funct3 {
-                                0b001 => SLLI(rd,rs1,shamt),
-                                0b101 => {
-                                    let b: i32 = (imm >> 10) as i32;
-                                    match b {
-                                        0b0 => SRLI(rd,rs1,shamt),
-                                        0b1 => SRAI(rd,rs1,shamt),
-                                        _ => return Err(ParseError::InvalidInstruction(combined)),
-                                    }
-                                },
+                            match (funct3, imm >> 5, shamt) {
+                                (0b001, 0b0000000, _) => SLLI(rd,rs1,shamt),
+                                (0b001, 0b0100100, _) => BCLRI(rd, rs1, shamt),
+                                (0b001, 0b0110100, _) => BINVI(rd, rs1, shamt),
+                                (0b001, 0b0010100, _) => BSETI(rd, rs1, shamt),
+                                (0b001, 0b0110000, 0b00000) => CLZ(rd, rs1),
+                                (0b001, 0b0110000, 0b00001) => CTZ(rd, rs1),
+                                (0b001, 0b0110000, 0b00010) => CPOP(rd, rs1),
+                                (0b001, 0b0110000, 0b00100) => SEXTB(rd, rs1),
+                                (0b001, 0b0110000, 0b00101) => SEXTH(rd, rs1),
+                                (0b101, 0b0000000, _) => SRLI(rd,rs1,shamt),
+                                (0b101, 0b0100000, _) => SRAI(rd,rs1,shamt),
+                                (0b101, 0b0100100, _) => BEXTI(rd, rs1, shamt),
+                                (0b101, 0b0110000, _) => RORI(rd, rs1, shamt),
+                                (0b101, 0b0010100, 0b00111) => ORCB(rd, rs1),
+                                (0b101, 0b0110100, 0b11000) => REV8(rd, rs1),
                                 _ => return Err(ParseError::InvalidInstruction(combined)),
                             }
                         },
@@ -190,7 +409,35 @@ impl Operation {
                         0b111 => ANDI(rd, rs1, imm_s),
                         _ => return Err(ParseError::InvalidInstruction(combined)),
                     },
-                    0b1110011 => ECALL,
+                    0b1110011 => {
+                        // The CSR number is the unsigned immediate, and the immediate forms use
+                        // the rs1 field as a 5-bit unsigned value
+                        let csr = imm as u16;
+                        let uimm = rs1.to_num() as Immediate;
+                        match (funct3, rs1, rd, imm) {
+                            (0, Register::Zero, Register::Zero, 0) => ECALL,
+                            (0, Register::Zero, Register::Zero, 1) => EBREAK,
+                            (0, Register::Zero, Register::Zero, 0x302) => MRET,
+                            (0b001, ..) => CSRRW(rd, rs1, csr),
+                            (0b010, ..) => CSRRS(rd, rs1, csr),
+                            (0b011, ..) => CSRRC(rd, rs1, csr),
+                            (0b101, ..) => CSRRWI(rd, uimm, csr),
+                            (0b110, ..) => CSRRSI(rd, uimm, csr),
+                            (0b111, ..) => CSRRCI(rd, uimm, csr),
+                            _ => return Err(ParseError::InvalidInstruction(combined)),
+                        }
+                    },
+                    0b0000111 => match funct3 {
+                        0b010 => FLW(f(rd), rs1, imm_s),
+                        0b011 => FLD(f(rd), rs1, imm_s),
+                        _ => return Err(ParseError::InvalidInstruction(combined)),
+                    },
+                    // The predecessor and successor sets are ignored, every fence is a full fence
+                    0b0001111 => match funct3 {
+                        0b000 => FENCE,
+                        0b001 => FENCEI,
+                        _ => return Err(ParseError::InvalidInstruction(combined)),
+                    },
                     _ => return Err(ParseError::InvalidOpcode(opcode)),
                 }
             }
@@ -209,6 +456,11 @@ impl Operation {
                         0b010 => SW(rs1, rs2, imm_s),
                         _ => return Err(ParseError::InvalidInstruction(combined)),
                     },
+                    0b0100111 => match funct3 {
+                        0b010 => FSW(rs1, f(rs2), imm_s),
+                        0b011 => FSD(rs1, f(rs2), imm_s),
+                        _ => return Err(ParseError::InvalidInstruction(combined)),
+                    },
                     _ => return Err(ParseError::InvalidInstruction(combined)),
                 }
             }
@@ -219,7 +471,8 @@ impl Operation {
                 funct3,
                 opcode,
             }) => {
-                let imm_s: Immediate = sign_extend(imm, 12);
+                // Like JAL the immediate is doubled, so the sign bit is bit 12
+                let imm_s: Immediate = sign_extend(imm, 13);
                 match opcode {
                     0b1100011 => match funct3 {
                         0b000 => BEQ(rs1, rs2, imm_s),
@@ -250,12 +503,425 @@ impl Operation {
                     _ => return Err(ParseError::InvalidInstruction(combined)),
                 }
             }
+            Ok(R4Type {
+                rd,
+                rs1,
+                rs2,
+                rs3,
+                funct3,
+                funct2,
+                opcode,
+            }) => {
+                let (rd, rs1, rs2, rs3, rm) = (f(rd), f(rs1), f(rs2), f(rs3), rm(funct3)?);
+                // funct2 is the format, 0 for single and 1 for double precision
+                match (opcode, funct2) {
+                    (0b1000011, 0b00) => FMADDS(rd, rs1, rs2, rs3, rm),
+                    (0b1000111, 0b00) => FMSUBS(rd, rs1, rs2, rs3, rm),
+                    (0b1001011, 0b00) => FNMSUBS(rd, rs1, rs2, rs3, rm),
+                    (0b1001111, 0b00) => FNMADDS(rd, rs1, rs2, rs3, rm),
+                    (0b1000011, 0b01) => FMADDD(rd, rs1, rs2, rs3, rm),
+                    (0b1000111, 0b01) => FMSUBD(rd, rs1, rs2, rs3, rm),
+                    (0b1001011, 0b01) => FNMSUBD(rd, rs1, rs2, rs3, rm),
+                    (0b1001111, 0b01) => FNMADDD(rd, rs1, rs2, rs3, rm),
+                    _ => return Err(ParseError::InvalidInstruction(combined)),
+                }
+            }
             // Bubble the error from opcode parsing up
             Err(e) => return Err(e),
+        };
+        // The canonical `addi zero, zero, 0` is a NOP. Other instructions that only write x0 are
+        // hints, but they are kept as what they are so that they encode and disassemble the same
+        Ok(match op {
+            ADDI(Register::Zero, Register::Zero, 0) => NOP,
+            op => op,
         })
     }
 }
 
+impl Operation {
+    /// Encode the operation as a little-endian instruction word, the inverse of `from_bytes`
+    pub fn to_bytes(&self) -> [u8; 4] {
+        use Operation::*;
+        fn r(funct7: u32, rs2: Register, rs1: Register, funct3: u32, rd: Register, opcode: u32) -> u32 {
+            (funct7 << 25) | ((rs2.to_num() as u32) << 20) | ((rs1.to_num() as u32) << 15) | (funct3 << 12)
+                | ((rd.to_num() as u32) << 7) | opcode
+        }
+        fn i(imm: i32, rs1: Register, funct3: u32, rd: Register, opcode: u32) -> u32 {
+            ((imm as u32 & 0xFFF) << 20) | ((rs1.to_num() as u32) << 15) | (funct3 << 12)
+                | ((rd.to_num() as u32) << 7) | opcode
+        }
+        fn s(imm: i32, rs2: Register, rs1: Register, funct3: u32, opcode: u32) -> u32 {
+            let imm = imm as u32;
+            (((imm >> 5) & 0x7F) << 25) | ((rs2.to_num() as u32) << 20) | ((rs1.to_num() as u32) << 15)
+                | (funct3 << 12) | ((imm & 0x1F) << 7) | opcode
+        }
+        fn b(imm: i32, rs2: Register, rs1: Register, funct3: u32, opcode: u32) -> u32 {
+            let imm = imm as u32;
+            (((imm >> 12) & 1) << 31) | (((imm >> 5) & 0x3F) << 25) | ((rs2.to_num() as u32) << 20)
+                | ((rs1.to_num() as u32) << 15) | (funct3 << 12) | (((imm >> 1) & 0xF) << 8)
+                | (((imm >> 11) & 1) << 7) | opcode
+        }
+        // The immediate here has already been shifted into the upper 20 bits
+        fn u(imm: i32, rd: Register, opcode: u32) -> u32 {
+            (imm as u32 & 0xFFFFF000) | ((rd.to_num() as u32) << 7) | opcode
+        }
+        fn j(imm: i32, rd: Register, opcode: u32) -> u32 {
+            let imm = imm as u32;
+            (((imm >> 20) & 1) << 31) | (((imm >> 1) & 0x3FF) << 21) | (((imm >> 11) & 1) << 20)
+                | (((imm >> 12) & 0xFF) << 12) | ((rd.to_num() as u32) << 7) | opcode
+        }
+        // The immediate goes where rs1 would be
+        fn csr_imm(csr: u16, uimm: i32, funct3: u32, rd: Register) -> u32 {
+            ((csr as u32) << 20) | ((uimm as u32 & 0x1F) << 15) | (funct3 << 12)
+                | ((rd.to_num() as u32) << 7) | 0b1110011
+        }
+        // Floating point instructions mix integer and float registers, so these take register
+        // numbers. The funct7 field is the operation and the format together.
+        fn fp(funct7: u32, rs2: usize, rs1: usize, funct3: u32, rd: usize) -> u32 {
+            (funct7 << 25) | ((rs2 as u32) << 20) | ((rs1 as u32) << 15) | (funct3 << 12) | ((rd as u32) << 7)
+                | 0b1010011
+        }
+        fn r4(rs3: FRegister, fmt: u32, rs2: FRegister, rs1: FRegister, rm: RoundingMode, rd: FRegister, opcode: u32) -> u32 {
+            ((rs3.to_num() as u32) << 27) | (fmt << 25) | ((rs2.to_num() as u32) << 20) | ((rs1.to_num() as u32) << 15)
+                | (rm.to_bits() << 12) | ((rd.to_num() as u32) << 7) | opcode
+        }
+        fn fl(imm: i32, rs1: Register, width: u32, rd: FRegister) -> u32 {
+            ((imm as u32 & 0xFFF) << 20) | ((rs1.to_num() as u32) << 15) | (width << 12)
+                | ((rd.to_num() as u32) << 7) | 0b0000111
+        }
+        fn fs(imm: i32, rs2: FRegister, rs1: Register, width: u32) -> u32 {
+            let imm = imm as u32;
+            (((imm >> 5) & 0x7F) << 25) | ((rs2.to_num() as u32) << 20) | ((rs1.to_num() as u32) << 15)
+                | (width << 12) | ((imm & 0x1F) << 7) | 0b0100111
+        }
+        let word = match *self {
+            ADDI(rd, rs1, imm) => i(imm, rs1, 0b000, rd, 0b0010011),
+            SLTI(rd, rs1, imm) => i(imm, rs1, 0b010, rd, 0b0010011),
+            SLTIU(rd, rs1, imm) => i(imm, rs1, 0b011, rd, 0b0010011),
+            XORI(rd, rs1, imm) => i(imm, rs1, 0b100, rd, 0b0010011),
+            ORI(rd, rs1, imm) => i(imm, rs1, 0b110, rd, 0b0010011),
+            ANDI(rd, rs1, imm) => i(imm, rs1, 0b111, rd, 0b0010011),
+            SLLI(rd, rs1, shamt) => i(shamt & 0x1F, rs1, 0b001, rd, 0b0010011),
+            SRLI(rd, rs1, shamt) => i(shamt & 0x1F, rs1, 0b101, rd, 0b0010011),
+            SRAI(rd, rs1, shamt) => i((shamt & 0x1F) | 0x400, rs1, 0b101, rd, 0b0010011),
+            LUI(rd, imm) => u(imm, rd, 0b0110111),
+            AUIPC(rd, imm) => u(imm, rd, 0b0010111),
+
+            ADD(rd, rs1, rs2) => r(0, rs2, rs1, 0b000, rd, 0b0110011),
+            SUB(rd, rs1, rs2) => r(0b0100000, rs2, rs1, 0b000, rd, 0b0110011),
+            SLL(rd, rs1, rs2) => r(0, rs2, rs1, 0b001, rd, 0b0110011),
+            SLT(rd, rs1, rs2) => r(0, rs2, rs1, 0b010, rd, 0b0110011),
+            SLTU(rd, rs1, rs2) => r(0, rs2, rs1, 0b011, rd, 0b0110011),
+            XOR(rd, rs1, rs2) => r(0, rs2, rs1, 0b100, rd, 0b0110011),
+            SRL(rd, rs1, rs2) => r(0, rs2, rs1, 0b101, rd, 0b0110011),
+            SRA(rd, rs1, rs2) => r(0b0100000, rs2, rs1, 0b101, rd, 0b0110011),
+            OR(rd, rs1, rs2) => r(0, rs2, rs1, 0b110, rd, 0b0110011),
+            AND(rd, rs1, rs2) => r(0, rs2, rs1, 0b111, rd, 0b0110011),
+            MUL(rd, rs1, rs2) => r(1, rs2, rs1, 0b000, rd, 0b0110011),
+            MULH(rd, rs1, rs2) => r(1, rs2, rs1, 0b001, rd, 0b0110011),
+            MULSU(rd, rs1, rs2) => r(1, rs2, rs1, 0b010, rd, 0b0110011),
+            MULU(rd, rs1, rs2) => r(1, rs2, rs1, 0b011, rd, 0b0110011),
+            DIV(rd, rs1, rs2) => r(1, rs2, rs1, 0b100, rd, 0b0110011),
+            DIVU(rd, rs1, rs2) => r(1, rs2, rs1, 0b101, rd, 0b0110011),
+            REM(rd, rs1, rs2) => r(1, rs2, rs1, 0b110, rd, 0b0110011),
+            REMU(rd, rs1, rs2) => r(1, rs2, rs1, 0b111, rd, 0b0110011),
+
+            SH1ADD(rd, rs1, rs2) => r(0b0010000, rs2, rs1, 0b010, rd, 0b0110011),
+            SH2ADD(rd, rs1, rs2) => r(0b0010000, rs2, rs1, 0b100, rd, 0b0110011),
+            SH3ADD(rd, rs1, rs2) => r(0b0010000, rs2, rs1, 0b110, rd, 0b0110011),
+            ANDN(rd, rs1, rs2) => r(0b0100000, rs2, rs1, 0b111, rd, 0b0110011),
+            ORN(rd, rs1, rs2) => r(0b0100000, rs2, rs1, 0b110, rd, 0b0110011),
+            XNOR(rd, rs1, rs2) => r(0b0100000, rs2, rs1, 0b100, rd, 0b0110011),
+            CLZ(rd, rs1) => i(0x600, rs1, 0b001, rd, 0b0010011),
+            CTZ(rd, rs1) => i(0x601, rs1, 0b001, rd, 0b0010011),
+            CPOP(rd, rs1) => i(0x602, rs1, 0b001, rd, 0b0010011),
+            MAX(rd, rs1, rs2) => r(0b0000101, rs2, rs1, 0b110, rd, 0b0110011),
+            MAXU(rd, rs1, rs2) => r(0b0000101, rs2, rs1, 0b111, rd, 0b0110011),
+            MIN(rd, rs1, rs2) => r(0b0000101, rs2, rs1, 0b100, rd, 0b0110011),
+            MINU(rd, rs1, rs2) => r(0b0000101, rs2, rs1, 0b101, rd, 0b0110011),
+            SEXTB(rd, rs1) => i(0x604, rs1, 0b001, rd, 0b0010011),
+            SEXTH(rd, rs1) => i(0x605, rs1, 0b001, rd, 0b0010011),
+            ZEXTH(rd, rs1) => r(0b0000100, Register::Zero, rs1, 0b100, rd, 0b0110011),
+            ROL(rd, rs1, rs2) => r(0b0110000, rs2, rs1, 0b001, rd, 0b0110011),
+            ROR(rd, rs1, rs2) => r(0b0110000, rs2, rs1, 0b101, rd, 0b0110011),
+            RORI(rd, rs1, shamt) => i((shamt & 0x1F) | 0x600, rs1, 0b101, rd, 0b0010011),
+            ORCB(rd, rs1) => i(0x287, rs1, 0b101, rd, 0b0010011),
+            REV8(rd, rs1) => i(0x698, rs1, 0b101, rd, 0b0010011),
+            CLMUL(rd, rs1, rs2) => r(0b0000101, rs2, rs1, 0b001, rd, 0b0110011),
+            CLMULH(rd, rs1, rs2) => r(0b0000101, rs2, rs1, 0b011, rd, 0b0110011),
+            CLMULR(rd, rs1, rs2) => r(0b0000101, rs2, rs1, 0b010, rd, 0b0110011),
+            BCLR(rd, rs1, rs2) => r(0b0100100, rs2, rs1, 0b001, rd, 0b0110011),
+            BCLRI(rd, rs1, shamt) => i((shamt & 0x1F) | 0x480, rs1, 0b001, rd, 0b0010011),
+            BEXT(rd, rs1, rs2) => r(0b0100100, rs2, rs1, 0b101, rd, 0b0110011),
+            BEXTI(rd, rs1, shamt) => i((shamt & 0x1F) | 0x480, rs1, 0b101, rd, 0b0010011),
+            BINV(rd, rs1, rs2) => r(0b0110100, rs2, rs1, 0b001, rd, 0b0110011),
+            BINVI(rd, rs1, shamt) => i((shamt & 0x1F) | 0x680, rs1, 0b001, rd, 0b0010011),
+            BSET(rd, rs1, rs2) => r(0b0010100, rs2, rs1, 0b001, rd, 0b0110011),
+            BSETI(rd, rs1, shamt) => i((shamt & 0x1F) | 0x280, rs1, 0b001, rd, 0b0010011),
+
+            LRW(rd, rs1) => r(0b00010 << 2, Register::Zero, rs1, 0b010, rd, 0b0101111),
+            SCW(rd, rs1, rs2) => r(0b00011 << 2, rs2, rs1, 0b010, rd, 0b0101111),
+            AMOSWAPW(rd, rs1, rs2) => r(0b00001 << 2, rs2, rs1, 0b010, rd, 0b0101111),
+            AMOADDW(rd, rs1, rs2) => r(0b00000 << 2, rs2, rs1, 0b010, rd, 0b0101111),
+            AMOXORW(rd, rs1, rs2) => r(0b00100 << 2, rs2, rs1, 0b010, rd, 0b0101111),
+            AMOANDW(rd, rs1, rs2) => r(0b01100 << 2, rs2, rs1, 0b010, rd, 0b0101111),
+            AMOORW(rd, rs1, rs2) => r(0b01000 << 2, rs2, rs1, 0b010, rd, 0b0101111),
+            AMOMINW(rd, rs1, rs2) => r(0b10000 << 2, rs2, rs1, 0b010, rd, 0b0101111),
+            AMOMAXW(rd, rs1, rs2) => r(0b10100 << 2, rs2, rs1, 0b010, rd, 0b0101111),
+            AMOMINUW(rd, rs1, rs2) => r(0b11000 << 2, rs2, rs1, 0b010, rd, 0b0101111),
+            AMOMAXUW(rd, rs1, rs2) => r(0b11100 << 2, rs2, rs1, 0b010, rd, 0b0101111),
+
+            FLW(rd, rs1, imm) => fl(imm, rs1, 0b010, rd),
+            FSW(rs1, rs2, imm) => fs(imm, rs2, rs1, 0b010),
+            FMADDS(rd, rs1, rs2, rs3, rm) => r4(rs3, 0b00, rs2, rs1, rm, rd, 0b1000011),
+            FMSUBS(rd, rs1, rs2, rs3, rm) => r4(rs3, 0b00, rs2, rs1, rm, rd, 0b1000111),
+            FNMSUBS(rd, rs1, rs2, rs3, rm) => r4(rs3, 0b00, rs2, rs1, rm, rd, 0b1001011),
+            FNMADDS(rd, rs1, rs2, rs3, rm) => r4(rs3, 0b00, rs2, rs1, rm, rd, 0b1001111),
+            FADDS(rd, rs1, rs2, rm) => fp(0b0000000, rs2.to_num(), rs1.to_num(), rm.to_bits(), rd.to_num()),
+            FSUBS(rd, rs1, rs2, rm) => fp(0b0000100, rs2.to_num(), rs1.to_num(), rm.to_bits(), rd.to_num()),
+            FMULS(rd, rs1, rs2, rm) => fp(0b0001000, rs2.to_num(), rs1.to_num(), rm.to_bits(), rd.to_num()),
+            FDIVS(rd, rs1, rs2, rm) => fp(0b0001100, rs2.to_num(), rs1.to_num(), rm.to_bits(), rd.to_num()),
+            FSQRTS(rd, rs1, rm) => fp(0b0101100, 0, rs1.to_num(), rm.to_bits(), rd.to_num()),
+            FSGNJS(rd, rs1, rs2) => fp(0b0010000, rs2.to_num(), rs1.to_num(), 0b000, rd.to_num()),
+            FSGNJNS(rd, rs1, rs2) => fp(0b0010000, rs2.to_num(), rs1.to_num(), 0b001, rd.to_num()),
+            FSGNJXS(rd, rs1, rs2) => fp(0b0010000, rs2.to_num(), rs1.to_num(), 0b010, rd.to_num()),
+            FMINS(rd, rs1, rs2) => fp(0b0010100, rs2.to_num(), rs1.to_num(), 0b000, rd.to_num()),
+            FMAXS(rd, rs1, rs2) => fp(0b0010100, rs2.to_num(), rs1.to_num(), 0b001, rd.to_num()),
+            FCVTWS(rd, rs1, rm) => fp(0b1100000, 0, rs1.to_num(), rm.to_bits(), rd.to_num()),
+            FCVTWUS(rd, rs1, rm) => fp(0b1100000, 1, rs1.to_num(), rm.to_bits(), rd.to_num()),
+            FMVXW(rd, rs1) => fp(0b1110000, 0, rs1.to_num(), 0b000, rd.to_num()),
+            FCLASSS(rd, rs1) => fp(0b1110000, 0, rs1.to_num(), 0b001, rd.to_num()),
+            FEQS(rd, rs1, rs2) => fp(0b1010000, rs2.to_num(), rs1.to_num(), 0b010, rd.to_num()),
+            FLTS(rd, rs1, rs2) => fp(0b1010000, rs2.to_num(), rs1.to_num(), 0b001, rd.to_num()),
+            FLES(rd, rs1, rs2) => fp(0b1010000, rs2.to_num(), rs1.to_num(), 0b000, rd.to_num()),
+            FCVTSW(rd, rs1, rm) => fp(0b1101000, 0, rs1.to_num(), rm.to_bits(), rd.to_num()),
+            FCVTSWU(rd, rs1, rm) => fp(0b1101000, 1, rs1.to_num(), rm.to_bits(), rd.to_num()),
+            FMVWX(rd, rs1) => fp(0b1111000, 0, rs1.to_num(), 0b000, rd.to_num()),
+            FLD(rd, rs1, imm) => fl(imm, rs1, 0b011, rd),
+            FSD(rs1, rs2, imm) => fs(imm, rs2, rs1, 0b011),
+            FMADDD(rd, rs1, rs2, rs3, rm) => r4(rs3, 0b01, rs2, rs1, rm, rd, 0b1000011),
+            FMSUBD(rd, rs1, rs2, rs3, rm) => r4(rs3, 0b01, rs2, rs1, rm, rd, 0b1000111),
+            FNMSUBD(rd, rs1, rs2, rs3, rm) => r4(rs3, 0b01, rs2, rs1, rm, rd, 0b1001011),
+            FNMADDD(rd, rs1, rs2, rs3, rm) => r4(rs3, 0b01, rs2, rs1, rm, rd, 0b1001111),
+            FADDD(rd, rs1, rs2, rm) => fp(0b0000001, rs2.to_num(), rs1.to_num(), rm.to_bits(), rd.to_num()),
+            FSUBD(rd, rs1, rs2, rm) => fp(0b0000101, rs2.to_num(), rs1.to_num(), rm.to_bits(), rd.to_num()),
+            FMULD(rd, rs1, rs2, rm) => fp(0b0001001, rs2.to_num(), rs1.to_num(), rm.to_bits(), rd.to_num()),
+            FDIVD(rd, rs1, rs2, rm) => fp(0b0001101, rs2.to_num(), rs1.to_num(), rm.to_bits(), rd.to_num()),
+            FSQRTD(rd, rs1, rm) => fp(0b0101101, 0, rs1.to_num(), rm.to_bits(), rd.to_num()),
+            FSGNJD(rd, rs1, rs2) => fp(0b0010001, rs2.to_num(), rs1.to_num(), 0b000, rd.to_num()),
+            FSGNJND(rd, rs1, rs2) => fp(0b0010001, rs2.to_num(), rs1.to_num(), 0b001, rd.to_num()),
+            FSGNJXD(rd, rs1, rs2) => fp(0b0010001, rs2.to_num(), rs1.to_num(), 0b010, rd.to_num()),
+            FMIND(rd, rs1, rs2) => fp(0b0010101, rs2.to_num(), rs1.to_num(), 0b000, rd.to_num()),
+            FMAXD(rd, rs1, rs2) => fp(0b0010101, rs2.to_num(), rs1.to_num(), 0b001, rd.to_num()),
+            FCVTSD(rd, rs1, rm) => fp(0b0100000, 1, rs1.to_num(), rm.to_bits(), rd.to_num()),
+            FCVTDS(rd, rs1, rm) => fp(0b0100001, 0, rs1.to_num(), rm.to_bits(), rd.to_num()),
+            FEQD(rd, rs1, rs2) => fp(0b1010001, rs2.to_num(), rs1.to_num(), 0b010, rd.to_num()),
+            FLTD(rd, rs1, rs2) => fp(0b1010001, rs2.to_num(), rs1.to_num(), 0b001, rd.to_num()),
+            FLED(rd, rs1, rs2) => fp(0b1010001, rs2.to_num(), rs1.to_num(), 0b000, rd.to_num()),
+            FCLASSD(rd, rs1) => fp(0b1110001, 0, rs1.to_num(), 0b001, rd.to_num()),
+            FCVTWD(rd, rs1, rm) => fp(0b1100001, 0, rs1.to_num(), rm.to_bits(), rd.to_num()),
+            FCVTWUD(rd, rs1, rm) => fp(0b1100001, 1, rs1.to_num(), rm.to_bits(), rd.to_num()),
+            FCVTDW(rd, rs1, rm) => fp(0b1101001, 0, rs1.to_num(), rm.to_bits(), rd.to_num()),
+            FCVTDWU(rd, rs1, rm) => fp(0b1101001, 1, rs1.to_num(), rm.to_bits(), rd.to_num()),
+
+            JAL(rd, imm) => j(imm, rd, 0b1101111),
+            JALR(rd, rs1, imm) => i(imm, rs1, 0b000, rd, 0b1100111),
+
+            BEQ(rs1, rs2, imm) => b(imm, rs2, rs1, 0b000, 0b1100011),
+            BNE(rs1, rs2, imm) => b(imm, rs2, rs1, 0b001, 0b1100011),
+            BLT(rs1, rs2, imm) => b(imm, rs2, rs1, 0b100, 0b1100011),
+            BGE(rs1, rs2, imm) => b(imm, rs2, rs1, 0b101, 0b1100011),
+            BLTU(rs1, rs2, imm) => b(imm, rs2, rs1, 0b110, 0b1100011),
+            BGEU(rs1, rs2, imm) => b(imm, rs2, rs1, 0b111, 0b1100011),
+
+            LB(rd, rs1, imm) => i(imm, rs1, 0b000, rd, 0b0000011),
+            LH(rd, rs1, imm) => i(imm, rs1, 0b001, rd, 0b0000011),
+            LW(rd, rs1, imm) => i(imm, rs1, 0b010, rd, 0b0000011),
+            LBU(rd, rs1, imm) => i(imm, rs1, 0b100, rd, 0b0000011),
+            LHU(rd, rs1, imm) => i(imm, rs1, 0b101, rd, 0b0000011),
+            SB(rs1, rs2, imm) => s(imm, rs2, rs1, 0b000, 0b0100011),
+            SH(rs1, rs2, imm) => s(imm, rs2, rs1, 0b001, 0b0100011),
+            SW(rs1, rs2, imm) => s(imm, rs2, rs1, 0b010, 0b0100011),
+
+            CSRRW(rd, rs1, csr) => i(csr as i32, rs1, 0b001, rd, 0b1110011),
+            CSRRS(rd, rs1, csr) => i(csr as i32, rs1, 0b010, rd, 0b1110011),
+            CSRRC(rd, rs1, csr) => i(csr as i32, rs1, 0b011, rd, 0b1110011),
+            CSRRWI(rd, uimm, csr) => csr_imm(csr, uimm, 0b101, rd),
+            CSRRSI(rd, uimm, csr) => csr_imm(csr, uimm, 0b110, rd),
+            CSRRCI(rd, uimm, csr) => csr_imm(csr, uimm, 0b111, rd),
+
+            ECALL => 0x00000073,
+            EBREAK => 0x00100073,
+            MRET => 0x30200073,
+            FENCE => 0x0FF0000F,
+            FENCEI => 0x0000100F,
+            NOP => 0x00000013,
+        };
+        word.to_le_bytes()
+    }
+
+    /// Where a pc-relative jump or branch goes when it is taken
+    pub fn target(&self, pc: u32) -> Option<u32> {
+        use Operation::*;
+        match self {
+            JAL(_, imm)
+            | BEQ(_, _, imm)
+            | BNE(_, _, imm)
+            | BLT(_, _, imm)
+            | BLTU(_, _, imm)
+            | BGE(_, _, imm)
+            | BGEU(_, _, imm) => Some(pc.wrapping_add(*imm as u32)),
+            _ => None,
+        }
+    }
+
+    /// The extension this operation belongs to, or `None` for the base integer set
+    pub fn extension(&self) -> Option<Extension> {
+        use Operation::*;
+        match self {
+            MUL(..) | MULH(..) | MULSU(..) | MULU(..) | DIV(..) | DIVU(..) | REM(..) | REMU(..) => {
+                Some(Extension::M)
+            }
+            LRW(..) | SCW(..) | AMOSWAPW(..) | AMOADDW(..) | AMOXORW(..) | AMOANDW(..) | AMOORW(..)
+            | AMOMINW(..) | AMOMAXW(..) | AMOMINUW(..) | AMOMAXUW(..) => Some(Extension::A),
+            FLW(..) | FSW(..) | FMADDS(..) | FMSUBS(..) | FNMSUBS(..) | FNMADDS(..) | FADDS(..)
+            | FSUBS(..) | FMULS(..) | FDIVS(..) | FSQRTS(..) | FSGNJS(..) | FSGNJNS(..) | FSGNJXS(..)
+            | FMINS(..) | FMAXS(..) | FCVTWS(..) | FCVTWUS(..) | FMVXW(..) | FEQS(..) | FLTS(..)
+            | FLES(..) | FCLASSS(..) | FCVTSW(..) | FCVTSWU(..) | FMVWX(..) => Some(Extension::F),
+            FLD(..) | FSD(..) | FMADDD(..) | FMSUBD(..) | FNMSUBD(..) | FNMADDD(..) | FADDD(..)
+            | FSUBD(..) | FMULD(..) | FDIVD(..) | FSQRTD(..) | FSGNJD(..) | FSGNJND(..) | FSGNJXD(..)
+            | FMIND(..) | FMAXD(..) | FCVTSD(..) | FCVTDS(..) | FEQD(..) | FLTD(..) | FLED(..)
+            | FCLASSD(..) | FCVTWD(..) | FCVTWUD(..) | FCVTDW(..) | FCVTDWU(..) => Some(Extension::D),
+            SH1ADD(..) | SH2ADD(..) | SH3ADD(..) => Some(Extension::Zba),
+            ANDN(..) | ORN(..) | XNOR(..) | CLZ(..) | CTZ(..) | CPOP(..) | MAX(..) | MAXU(..) | MIN(..)
+            | MINU(..) | SEXTB(..) | SEXTH(..) | ZEXTH(..) | ROL(..) | ROR(..) | RORI(..) | ORCB(..)
+            | REV8(..) => Some(Extension::Zbb),
+            CLMUL(..) | CLMULH(..) | CLMULR(..) => Some(Extension::Zbc),
+            BCLR(..) | BCLRI(..) | BEXT(..) | BEXTI(..) | BINV(..) | BINVI(..) | BSET(..) | BSETI(..) => {
+                Some(Extension::Zbs)
+            }
+            _ => None,
+        }
+    }
+    /// Display the operation at `pc`, naming the target of any jump or branch
+    pub fn display_with<'a>(&'a self, pc: u32, symbols: &'a SymbolTable) -> SymbolizedOperation<'a> {
+        SymbolizedOperation { op: self, target: self.target(pc), symbols }
+    }
+}
+
+/// A decoded instruction as it sits in memory, either a full 32-bit one or a 16-bit RV32C one
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instruction {
+    Full(Operation),
+    Compressed(CompressedOp),
+}
+
+impl Instruction {
+    /// Decode the instruction at the start of `bytes`, which has to hold at least 2 bytes. The
+    /// length of the instruction is given by its lowest bits, a full one needs all 4.
+    pub fn decode(bytes: &[u8]) -> Result<Self, ParseError> {
+        if is_compressed(bytes[0]) {
+            Ok(Instruction::Compressed(CompressedOp::from_bytes(bytes)?))
+        } else if bytes.len() < 4 {
+            Err(ParseError::InvalidInstruction(bytes[0] as u32 | (bytes[1] as u32) << 8))
+        } else {
+            Ok(Instruction::Full(Operation::from_bytes(bytes)?))
+        }
+    }
+
+    /// Decode like `decode`, but only accept instructions from the extensions in `isa`
+    pub fn decode_for(bytes: &[u8], isa: &Isa) -> Result<Self, ParseError> {
+        let instruction = Instruction::decode(bytes)?;
+        let missing = match instruction {
+            Instruction::Compressed(_) if !isa.has(Extension::C) => Some(Extension::C),
+            _ => instruction.operation().extension().filter(|ext| !isa.has(*ext)),
+        };
+        match missing {
+            Some(ext) => {
+                let word = match instruction {
+                    Instruction::Compressed(_) => bytes[0] as u32 | (bytes[1] as u32) << 8,
+                    Instruction::Full(_) => bytes_to_u32(bytes),
+                };
+                Err(ParseError::MissingExtension(word, ext))
+            }
+            None => Ok(instruction),
+        }
+    }
+
+    /// Size in bytes
+    pub fn len(&self) -> u32 {
+        match self {
+            Instruction::Full(_) => 4,
+            Instruction::Compressed(_) => 2,
+        }
+    }
+
+    /// The operation to execute
+    pub fn operation(&self) -> Operation {
+        match self {
+            Instruction::Full(op) => op.clone(),
+            Instruction::Compressed(op) => op.expand(),
+        }
+    }
+
+    pub fn target(&self, pc: u32) -> Option<u32> {
+        self.operation().target(pc)
+    }
+
+    /// Display the instruction at `pc`, naming the target of any jump or branch
+    pub fn display_with<'a>(&'a self, pc: u32, symbols: &'a SymbolTable) -> SymbolizedOperation<'a> {
+        match self {
+            Instruction::Full(op) => op.display_with(pc, symbols),
+            Instruction::Compressed(op) => SymbolizedOperation { op, target: self.target(pc), symbols },
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Instruction::Full(op) => write!(f, "{op}"),
+            Instruction::Compressed(op) => write!(f, "{op}"),
+        }
+    }
+}
+
+pub struct SymbolizedOperation<'a> {
+    op: &'a dyn fmt::Display,
+    target: Option<u32>,
+    symbols: &'a SymbolTable,
+}
+impl fmt::Display for SymbolizedOperation<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.op)?;
+        match self.target.and_then(|t| self.symbols.symbolize(t)) {
+            Some(name) => write!(f, " <{name}>"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The rounding mode operand, which is left out when it is the dynamic one like assemblers do
+struct Rounding(RoundingMode);
+impl fmt::Display for Rounding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            RoundingMode::DYN => Ok(()),
+            rm => write!(f, ", {rm}"),
+        }
+    }
+}
+
+struct CsrName(u16);
+impl fmt::Display for CsrName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match csr::name(self.0) {
+            Some(name) => write!(f, "{name}"),
+            None => write!(f, "{:#x}", self.0),
+        }
+    }
+}
+
 impl fmt::Display for Operation {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         use Operation::*;
@@ -322,6 +988,71 @@ impl fmt::Display for Operation {
                 write!(f,"REM   {r1}, {r2}, {r3}"),
             REMU(r1, r2, r3) => 
                 write!(f,"REMU   {r1}, {r2}, {r3}"),
+
+            SH1ADD(r1, r2, r3) =>
+                write!(f,"SH1ADD {r1}, {r2}, {r3}"),
+            SH2ADD(r1, r2, r3) =>
+                write!(f,"SH2ADD {r1}, {r2}, {r3}"),
+            SH3ADD(r1, r2, r3) =>
+                write!(f,"SH3ADD {r1}, {r2}, {r3}"),
+            ANDN(r1, r2, r3) =>
+                write!(f,"ANDN  {r1}, {r2}, {r3}"),
+            ORN(r1, r2, r3) =>
+                write!(f,"ORN   {r1}, {r2}, {r3}"),
+            XNOR(r1, r2, r3) =>
+                write!(f,"XNOR  {r1}, {r2}, {r3}"),
+            CLZ(r1, r2) =>
+                write!(f,"CLZ   {r1}, {r2}"),
+            CTZ(r1, r2) =>
+                write!(f,"CTZ   {r1}, {r2}"),
+            CPOP(r1, r2) =>
+                write!(f,"CPOP  {r1}, {r2}"),
+            MAX(r1, r2, r3) =>
+                write!(f,"MAX   {r1}, {r2}, {r3}"),
+            MAXU(r1, r2, r3) =>
+                write!(f,"MAXU  {r1}, {r2}, {r3}"),
+            MIN(r1, r2, r3) =>
+                write!(f,"MIN   {r1}, {r2}, {r3}"),
+            MINU(r1, r2, r3) =>
+                write!(f,"MINU  {r1}, {r2}, {r3}"),
+            SEXTB(r1, r2) =>
+                write!(f,"SEXT.B {r1}, {r2}"),
+            SEXTH(r1, r2) =>
+                write!(f,"SEXT.H {r1}, {r2}"),
+            ZEXTH(r1, r2) =>
+                write!(f,"ZEXT.H {r1}, {r2}"),
+            ROL(r1, r2, r3) =>
+                write!(f,"ROL   {r1}, {r2}, {r3}"),
+            ROR(r1, r2, r3) =>
+                write!(f,"ROR   {r1}, {r2}, {r3}"),
+            RORI(r1, r2, imm) =>
+                write!(f,"RORI  {r1}, {r2}, {imm:#x}"),
+            ORCB(r1, r2) =>
+                write!(f,"ORC.B {r1}, {r2}"),
+            REV8(r1, r2) =>
+                write!(f,"REV8  {r1}, {r2}"),
+            CLMUL(r1, r2, r3) =>
+                write!(f,"CLMUL {r1}, {r2}, {r3}"),
+            CLMULH(r1, r2, r3) =>
+                write!(f,"CLMULH {r1}, {r2}, {r3}"),
+            CLMULR(r1, r2, r3) =>
+                write!(f,"CLMULR {r1}, {r2}, {r3}"),
+            BCLR(r1, r2, r3) =>
+                write!(f,"BCLR  {r1}, {r2}, {r3}"),
+            BCLRI(r1, r2, imm) =>
+                write!(f,"BCLRI {r1}, {r2}, {imm:#x}"),
+            BEXT(r1, r2, r3) =>
+                write!(f,"BEXT  {r1}, {r2}, {r3}"),
+            BEXTI(r1, r2, imm) =>
+                write!(f,"BEXTI {r1}, {r2}, {imm:#x}"),
+            BINV(r1, r2, r3) =>
+                write!(f,"BINV  {r1}, {r2}, {r3}"),
+            BINVI(r1, r2, imm) =>
+                write!(f,"BINVI {r1}, {r2}, {imm:#x}"),
+            BSET(r1, r2, r3) =>
+                write!(f,"BSET  {r1}, {r2}, {r3}"),
+            BSETI(r1, r2, imm) =>
+                write!(f,"BSETI {r1}, {r2}, {imm:#x}"),
             
 
             // Control transfer instructions
@@ -365,6 +1096,150 @@ impl fmt::Display for Operation {
             SB(r1, r2, imm) => 
                 write!(f,"SB    {r1}, {r2}, {imm:#x}"),
 
+            // The address register is shown in brackets, like in assembly
+            LRW(r1, r2) =>
+                write!(f,"LR.W   {r1}, ({r2})"),
+            SCW(r1, r2, r3) =>
+                write!(f,"SC.W   {r1}, {r3}, ({r2})"),
+            AMOSWAPW(r1, r2, r3) =>
+                write!(f,"AMOSWAP.W {r1}, {r3}, ({r2})"),
+            AMOADDW(r1, r2, r3) =>
+                write!(f,"AMOADD.W {r1}, {r3}, ({r2})"),
+            AMOXORW(r1, r2, r3) =>
+                write!(f,"AMOXOR.W {r1}, {r3}, ({r2})"),
+            AMOANDW(r1, r2, r3) =>
+                write!(f,"AMOAND.W {r1}, {r3}, ({r2})"),
+            AMOORW(r1, r2, r3) =>
+                write!(f,"AMOOR.W {r1}, {r3}, ({r2})"),
+            AMOMINW(r1, r2, r3) =>
+                write!(f,"AMOMIN.W {r1}, {r3}, ({r2})"),
+            AMOMAXW(r1, r2, r3) =>
+                write!(f,"AMOMAX.W {r1}, {r3}, ({r2})"),
+            AMOMINUW(r1, r2, r3) =>
+                write!(f,"AMOMINU.W {r1}, {r3}, ({r2})"),
+            AMOMAXUW(r1, r2, r3) =>
+                write!(f,"AMOMAXU.W {r1}, {r3}, ({r2})"),
+
+            // CSRs are shown by name when they have one
+            CSRRW(r1, r2, c) =>
+                write!(f,"CSRRW  {r1}, {r2}, {}", CsrName(*c)),
+            CSRRS(r1, r2, c) =>
+                write!(f,"CSRRS  {r1}, {r2}, {}", CsrName(*c)),
+            CSRRC(r1, r2, c) =>
+                write!(f,"CSRRC  {r1}, {r2}, {}", CsrName(*c)),
+            CSRRWI(r1, imm, c) =>
+                write!(f,"CSRRWI {r1}, {imm:#x}, {}", CsrName(*c)),
+            CSRRSI(r1, imm, c) =>
+                write!(f,"CSRRSI {r1}, {imm:#x}, {}", CsrName(*c)),
+            CSRRCI(r1, imm, c) =>
+                write!(f,"CSRRCI {r1}, {imm:#x}, {}", CsrName(*c)),
+
+            FLW(r1, r2, imm) =>
+                write!(f,"FLW   {r1}, {r2}, {imm:#x}"),
+            FSW(r1, r2, imm) =>
+                write!(f,"FSW   {r1}, {r2}, {imm:#x}"),
+            FMADDS(r1, r2, r3, r4, rm) =>
+                write!(f,"FMADD.S {r1}, {r2}, {r3}, {r4}{}", Rounding(*rm)),
+            FMSUBS(r1, r2, r3, r4, rm) =>
+                write!(f,"FMSUB.S {r1}, {r2}, {r3}, {r4}{}", Rounding(*rm)),
+            FNMSUBS(r1, r2, r3, r4, rm) =>
+                write!(f,"FNMSUB.S {r1}, {r2}, {r3}, {r4}{}", Rounding(*rm)),
+            FNMADDS(r1, r2, r3, r4, rm) =>
+                write!(f,"FNMADD.S {r1}, {r2}, {r3}, {r4}{}", Rounding(*rm)),
+            FADDS(r1, r2, r3, rm) =>
+                write!(f,"FADD.S {r1}, {r2}, {r3}{}", Rounding(*rm)),
+            FSUBS(r1, r2, r3, rm) =>
+                write!(f,"FSUB.S {r1}, {r2}, {r3}{}", Rounding(*rm)),
+            FMULS(r1, r2, r3, rm) =>
+                write!(f,"FMUL.S {r1}, {r2}, {r3}{}", Rounding(*rm)),
+            FDIVS(r1, r2, r3, rm) =>
+                write!(f,"FDIV.S {r1}, {r2}, {r3}{}", Rounding(*rm)),
+            FSQRTS(r1, r2, rm) =>
+                write!(f,"FSQRT.S {r1}, {r2}{}", Rounding(*rm)),
+            FSGNJS(r1, r2, r3) =>
+                write!(f,"FSGNJ.S {r1}, {r2}, {r3}"),
+            FSGNJNS(r1, r2, r3) =>
+                write!(f,"FSGNJN.S {r1}, {r2}, {r3}"),
+            FSGNJXS(r1, r2, r3) =>
+                write!(f,"FSGNJX.S {r1}, {r2}, {r3}"),
+            FMINS(r1, r2, r3) =>
+                write!(f,"FMIN.S {r1}, {r2}, {r3}"),
+            FMAXS(r1, r2, r3) =>
+                write!(f,"FMAX.S {r1}, {r2}, {r3}"),
+            FCVTWS(r1, r2, rm) =>
+                write!(f,"FCVT.W.S {r1}, {r2}{}", Rounding(*rm)),
+            FCVTWUS(r1, r2, rm) =>
+                write!(f,"FCVT.WU.S {r1}, {r2}{}", Rounding(*rm)),
+            FMVXW(r1, r2) =>
+                write!(f,"FMV.X.W {r1}, {r2}"),
+            FEQS(r1, r2, r3) =>
+                write!(f,"FEQ.S {r1}, {r2}, {r3}"),
+            FLTS(r1, r2, r3) =>
+                write!(f,"FLT.S {r1}, {r2}, {r3}"),
+            FLES(r1, r2, r3) =>
+                write!(f,"FLE.S {r1}, {r2}, {r3}"),
+            FCLASSS(r1, r2) =>
+                write!(f,"FCLASS.S {r1}, {r2}"),
+            FCVTSW(r1, r2, rm) =>
+                write!(f,"FCVT.S.W {r1}, {r2}{}", Rounding(*rm)),
+            FCVTSWU(r1, r2, rm) =>
+                write!(f,"FCVT.S.WU {r1}, {r2}{}", Rounding(*rm)),
+            FMVWX(r1, r2) =>
+                write!(f,"FMV.W.X {r1}, {r2}"),
+            FLD(r1, r2, imm) =>
+                write!(f,"FLD   {r1}, {r2}, {imm:#x}"),
+            FSD(r1, r2, imm) =>
+                write!(f,"FSD   {r1}, {r2}, {imm:#x}"),
+            FMADDD(r1, r2, r3, r4, rm) =>
+                write!(f,"FMADD.D {r1}, {r2}, {r3}, {r4}{}", Rounding(*rm)),
+            FMSUBD(r1, r2, r3, r4, rm) =>
+                write!(f,"FMSUB.D {r1}, {r2}, {r3}, {r4}{}", Rounding(*rm)),
+            FNMSUBD(r1, r2, r3, r4, rm) =>
+                write!(f,"FNMSUB.D {r1}, {r2}, {r3}, {r4}{}", Rounding(*rm)),
+            FNMADDD(r1, r2, r3, r4, rm) =>
+                write!(f,"FNMADD.D {r1}, {r2}, {r3}, {r4}{}", Rounding(*rm)),
+            FADDD(r1, r2, r3, rm) =>
+                write!(f,"FADD.D {r1}, {r2}, {r3}{}", Rounding(*rm)),
+            FSUBD(r1, r2, r3, rm) =>
+                write!(f,"FSUB.D {r1}, {r2}, {r3}{}", Rounding(*rm)),
+            FMULD(r1, r2, r3, rm) =>
+                write!(f,"FMUL.D {r1}, {r2}, {r3}{}", Rounding(*rm)),
+            FDIVD(r1, r2, r3, rm) =>
+                write!(f,"FDIV.D {r1}, {r2}, {r3}{}", Rounding(*rm)),
+            FSQRTD(r1, r2, rm) =>
+                write!(f,"FSQRT.D {r1}, {r2}{}", Rounding(*rm)),
+            FSGNJD(r1, r2, r3) =>
+                write!(f,"FSGNJ.D {r1}, {r2}, {r3}"),
+            FSGNJND(r1, r2, r3) =>
+                write!(f,"FSGNJN.D {r1}, {r2}, {r3}"),
+            FSGNJXD(r1, r2, r3) =>
+                write!(f,"FSGNJX.D {r1}, {r2}, {r3}"),
+            FMIND(r1, r2, r3) =>
+                write!(f,"FMIN.D {r1}, {r2}, {r3}"),
+            FMAXD(r1, r2, r3) =>
+                write!(f,"FMAX.D {r1}, {r2}, {r3}"),
+            FCVTSD(r1, r2, rm) =>
+                write!(f,"FCVT.S.D {r1}, {r2}{}", Rounding(*rm)),
+            FCVTDS(r1, r2, rm) =>
+                write!(f,"FCVT.D.S {r1}, {r2}{}", Rounding(*rm)),
+            FEQD(r1, r2, r3) =>
+                write!(f,"FEQ.D {r1}, {r2}, {r3}"),
+            FLTD(r1, r2, r3) =>
+                write!(f,"FLT.D {r1}, {r2}, {r3}"),
+            FLED(r1, r2, r3) =>
+                write!(f,"FLE.D {r1}, {r2}, {r3}"),
+            FCLASSD(r1, r2) =>
+                write!(f,"FCLASS.D {r1}, {r2}"),
+            FCVTWD(r1, r2, rm) =>
+                write!(f,"FCVT.W.D {r1}, {r2}{}", Rounding(*rm)),
+            FCVTWUD(r1, r2, rm) =>
+                write!(f,"FCVT.WU.D {r1}, {r2}{}", Rounding(*rm)),
+            FCVTDW(r1, r2, rm) =>
+                write!(f,"FCVT.D.W {r1}, {r2}{}", Rounding(*rm)),
+            FCVTDWU(r1, r2, rm) =>
+                write!(f,"FCVT.D.WU {r1}, {r2}{}", Rounding(*rm)),
+            FENCEI => write!(f, "FENCE.I"),
+
             
 
             _ => write!(f, "{:?}", self)
@@ -388,4 +1263,190 @@ mod tests {
         assert_eq!(sign_extend(0x7F,8),0x7F);
 
     }
+
+    #[test]
+    fn test_decode_system_and_hints() {
+        use Operation::*;
+        assert_eq!(Operation::from_bytes(&0x00000073u32.to_le_bytes()), Ok(ECALL));
+        assert_eq!(Operation::from_bytes(&0x00100073u32.to_le_bytes()), Ok(EBREAK));
+        assert_eq!(Operation::from_bytes(&0x30200073u32.to_le_bytes()), Ok(MRET));
+        assert_eq!(Operation::from_bytes(&0x0ff0000fu32.to_le_bytes()), Ok(FENCE));
+        assert_eq!(Operation::from_bytes(&0x0000100fu32.to_le_bytes()), Ok(FENCEI));
+        assert_eq!(Operation::from_bytes(&0x00000013u32.to_le_bytes()), Ok(NOP));
+        // addi zero, a0, 1 is a hint, which does nothing but still decodes as itself
+        assert_eq!(Operation::from_bytes(&0x00150013u32.to_le_bytes()), Ok(ADDI(Register::Zero, Register::A0, 1)));
+        // lr.w.aq a0, (a1) and amoadd.w.aqrl a0, a2, (a1), from GNU as
+        assert_eq!(Operation::from_bytes(&0x1405a52fu32.to_le_bytes()), Ok(LRW(Register::A0, Register::A1)));
+        assert_eq!(Operation::from_bytes(&0x06c5a52fu32.to_le_bytes()), Ok(AMOADDW(Register::A0, Register::A1, Register::A2)));
+        // csrr a0, mhartid and csrwi mtvec, 4
+        assert_eq!(Operation::from_bytes(&0xf1402573u32.to_le_bytes()), Ok(CSRRS(Register::A0, Register::Zero, 0xF14)));
+        assert_eq!(Operation::from_bytes(&0x30525073u32.to_le_bytes()), Ok(CSRRWI(Register::Zero, 4, 0x305)));
+        // fadd.s fa0, fa1, fa2, rtz and fmadd.s fa0, fa1, fa2, fa3 from GNU as
+        assert_eq!(Operation::from_bytes(&0x00c59553u32.to_le_bytes()),
+                   Ok(FADDS(FRegister::FA0, FRegister::FA1, FRegister::FA2, RoundingMode::RTZ)));
+        assert_eq!(Operation::from_bytes(&0x68c5f543u32.to_le_bytes()),
+                   Ok(FMADDS(FRegister::FA0, FRegister::FA1, FRegister::FA2, FRegister::FA3, RoundingMode::DYN)));
+        // fmadd.d fa0, fa1, fa2, fa3 and fcvt.s.d fa0, fa1 from GNU as
+        assert_eq!(Operation::from_bytes(&0x6ac5f543u32.to_le_bytes()),
+                   Ok(FMADDD(FRegister::FA0, FRegister::FA1, FRegister::FA2, FRegister::FA3, RoundingMode::DYN)));
+        assert_eq!(Operation::from_bytes(&0x4015f553u32.to_le_bytes()),
+                   Ok(FCVTSD(FRegister::FA0, FRegister::FA1, RoundingMode::DYN)));
+        // Rounding mode 5 is reserved
+        assert_eq!(Operation::from_bytes(&0x00c5d553u32.to_le_bytes()), Err(ParseError::InvalidInstruction(0x00c5d553)));
+        // A branch forward by 2KiB, which needs bit 12 of the immediate as the sign
+        assert_eq!(Operation::from_bytes(&BEQ(Register::A0, Register::A1, 2048).to_bytes()),
+                   Ok(BEQ(Register::A0, Register::A1, 2048)));
+    }
+
+    use proptest::prelude::*;
+
+    type ImmOp = fn(Register, Register, Immediate) -> Operation;
+    type RegOp = fn(Register, Register, Register) -> Operation;
+    // Grouped by the range their immediate can take
+    const ALU_IMM: [ImmOp; 6] = [Operation::ADDI, Operation::SLTI, Operation::SLTIU,
+                                 Operation::ANDI, Operation::ORI, Operation::XORI];
+    const SHIFT_IMM: [ImmOp; 3] = [Operation::SLLI, Operation::SRLI, Operation::SRAI];
+    const LOADS: [ImmOp; 6] = [Operation::LB, Operation::LH, Operation::LW,
+                               Operation::LBU, Operation::LHU, Operation::JALR];
+    const STORES: [ImmOp; 3] = [Operation::SB, Operation::SH, Operation::SW];
+    const BRANCHES: [ImmOp; 6] = [Operation::BEQ, Operation::BNE, Operation::BLT,
+                                  Operation::BGE, Operation::BLTU, Operation::BGEU];
+    type CsrOp = fn(Register, Register, u16) -> Operation;
+    type CsrImmOp = fn(Register, Immediate, u16) -> Operation;
+    const CSR_OPS: [CsrOp; 3] = [Operation::CSRRW, Operation::CSRRS, Operation::CSRRC];
+    const CSR_IMM_OPS: [CsrImmOp; 3] = [Operation::CSRRWI, Operation::CSRRSI, Operation::CSRRCI];
+    const ATOMICS: [RegOp; 10] = [Operation::SCW, Operation::AMOSWAPW, Operation::AMOADDW,
+                                  Operation::AMOXORW, Operation::AMOANDW, Operation::AMOORW,
+                                  Operation::AMOMINW, Operation::AMOMAXW, Operation::AMOMINUW,
+                                  Operation::AMOMAXUW];
+    const REG_OPS: [RegOp; 18] = [Operation::ADD, Operation::SUB, Operation::SLL, Operation::SLT,
+                                  Operation::SLTU, Operation::XOR, Operation::SRL, Operation::SRA,
+                                  Operation::OR, Operation::AND, Operation::MUL, Operation::MULH,
+                                  Operation::MULSU, Operation::MULU, Operation::DIV, Operation::DIVU,
+                                  Operation::REM, Operation::REMU];
+    const BITMANIP_OPS: [RegOp; 19] = [Operation::SH1ADD, Operation::SH2ADD, Operation::SH3ADD,
+                                       Operation::ANDN, Operation::ORN, Operation::XNOR,
+                                       Operation::MAX, Operation::MAXU, Operation::MIN, Operation::MINU,
+                                       Operation::ROL, Operation::ROR, Operation::CLMUL,
+                                       Operation::CLMULH, Operation::CLMULR, Operation::BCLR,
+                                       Operation::BEXT, Operation::BINV, Operation::BSET];
+    const BITMANIP_IMM: [ImmOp; 5] = [Operation::RORI, Operation::BCLRI, Operation::BEXTI,
+                                      Operation::BINVI, Operation::BSETI];
+    const BITMANIP_UNARY: [fn(Register, Register) -> Operation; 8] =
+        [Operation::CLZ, Operation::CTZ, Operation::CPOP, Operation::SEXTB, Operation::SEXTH,
+         Operation::ZEXTH, Operation::ORCB, Operation::REV8];
+
+    fn any_reg() -> impl Strategy<Value = Register> {
+        (0u32..32).prop_map(|n| Register::from_num(n).unwrap())
+    }
+    type FpOp = fn(FRegister, FRegister, FRegister, RoundingMode) -> Operation;
+    type FmaOp = fn(FRegister, FRegister, FRegister, FRegister, RoundingMode) -> Operation;
+    type FpCompareOp = fn(Register, FRegister, FRegister) -> Operation;
+    type FpToIntOp = fn(Register, FRegister, RoundingMode) -> Operation;
+    type FpFromIntOp = fn(FRegister, Register, RoundingMode) -> Operation;
+    type FpUnaryOp = fn(FRegister, FRegister, RoundingMode) -> Operation;
+    const FP_OPS: [FpOp; 8] = [Operation::FADDS, Operation::FSUBS, Operation::FMULS, Operation::FDIVS,
+                               Operation::FADDD, Operation::FSUBD, Operation::FMULD, Operation::FDIVD];
+    const FMA_OPS: [FmaOp; 8] = [Operation::FMADDS, Operation::FMSUBS, Operation::FNMSUBS, Operation::FNMADDS,
+                                 Operation::FMADDD, Operation::FMSUBD, Operation::FNMSUBD, Operation::FNMADDD];
+    const FP_SIGN_OPS: [fn(FRegister, FRegister, FRegister) -> Operation; 10] =
+        [Operation::FSGNJS, Operation::FSGNJNS, Operation::FSGNJXS, Operation::FMINS, Operation::FMAXS,
+         Operation::FSGNJD, Operation::FSGNJND, Operation::FSGNJXD, Operation::FMIND, Operation::FMAXD];
+    const FP_COMPARES: [FpCompareOp; 6] = [Operation::FEQS, Operation::FLTS, Operation::FLES,
+                                           Operation::FEQD, Operation::FLTD, Operation::FLED];
+    const FP_TO_INT: [FpToIntOp; 4] = [Operation::FCVTWS, Operation::FCVTWUS, Operation::FCVTWD, Operation::FCVTWUD];
+    const FP_FROM_INT: [FpFromIntOp; 4] = [Operation::FCVTSW, Operation::FCVTSWU, Operation::FCVTDW, Operation::FCVTDWU];
+    const FP_UNARY: [FpUnaryOp; 4] = [Operation::FSQRTS, Operation::FSQRTD, Operation::FCVTSD, Operation::FCVTDS];
+
+    fn any_freg() -> impl Strategy<Value = FRegister> {
+        (0u32..32).prop_map(|n| FRegister::from_num(n).unwrap())
+    }
+    fn any_rm() -> impl Strategy<Value = RoundingMode> {
+        prop::sample::select(&[0, 1, 2, 3, 4, 7][..]).prop_map(|bits| RoundingMode::from_bits(bits).unwrap())
+    }
+    fn any_fp_operation() -> impl Strategy<Value = Operation> {
+        prop_oneof![
+            (any_freg(), any_reg(), -2048..2048).prop_map(|(rd, rs1, imm)| Operation::FLW(rd, rs1, imm)),
+            (any_reg(), any_freg(), -2048..2048).prop_map(|(rs1, rs2, imm)| Operation::FSW(rs1, rs2, imm)),
+            (0..FP_OPS.len(), any_freg(), any_freg(), any_freg(), any_rm())
+                .prop_map(|(i, rd, rs1, rs2, rm)| FP_OPS[i](rd, rs1, rs2, rm)),
+            (0..FMA_OPS.len(), any_freg(), any_freg(), any_freg(), any_freg(), any_rm())
+                .prop_map(|(i, rd, rs1, rs2, rs3, rm)| FMA_OPS[i](rd, rs1, rs2, rs3, rm)),
+            (0..FP_SIGN_OPS.len(), any_freg(), any_freg(), any_freg())
+                .prop_map(|(i, rd, rs1, rs2)| FP_SIGN_OPS[i](rd, rs1, rs2)),
+            (0..FP_COMPARES.len(), any_reg(), any_freg(), any_freg())
+                .prop_map(|(i, rd, rs1, rs2)| FP_COMPARES[i](rd, rs1, rs2)),
+            (0..FP_TO_INT.len(), any_reg(), any_freg(), any_rm())
+                .prop_map(|(i, rd, rs1, rm)| FP_TO_INT[i](rd, rs1, rm)),
+            (0..FP_FROM_INT.len(), any_freg(), any_reg(), any_rm())
+                .prop_map(|(i, rd, rs1, rm)| FP_FROM_INT[i](rd, rs1, rm)),
+            (0..FP_UNARY.len(), any_freg(), any_freg(), any_rm())
+                .prop_map(|(i, rd, rs1, rm)| FP_UNARY[i](rd, rs1, rm)),
+            (any_freg(), any_reg(), -2048..2048).prop_map(|(rd, rs1, imm)| Operation::FLD(rd, rs1, imm)),
+            (any_reg(), any_freg(), -2048..2048).prop_map(|(rs1, rs2, imm)| Operation::FSD(rs1, rs2, imm)),
+            (any_reg(), any_freg()).prop_map(|(rd, rs1)| Operation::FMVXW(rd, rs1)),
+            (any_reg(), any_freg()).prop_map(|(rd, rs1)| Operation::FCLASSS(rd, rs1)),
+            (any_reg(), any_freg()).prop_map(|(rd, rs1)| Operation::FCLASSD(rd, rs1)),
+            (any_freg(), any_reg()).prop_map(|(rd, rs1)| Operation::FMVWX(rd, rs1)),
+        ]
+    }
+    fn any_operation() -> impl Strategy<Value = Operation> {
+        prop_oneof![
+            (0..ALU_IMM.len(), any_reg(), any_reg(), -2048..2048)
+                .prop_map(|(i, rd, rs1, imm)| ALU_IMM[i](rd, rs1, imm)),
+            (0..SHIFT_IMM.len(), any_reg(), any_reg(), 0..32)
+                .prop_map(|(i, rd, rs1, shamt)| SHIFT_IMM[i](rd, rs1, shamt)),
+            (0..LOADS.len(), any_reg(), any_reg(), -2048..2048)
+                .prop_map(|(i, rd, rs1, imm)| LOADS[i](rd, rs1, imm)),
+            (0..STORES.len(), any_reg(), any_reg(), -2048..2048)
+                .prop_map(|(i, rs1, rs2, imm)| STORES[i](rs1, rs2, imm)),
+            (0..BRANCHES.len(), any_reg(), any_reg(), -2048..2048)
+                .prop_map(|(i, rs1, rs2, imm)| BRANCHES[i](rs1, rs2, imm * 2)),
+            (0..REG_OPS.len(), any_reg(), any_reg(), any_reg())
+                .prop_map(|(i, rd, rs1, rs2)| REG_OPS[i](rd, rs1, rs2)),
+            (0..ATOMICS.len(), any_reg(), any_reg(), any_reg())
+                .prop_map(|(i, rd, rs1, rs2)| ATOMICS[i](rd, rs1, rs2)),
+            (any_reg(), any_reg()).prop_map(|(rd, rs1)| Operation::LRW(rd, rs1)),
+            (0..BITMANIP_OPS.len(), any_reg(), any_reg(), any_reg())
+                .prop_map(|(i, rd, rs1, rs2)| BITMANIP_OPS[i](rd, rs1, rs2)),
+            (0..BITMANIP_IMM.len(), any_reg(), any_reg(), 0..32)
+                .prop_map(|(i, rd, rs1, shamt)| BITMANIP_IMM[i](rd, rs1, shamt)),
+            (0..BITMANIP_UNARY.len(), any_reg(), any_reg())
+                .prop_map(|(i, rd, rs1)| BITMANIP_UNARY[i](rd, rs1)),
+            (any_reg(), -(1 << 19)..(1 << 19)).prop_map(|(rd, imm)| Operation::LUI(rd, imm << 12)),
+            (any_reg(), -(1 << 19)..(1 << 19)).prop_map(|(rd, imm)| Operation::AUIPC(rd, imm << 12)),
+            (any_reg(), -(1 << 19)..(1 << 19)).prop_map(|(rd, imm)| Operation::JAL(rd, imm * 2)),
+            (0..CSR_OPS.len(), any_reg(), any_reg(), 0u16..0x1000)
+                .prop_map(|(i, rd, rs1, csr)| CSR_OPS[i](rd, rs1, csr)),
+            (0..CSR_IMM_OPS.len(), any_reg(), 0..32, 0u16..0x1000)
+                .prop_map(|(i, rd, uimm, csr)| CSR_IMM_OPS[i](rd, uimm, csr)),
+            Just(Operation::ECALL),
+            Just(Operation::EBREAK),
+            Just(Operation::MRET),
+            Just(Operation::FENCE),
+            Just(Operation::FENCEI),
+            Just(Operation::NOP),
+            any_fp_operation(),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn encode_decode_round_trip(op in any_operation()) {
+            // `addi zero, zero, 0` is the one encoding with two names, and it decodes as NOP
+            let expected = if op == Operation::ADDI(Register::Zero, Register::Zero, 0) { Operation::NOP } else { op.clone() };
+            prop_assert_eq!(Operation::from_bytes(&op.to_bytes()), Ok(expected));
+        }
+    }
+
+    #[test]
+    fn test_display_with_symbols() {
+        use crate::symbols::{Symbol, SymbolKind};
+        let symbols = SymbolTable::new(vec![
+            Symbol { name: "loop".to_string(), addr: 0x10, size: 0, kind: SymbolKind::Other }
+        ]);
+        let op = Operation::BNE(Register::A0, Register::Zero, -8);
+        assert_eq!(format!("{}", op.display_with(0x18, &symbols)), "BNE   A0, ZERO, 0xfffffff8 <loop>");
+        assert_eq!(format!("{}", op.display_with(0x8, &symbols)), "BNE   A0, ZERO, 0xfffffff8");
+    }
 }
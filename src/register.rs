@@ -1,6 +1,7 @@
 use std::ops::Index;
 use strum::EnumString;
 use std::fmt;
+use std::str::FromStr;
 
 #[derive(PartialEq, Debug, EnumString,Clone,Copy)]
 #[strum(ascii_case_insensitive)]
@@ -143,6 +144,44 @@ impl fmt::Display for Register {
     }
 }
 
+/// The floating point registers, in order, by their ABI names
+#[derive(PartialEq, Debug, EnumString,Clone,Copy)]
+#[strum(ascii_case_insensitive)]
+pub enum FRegister {
+    FT0, FT1, FT2, FT3, FT4, FT5, FT6, FT7,
+    FS0, FS1,
+    FA0, FA1, FA2, FA3, FA4, FA5, FA6, FA7,
+    FS2, FS3, FS4, FS5, FS6, FS7, FS8, FS9, FS10, FS11,
+    FT8, FT9, FT10, FT11,
+}
+impl FRegister {
+    const ALL: [FRegister; 32] = {
+        use FRegister::*;
+        [FT0, FT1, FT2, FT3, FT4, FT5, FT6, FT7, FS0, FS1, FA0, FA1, FA2, FA3, FA4, FA5, FA6, FA7,
+         FS2, FS3, FS4, FS5, FS6, FS7, FS8, FS9, FS10, FS11, FT8, FT9, FT10, FT11]
+    };
+    pub fn to_num(self) -> usize {
+        self as usize
+    }
+    pub fn from_num(num: u32) -> Option<FRegister> {
+        Self::ALL.get(num as usize).copied()
+    }
+    /// Parse a register by ABI name (`fa0`) or number (`f10`)
+    pub fn parse(s: &str) -> Option<FRegister> {
+        let s = s.trim();
+        s.strip_prefix(['f', 'F'])
+            .and_then(|n| n.parse::<u32>().ok())
+            .and_then(FRegister::from_num)
+            .or_else(|| FRegister::from_str(s).ok())
+    }
+}
+
+impl fmt::Display for FRegister {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f,"{:?}",self)
+    }
+}
+
 // We don't really ever want to return an &mut for registor Zero
 // so this doesn't work
 //impl<T> IndexMut<Register> for [T] {
@@ -160,6 +199,10 @@ mod tests {
     fn test_reg_num_conversion() {
         for i in 0..32 {
             assert_eq!(i, Register::from_num(i as u32).unwrap().to_num());
+            assert_eq!(i, FRegister::from_num(i as u32).unwrap().to_num());
         }
+        assert_eq!(FRegister::parse("f10"), Some(FRegister::FA0));
+        assert_eq!(FRegister::parse("fs11"), Some(FRegister::FS11));
+        assert_eq!(FRegister::parse("f32"), None);
     }
 }
@@ -0,0 +1,673 @@
+//! IEEE-754 binary floating point in software, so that every rounding mode and exception flag
+//! the F and D extensions ask for comes out exactly right. The host's floats only round to
+//! nearest and don't report flags.
+//!
+//! Values are passed around as their raw bits in a u64, with single precision values in the low
+//! 32 bits. Every operation takes the flags to accumulate into, and returns the canonical NaN
+//! whenever the result is a NaN, as RISC-V does.
+
+use std::cmp::Ordering;
+use std::fmt;
+
+/// The rounding modes, as encoded in the `rm` field of an instruction and in `frm`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RoundingMode {
+    /// Round to nearest, ties to even
+    RNE,
+    /// Round towards zero
+    RTZ,
+    /// Round down, towards negative infinity
+    RDN,
+    /// Round up, towards positive infinity
+    RUP,
+    /// Round to nearest, ties to max magnitude
+    RMM,
+    /// Use the mode in `frm`, only valid in an instruction
+    DYN,
+}
+
+impl RoundingMode {
+    /// Decode the 3-bit field, 5 and 6 are reserved
+    pub fn from_bits(bits: u32) -> Option<Self> {
+        use RoundingMode::*;
+        match bits {
+            0 => Some(RNE),
+            1 => Some(RTZ),
+            2 => Some(RDN),
+            3 => Some(RUP),
+            4 => Some(RMM),
+            7 => Some(DYN),
+            _ => None,
+        }
+    }
+
+    /// Look up a mode by the name assemblers use for it, like `rtz`
+    pub fn from_name(name: &str) -> Option<Self> {
+        use RoundingMode::*;
+        [RNE, RTZ, RDN, RUP, RMM, DYN]
+            .into_iter()
+            .find(|rm| rm.to_string().eq_ignore_ascii_case(name))
+    }
+
+    pub fn to_bits(self) -> u32 {
+        use RoundingMode::*;
+        match self {
+            RNE => 0,
+            RTZ => 1,
+            RDN => 2,
+            RUP => 3,
+            RMM => 4,
+            DYN => 7,
+        }
+    }
+}
+
+impl fmt::Display for RoundingMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", format!("{self:?}").to_lowercase())
+    }
+}
+
+// Exception flags, as they sit in `fflags`
+/// Inexact
+pub const NX: u32 = 1 << 0;
+/// Underflow
+pub const UF: u32 = 1 << 1;
+/// Overflow
+pub const OF: u32 = 1 << 2;
+/// Divide by zero
+pub const DZ: u32 = 1 << 3;
+/// Invalid operation
+pub const NV: u32 = 1 << 4;
+
+/// A binary interchange format
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Format {
+    exp_bits: u32,
+    frac_bits: u32,
+}
+
+pub const SINGLE: Format = Format { exp_bits: 8, frac_bits: 23 };
+pub const DOUBLE: Format = Format { exp_bits: 11, frac_bits: 52 };
+
+/// A value split into its parts. Finite values are `mant * 2^exp` with a non-zero mantissa.
+#[derive(Debug, Clone, Copy)]
+enum Value {
+    Zero(bool),
+    Inf(bool),
+    NaN { signaling: bool },
+    Finite { sign: bool, exp: i32, mant: u128 },
+}
+
+/// Where mantissas get normalized to before rounding, so there is plenty of room below the
+/// rounding point for a sticky bit
+const WORKING_MSB: u32 = 125;
+
+/// Round `mant` shifted right by `shift`, returning the rounded value and whether anything
+/// non-zero was shifted out. The lowest bit of `mant` may be a sticky bit standing in for
+/// anything smaller.
+fn round_shift(mant: u128, shift: u32, sign: bool, rm: RoundingMode) -> (u128, bool) {
+    use RoundingMode::*;
+    if shift == 0 {
+        return (mant, false);
+    }
+    // Everything is shifted out, which rounds the same as a lone sticky bit
+    let (mant, shift) = if shift > 126 { (1, 2) } else { (mant, shift) };
+    let quotient = mant >> shift;
+    let rem = mant & ((1 << shift) - 1);
+    let half = 1 << (shift - 1);
+    let round_up = match rm {
+        RNE | DYN => rem > half || (rem == half && quotient & 1 == 1),
+        RMM => rem >= half,
+        RTZ => false,
+        RDN => sign && rem != 0,
+        RUP => !sign && rem != 0,
+    };
+    (quotient + round_up as u128, rem != 0)
+}
+
+fn msb(mant: u128) -> u32 {
+    127 - mant.leading_zeros()
+}
+
+/// Shift right, ORing anything shifted out into the lowest bit
+fn shift_right_jam(mant: u128, shift: u32) -> u128 {
+    if shift == 0 {
+        mant
+    } else if shift > 127 {
+        (mant != 0) as u128
+    } else {
+        (mant >> shift) | (mant & ((1 << shift) - 1) != 0) as u128
+    }
+}
+
+/// Move the top bit of a non-zero mantissa to `WORKING_MSB`, returning the mantissa and exponent
+fn normalize(exp: i32, mant: u128) -> (i32, u128) {
+    let top = msb(mant);
+    if top < WORKING_MSB {
+        let shift = WORKING_MSB - top;
+        (exp - shift as i32, mant << shift)
+    } else {
+        let shift = top - WORKING_MSB;
+        (exp + shift as i32, shift_right_jam(mant, shift))
+    }
+}
+
+fn isqrt(n: u128) -> (u128, bool) {
+    let mut rem = n;
+    let mut root = 0u128;
+    let mut bit = 1u128 << 126;
+    while bit > n {
+        bit >>= 2;
+    }
+    while bit != 0 {
+        if rem >= root + bit {
+            rem -= root + bit;
+            root = (root >> 1) + bit;
+        } else {
+            root >>= 1;
+        }
+        bit >>= 2;
+    }
+    (root, rem == 0)
+}
+
+impl Format {
+    fn bias(self) -> i32 {
+        (1 << (self.exp_bits - 1)) - 1
+    }
+    fn exp_max(self) -> u64 {
+        (1 << self.exp_bits) - 1
+    }
+    fn frac_mask(self) -> u64 {
+        (1 << self.frac_bits) - 1
+    }
+    pub fn sign_bit(self) -> u64 {
+        1 << (self.exp_bits + self.frac_bits)
+    }
+    /// The NaN RISC-V hands back for any operation with a NaN result
+    pub fn canonical_nan(self) -> u64 {
+        (self.exp_max() << self.frac_bits) | (1 << (self.frac_bits - 1))
+    }
+    fn zero(self, sign: bool) -> u64 {
+        if sign { self.sign_bit() } else { 0 }
+    }
+    fn inf(self, sign: bool) -> u64 {
+        self.zero(sign) | (self.exp_max() << self.frac_bits)
+    }
+    fn max_finite(self, sign: bool) -> u64 {
+        self.inf(sign) - 1
+    }
+
+    fn unpack(self, bits: u64) -> Value {
+        let sign = bits & self.sign_bit() != 0;
+        let exp = (bits >> self.frac_bits) & self.exp_max();
+        let frac = bits & self.frac_mask();
+        if exp == self.exp_max() {
+            if frac == 0 {
+                Value::Inf(sign)
+            } else {
+                Value::NaN { signaling: frac >> (self.frac_bits - 1) == 0 }
+            }
+        } else if exp == 0 {
+            if frac == 0 {
+                Value::Zero(sign)
+            } else {
+                Value::Finite { sign, exp: 1 - self.bias() - self.frac_bits as i32, mant: frac as u128 }
+            }
+        } else {
+            let mant = (frac | (1 << self.frac_bits)) as u128;
+            Value::Finite { sign, exp: exp as i32 - self.bias() - self.frac_bits as i32, mant }
+        }
+    }
+
+    /// Round `mant * 2^exp` to this format. `mant` can't be zero.
+    fn round_pack(self, sign: bool, exp: i32, mant: u128, rm: RoundingMode, flags: &mut u32) -> u64 {
+        let (exp, mant) = normalize(exp, mant);
+        let biased = exp + WORKING_MSB as i32 + self.bias();
+        let normal_shift = WORKING_MSB - self.frac_bits;
+        if biased >= 1 {
+            let (mut rounded, inexact) = round_shift(mant, normal_shift, sign, rm);
+            let mut biased = biased as u64;
+            // Rounding can carry all the way up into the next binade
+            if rounded >> (self.frac_bits + 1) != 0 {
+                rounded >>= 1;
+                biased += 1;
+            }
+            if biased >= self.exp_max() {
+                *flags |= OF | NX;
+                return match rm {
+                    RoundingMode::RTZ => self.max_finite(sign),
+                    RoundingMode::RDN if !sign => self.max_finite(sign),
+                    RoundingMode::RUP if sign => self.max_finite(sign),
+                    _ => self.inf(sign),
+                };
+            }
+            if inexact {
+                *flags |= NX;
+            }
+            self.zero(sign) | (biased << self.frac_bits) | (rounded as u64 & self.frac_mask())
+        } else {
+            // Tininess is detected after rounding, so a value that only reaches the smallest
+            // normal once rounded with an unbounded exponent isn't tiny
+            let (unbounded, _) = round_shift(mant, normal_shift, sign, rm);
+            let tiny = biased < 0 || unbounded >> (self.frac_bits + 1) == 0;
+            let shift = normal_shift + (1 - biased) as u32;
+            let (rounded, inexact) = round_shift(mant, shift, sign, rm);
+            if inexact {
+                *flags |= NX;
+                if tiny {
+                    *flags |= UF;
+                }
+            }
+            // Rounding up into the smallest normal sets the exponent field by itself
+            self.zero(sign) | rounded as u64
+        }
+    }
+
+    /// Any NaN result, flagging an invalid operation if one of the inputs is signaling
+    fn nan_result(self, inputs: &[Value], flags: &mut u32) -> u64 {
+        if inputs.iter().any(|v| matches!(v, Value::NaN { signaling: true })) {
+            *flags |= NV;
+        }
+        self.canonical_nan()
+    }
+
+    fn invalid(self, flags: &mut u32) -> u64 {
+        *flags |= NV;
+        self.canonical_nan()
+    }
+
+    fn add_values(self, a: Value, b: Value, rm: RoundingMode, flags: &mut u32) -> u64 {
+        use Value::*;
+        match (a, b) {
+            (NaN { .. }, _) | (_, NaN { .. }) => self.nan_result(&[a, b], flags),
+            (Inf(sa), Inf(sb)) if sa != sb => self.invalid(flags),
+            (Inf(sign), _) | (_, Inf(sign)) => self.inf(sign),
+            // The sum of opposite zeros is only negative when rounding down
+            (Zero(sa), Zero(sb)) => self.zero(if sa == sb { sa } else { rm == RoundingMode::RDN }),
+            (Zero(_), Finite { sign, exp, mant }) | (Finite { sign, exp, mant }, Zero(_)) => {
+                self.round_pack(sign, exp, mant, rm, flags)
+            }
+            (Finite { sign: sa, exp: ea, mant: ma }, Finite { sign: sb, exp: eb, mant: mb }) => {
+                let (ea, ma) = normalize(ea, ma);
+                let (eb, mb) = normalize(eb, mb);
+                // Line the smaller one up with the larger, anything shifted out only matters as a
+                // sticky bit
+                let ((sa, ea, ma), (sb, _, mb)) = if ea >= eb {
+                    ((sa, ea, ma), (sb, eb, shift_right_jam(mb, (ea - eb) as u32)))
+                } else {
+                    ((sb, eb, mb), (sa, ea, shift_right_jam(ma, (eb - ea) as u32)))
+                };
+                if sa == sb {
+                    self.round_pack(sa, ea, ma + mb, rm, flags)
+                } else {
+                    match ma.cmp(&mb) {
+                        Ordering::Greater => self.round_pack(sa, ea, ma - mb, rm, flags),
+                        Ordering::Less => self.round_pack(sb, ea, mb - ma, rm, flags),
+                        Ordering::Equal => self.zero(rm == RoundingMode::RDN),
+                    }
+                }
+            }
+        }
+    }
+
+    /// The exact product of two values, which is only rounded once it is used
+    fn mul_values(self, a: Value, b: Value, flags: &mut u32) -> Result<Value, u64> {
+        use Value::*;
+        Ok(match (a, b) {
+            (NaN { .. }, _) | (_, NaN { .. }) => return Err(self.nan_result(&[a, b], flags)),
+            (Inf(_), Zero(_)) | (Zero(_), Inf(_)) => return Err(self.invalid(flags)),
+            (Inf(sa), Inf(sb) | Finite { sign: sb, .. }) | (Finite { sign: sa, .. }, Inf(sb)) => Inf(sa != sb),
+            (Zero(sa), Zero(sb) | Finite { sign: sb, .. }) | (Finite { sign: sa, .. }, Zero(sb)) => {
+                Zero(sa != sb)
+            }
+            (Finite { sign: sa, exp: ea, mant: ma }, Finite { sign: sb, exp: eb, mant: mb }) => {
+                Finite { sign: sa != sb, exp: ea + eb, mant: ma * mb }
+            }
+        })
+    }
+
+    fn pack_value(self, value: Value, rm: RoundingMode, flags: &mut u32) -> u64 {
+        match value {
+            Value::Zero(sign) => self.zero(sign),
+            Value::Inf(sign) => self.inf(sign),
+            Value::NaN { .. } => self.canonical_nan(),
+            Value::Finite { sign, exp, mant } => self.round_pack(sign, exp, mant, rm, flags),
+        }
+    }
+
+    pub fn add(self, a: u64, b: u64, rm: RoundingMode, flags: &mut u32) -> u64 {
+        self.add_values(self.unpack(a), self.unpack(b), rm, flags)
+    }
+
+    pub fn sub(self, a: u64, b: u64, rm: RoundingMode, flags: &mut u32) -> u64 {
+        self.add(a, b ^ self.sign_bit(), rm, flags)
+    }
+
+    pub fn mul(self, a: u64, b: u64, rm: RoundingMode, flags: &mut u32) -> u64 {
+        match self.mul_values(self.unpack(a), self.unpack(b), flags) {
+            Ok(product) => self.pack_value(product, rm, flags),
+            Err(nan) => nan,
+        }
+    }
+
+    /// `a * b + c` with a single rounding. Negate the inputs to get the other three forms.
+    pub fn fma(self, a: u64, b: u64, c: u64, rm: RoundingMode, flags: &mut u32) -> u64 {
+        let addend = self.unpack(c);
+        match self.mul_values(self.unpack(a), self.unpack(b), flags) {
+            // A NaN addend is only a NaN result, unless the product was invalid by itself
+            Ok(product) => self.add_values(product, addend, rm, flags),
+            Err(nan) => {
+                if let Value::NaN { signaling: true } = addend {
+                    *flags |= NV;
+                }
+                nan
+            }
+        }
+    }
+
+    pub fn div(self, a: u64, b: u64, rm: RoundingMode, flags: &mut u32) -> u64 {
+        use Value::*;
+        let (a, b) = (self.unpack(a), self.unpack(b));
+        match (a, b) {
+            (NaN { .. }, _) | (_, NaN { .. }) => self.nan_result(&[a, b], flags),
+            (Inf(_), Inf(_)) | (Zero(_), Zero(_)) => self.invalid(flags),
+            (Inf(sa), Zero(sb) | Finite { sign: sb, .. }) => self.inf(sa != sb),
+            (Zero(sa) | Finite { sign: sa, .. }, Inf(sb)) | (Zero(sa), Finite { sign: sb, .. }) => {
+                self.zero(sa != sb)
+            }
+            (Finite { sign: sa, .. }, Zero(sb)) => {
+                *flags |= DZ;
+                self.inf(sa != sb)
+            }
+            (Finite { sign: sa, exp: ea, mant: ma }, Finite { sign: sb, exp: eb, mant: mb }) => {
+                let (ea, ma) = normalize(ea, ma);
+                let quotient = ma / mb;
+                // One extra bit at the bottom for whether there was a remainder
+                let mant = (quotient << 1) | (ma % mb != 0) as u128;
+                self.round_pack(sa != sb, ea - eb - 1, mant, rm, flags)
+            }
+        }
+    }
+
+    pub fn sqrt(self, a: u64, rm: RoundingMode, flags: &mut u32) -> u64 {
+        use Value::*;
+        let a = self.unpack(a);
+        match a {
+            NaN { .. } => self.nan_result(&[a], flags),
+            Zero(sign) => self.zero(sign),
+            Inf(false) => self.inf(false),
+            Inf(true) | Finite { sign: true, .. } => self.invalid(flags),
+            Finite { sign: false, exp, mant } => {
+                // The exponent has to be even to be halved
+                let (mut exp, mut mant) = normalize(exp, mant);
+                if exp & 1 != 0 {
+                    mant <<= 1;
+                    exp -= 1;
+                }
+                let (root, exact) = isqrt(mant);
+                let mant = (root << 1) | !exact as u128;
+                self.round_pack(false, exp / 2 - 1, mant, rm, flags)
+            }
+        }
+    }
+
+    /// IEEE minimumNumber and maximumNumber: a single NaN input is ignored, and -0 is less than +0
+    pub fn min_max(self, a: u64, b: u64, max: bool, flags: &mut u32) -> u64 {
+        let (va, vb) = (self.unpack(a), self.unpack(b));
+        match (va, vb) {
+            (Value::NaN { .. }, Value::NaN { .. }) => self.nan_result(&[va, vb], flags),
+            (Value::NaN { .. }, _) | (_, Value::NaN { .. }) => {
+                self.nan_result(&[va, vb], flags);
+                if let Value::NaN { .. } = va { b } else { a }
+            }
+            _ => {
+                let a_less = self.ordered_key(a) < self.ordered_key(b)
+                    || (self.ordered_key(a) == self.ordered_key(b) && a & self.sign_bit() != 0);
+                if a_less != max { a } else { b }
+            }
+        }
+    }
+
+    /// An integer that sorts the same as the value, for anything but NaNs. Both zeros map to 0.
+    fn ordered_key(self, bits: u64) -> i128 {
+        let magnitude = (bits & !self.sign_bit()) as i128;
+        if bits & self.sign_bit() != 0 { -magnitude } else { magnitude }
+    }
+
+    /// Compare two values, with `None` if either is a NaN. A quiet comparison only flags signaling
+    /// NaNs as invalid, a signaling one flags any NaN.
+    pub fn compare(self, a: u64, b: u64, quiet: bool, flags: &mut u32) -> Option<Ordering> {
+        let (va, vb) = (self.unpack(a), self.unpack(b));
+        match (va, vb) {
+            (Value::NaN { .. }, _) | (_, Value::NaN { .. }) => {
+                if quiet {
+                    self.nan_result(&[va, vb], flags);
+                } else {
+                    *flags |= NV;
+                }
+                None
+            }
+            _ => Some(self.ordered_key(a).cmp(&self.ordered_key(b))),
+        }
+    }
+
+    /// The 10-bit mask FCLASS produces, with one bit set for the kind of value
+    pub fn classify(self, bits: u64) -> u32 {
+        let sign = bits & self.sign_bit() != 0;
+        let exp = (bits >> self.frac_bits) & self.exp_max();
+        let bit = match self.unpack(bits) {
+            Value::Inf(_) => if sign { 0 } else { 7 },
+            Value::Finite { .. } if exp != 0 => if sign { 1 } else { 6 },
+            Value::Finite { .. } => if sign { 2 } else { 5 },
+            Value::Zero(_) => if sign { 3 } else { 4 },
+            Value::NaN { signaling: true } => 8,
+            Value::NaN { signaling: false } => 9,
+        };
+        1 << bit
+    }
+
+    /// Convert to a 32-bit integer. Out of range values and NaNs saturate and flag an invalid
+    /// operation, with NaNs going to the largest value.
+    pub fn float_to_int(self, bits: u64, signed: bool, rm: RoundingMode, flags: &mut u32) -> u32 {
+        let (min, max) = if signed { (i32::MIN as u32, i32::MAX as u32) } else { (0, u32::MAX) };
+        let (sign, magnitude, inexact) = match self.unpack(bits) {
+            Value::NaN { .. } => {
+                *flags |= NV;
+                return max;
+            }
+            Value::Inf(sign) => (sign, u128::MAX, false),
+            Value::Zero(_) => return 0,
+            // Anything this large is out of range anyway
+            Value::Finite { sign, exp, .. } if exp > 64 => (sign, u128::MAX, false),
+            Value::Finite { sign, exp, mant } if exp >= 0 => (sign, mant << exp, false),
+            Value::Finite { sign, exp, mant } => {
+                let (magnitude, inexact) = round_shift(mant, (-exp) as u32, sign, rm);
+                (sign, magnitude, inexact)
+            }
+        };
+        let limit = if sign { (min as i32).unsigned_abs() as u128 } else { max as u128 };
+        if magnitude > limit {
+            *flags |= NV;
+            return if sign { min } else { max };
+        }
+        if inexact {
+            *flags |= NX;
+        }
+        if sign { (magnitude as u32).wrapping_neg() } else { magnitude as u32 }
+    }
+
+    /// Convert a value in another format to this one
+    pub fn convert(self, from: Format, bits: u64, rm: RoundingMode, flags: &mut u32) -> u64 {
+        match from.unpack(bits) {
+            value @ Value::NaN { .. } => self.nan_result(&[value], flags),
+            value => self.pack_value(value, rm, flags),
+        }
+    }
+
+    /// Convert from a 32-bit integer
+    pub fn int_to_float(self, value: u32, signed: bool, rm: RoundingMode, flags: &mut u32) -> u64 {
+        let sign = signed && (value as i32) < 0;
+        let magnitude = if sign { (value as i32).unsigned_abs() } else { value };
+        if magnitude == 0 {
+            0
+        } else {
+            self.round_pack(sign, 0, magnitude as u128, rm, flags)
+        }
+    }
+}
+
+/// Whether a floating point register holds a properly NaN-boxed single, with the upper 32 bits all
+/// set
+pub fn is_boxed(bits: u64) -> bool {
+    bits >> 32 == 0xFFFF_FFFF
+}
+
+/// Show the value in a floating point register. A NaN-boxed single is shown as a single, anything
+/// else as a double.
+pub fn describe(bits: u64) -> String {
+    if is_boxed(bits) {
+        format!("{:?}", f32::from_bits(bits as u32))
+    } else {
+        format!("{:?}", f64::from_bits(bits))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use RoundingMode::*;
+
+    fn f(value: f32) -> u64 {
+        value.to_bits() as u64
+    }
+
+    fn d(value: f64) -> u64 {
+        value.to_bits()
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let mut flags = 0;
+        assert_eq!(SINGLE.add(f(1.5), f(2.25), RNE, &mut flags), f(3.75));
+        assert_eq!(SINGLE.sub(f(1.0), f(1.0), RNE, &mut flags), f(0.0));
+        assert_eq!(SINGLE.sub(f(1.0), f(1.0), RDN, &mut flags), f(-0.0));
+        assert_eq!(SINGLE.mul(f(-3.0), f(0.5), RNE, &mut flags), f(-1.5));
+        assert_eq!(SINGLE.div(f(1.0), f(4.0), RNE, &mut flags), f(0.25));
+        assert_eq!(SINGLE.sqrt(f(2.25), RNE, &mut flags), f(1.5));
+        assert_eq!(SINGLE.fma(f(2.0), f(3.0), f(-1.0), RNE, &mut flags), f(5.0));
+        assert_eq!(flags, 0);
+        assert_eq!(SINGLE.add(f(0.1), f(0.2), RNE, &mut flags), f(0.1 + 0.2));
+        assert_eq!(flags, NX);
+        flags = 0;
+        assert_eq!(DOUBLE.add(d(0.1), d(0.2), RNE, &mut flags), d(0.1 + 0.2));
+        assert_eq!(DOUBLE.div(d(1.0), d(3.0), RNE, &mut flags), d(1.0 / 3.0));
+        assert_eq!(DOUBLE.sqrt(d(2.0), RNE, &mut flags), d(2f64.sqrt()));
+        assert_eq!(DOUBLE.fma(d(0.1), d(10.0), d(-1.0), RNE, &mut flags), d(0.1f64.mul_add(10.0, -1.0)));
+        assert_eq!(flags, NX);
+    }
+
+    #[test]
+    fn test_rounding_modes() {
+        // 1/3 sits between two singles, RNE and RUP round up and the rest round down
+        let third = |rm| {
+            let mut flags = 0;
+            let result = SINGLE.div(f(1.0), f(3.0), rm, &mut flags);
+            assert_eq!(flags, NX);
+            result
+        };
+        assert_eq!(third(RNE), f(1.0 / 3.0));
+        assert_eq!(third(RUP), f(1.0 / 3.0));
+        assert_eq!(third(RTZ), f(1.0 / 3.0) - 1);
+        assert_eq!(third(RDN), f(1.0 / 3.0) - 1);
+        // A tie, 1 + 2^-24 is halfway between 1 and the next single up
+        let mut flags = 0;
+        assert_eq!(SINGLE.add(f(1.0), f(2f32.powi(-24)), RNE, &mut flags), f(1.0));
+        assert_eq!(SINGLE.add(f(1.0), f(2f32.powi(-24)), RMM, &mut flags), f(1.0) + 1);
+    }
+
+    #[test]
+    fn test_exceptions() {
+        let mut flags = 0;
+        assert_eq!(SINGLE.div(f(1.0), f(0.0), RNE, &mut flags), f(f32::INFINITY));
+        assert_eq!(flags, DZ);
+        flags = 0;
+        assert_eq!(SINGLE.sqrt(f(-1.0), RNE, &mut flags), SINGLE.canonical_nan());
+        assert_eq!(flags, NV);
+        flags = 0;
+        assert_eq!(SINGLE.mul(f(f32::MAX), f(2.0), RNE, &mut flags), f(f32::INFINITY));
+        assert_eq!(SINGLE.mul(f(f32::MAX), f(2.0), RTZ, &mut flags), f(f32::MAX));
+        assert_eq!(flags, OF | NX);
+        flags = 0;
+        // The smallest subnormal halved is a tie that rounds to zero
+        assert_eq!(SINGLE.mul(1, f(0.5), RNE, &mut flags), 0);
+        assert_eq!(flags, UF | NX);
+        flags = 0;
+        // Exact subnormal results don't underflow
+        assert_eq!(SINGLE.mul(2, f(0.5), RNE, &mut flags), 1);
+        assert_eq!(flags, 0);
+        // inf * 0 is invalid even with a quiet NaN to add
+        assert_eq!(SINGLE.fma(f(f32::INFINITY), 0, SINGLE.canonical_nan(), RNE, &mut flags), SINGLE.canonical_nan());
+        assert_eq!(flags, NV);
+    }
+
+    #[test]
+    fn test_nans() {
+        let snan = 0x7f800001;
+        let mut flags = 0;
+        assert_eq!(SINGLE.add(f(1.0), SINGLE.canonical_nan(), RNE, &mut flags), SINGLE.canonical_nan());
+        assert_eq!(flags, 0);
+        assert_eq!(SINGLE.min_max(f(1.0), SINGLE.canonical_nan(), false, &mut flags), f(1.0));
+        assert_eq!(SINGLE.min_max(f(-0.0), f(0.0), false, &mut flags), f(-0.0));
+        assert_eq!(SINGLE.min_max(f(-0.0), f(0.0), true, &mut flags), f(0.0));
+        assert_eq!(SINGLE.compare(f(1.0), SINGLE.canonical_nan(), true, &mut flags), None);
+        assert_eq!(flags, 0);
+        assert_eq!(SINGLE.compare(f(1.0), SINGLE.canonical_nan(), false, &mut flags), None);
+        assert_eq!(flags, NV);
+        flags = 0;
+        assert_eq!(SINGLE.min_max(snan, f(2.0), true, &mut flags), f(2.0));
+        assert_eq!(flags, NV);
+        assert_eq!(SINGLE.classify(snan), 1 << 8);
+        assert_eq!(SINGLE.classify(f(-0.0)), 1 << 3);
+        assert_eq!(SINGLE.classify(1), 1 << 5);
+    }
+
+    #[test]
+    fn test_conversions() {
+        let mut flags = 0;
+        assert_eq!(SINGLE.float_to_int(f(-2.5), true, RNE, &mut flags), -2i32 as u32);
+        assert_eq!(SINGLE.float_to_int(f(-2.5), true, RMM, &mut flags), -3i32 as u32);
+        assert_eq!(SINGLE.float_to_int(f(2.5), false, RUP, &mut flags), 3);
+        assert_eq!(SINGLE.float_to_int(f(-0.5), false, RTZ, &mut flags), 0);
+        assert_eq!(flags, NX);
+        flags = 0;
+        assert_eq!(SINGLE.float_to_int(f(3e9), true, RNE, &mut flags), i32::MAX as u32);
+        assert_eq!(SINGLE.float_to_int(f(-1.0), false, RNE, &mut flags), 0);
+        assert_eq!(SINGLE.float_to_int(SINGLE.canonical_nan(), true, RNE, &mut flags), i32::MAX as u32);
+        assert_eq!(SINGLE.float_to_int(f(-2147483648.0), true, RNE, &mut flags), i32::MIN as u32);
+        assert_eq!(flags, NV);
+        flags = 0;
+        assert_eq!(SINGLE.int_to_float(-7i32 as u32, true, RNE, &mut flags), f(-7.0));
+        assert_eq!(SINGLE.int_to_float(u32::MAX, false, RNE, &mut flags), f(4294967296.0));
+        assert_eq!(flags, NX);
+        flags = 0;
+        // Every 32-bit integer fits in a double, and every single does too
+        assert_eq!(DOUBLE.int_to_float(u32::MAX, false, RNE, &mut flags), d(4294967295.0));
+        assert_eq!(DOUBLE.int_to_float(i32::MIN as u32, true, RNE, &mut flags), d(-2147483648.0));
+        assert_eq!(DOUBLE.convert(SINGLE, f(0.1), RNE, &mut flags), d(0.1f32 as f64));
+        assert_eq!(DOUBLE.convert(SINGLE, 1, RNE, &mut flags), d(f32::from_bits(1) as f64));
+        assert_eq!(flags, 0);
+        assert_eq!(DOUBLE.float_to_int(d(-4294967295.5), false, RNE, &mut flags), 0);
+        assert_eq!(flags, NV);
+        flags = 0;
+        assert_eq!(SINGLE.convert(DOUBLE, d(0.1), RNE, &mut flags), f(0.1));
+        assert_eq!(flags, NX);
+        assert_eq!(SINGLE.convert(DOUBLE, d(1e300), RNE, &mut flags), f(f32::INFINITY));
+        assert_eq!(SINGLE.convert(DOUBLE, d(1e-300), RUP, &mut flags), 1);
+        assert_eq!(flags, OF | UF | NX);
+        flags = 0;
+        assert_eq!(SINGLE.convert(DOUBLE, 0x7ff0_0000_0000_0001, RNE, &mut flags), SINGLE.canonical_nan());
+        assert_eq!(flags, NV);
+    }
+}
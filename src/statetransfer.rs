@@ -4,6 +4,8 @@ pub enum ControlCode {
   RUN,
   STOP,
   STEP,
+  STEPBACK,
+  RCONTINUE,
   RESET,
   LOAD {
     file: &'static str,
@@ -0,0 +1,152 @@
+use crate::ReadFileError;
+
+use std::collections::HashMap;
+
+/// How far past a symbol without a size an address can be and still be named after it. Labels
+/// from the assembler have no size, and without a limit the last one would name everything above
+/// it, right up through the stack and devices.
+const UNSIZED_REACH: u32 = 0x1000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SymbolKind {
+    Function,
+    Object,
+    Other,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub addr: u32,
+    pub size: u32,
+    pub kind: SymbolKind,
+}
+
+/// Lookup table from names to addresses and back
+#[derive(Debug, Default)]
+pub struct SymbolTable {
+    // Sorted by address so the symbol containing an address can be binary searched
+    symbols: Vec<Symbol>,
+    by_name: HashMap<String, u32>,
+}
+
+impl SymbolTable {
+    pub fn new(mut symbols: Vec<Symbol>) -> Self {
+        symbols.sort_by_key(|s| s.addr);
+        let by_name = symbols.iter().map(|s| (s.name.clone(), s.addr)).collect();
+        SymbolTable { symbols, by_name }
+    }
+
+    /// Parse an `nm`-style map, with lines of `ADDR TYPE NAME` or `ADDR SIZE TYPE NAME`
+    /// (as printed by `nm -S`). Undefined symbols, which have no address, are skipped.
+    pub fn parse_map(text: &str, file: &str) -> Result<Vec<Symbol>, ReadFileError> {
+        let mut symbols = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let (addr, size, kind, name) = match fields[..] {
+                [] | [_, _] => continue,
+                [addr, kind, name] => (addr, None, kind, name),
+                [addr, size, kind, name] => (addr, Some(size), kind, name),
+                _ => {
+                    return Err(ReadFileError::MalformedRecord {
+                        file: file.to_string(),
+                        line: index + 1,
+                        message: "expected `ADDR [SIZE] TYPE NAME`".to_string(),
+                    })
+                }
+            };
+            let hex = |s: &str| {
+                u32::from_str_radix(s, 16).map_err(|_| ReadFileError::MalformedRecord {
+                    file: file.to_string(),
+                    line: index + 1,
+                    message: format!("invalid hex `{s}`"),
+                })
+            };
+            let kind = match kind {
+                "T" | "t" | "W" | "w" => SymbolKind::Function,
+                "D" | "d" | "B" | "b" | "R" | "r" | "G" | "g" | "S" | "s" => SymbolKind::Object,
+                _ => SymbolKind::Other,
+            };
+            symbols.push(Symbol {
+                name: name.to_string(),
+                addr: hex(addr)?,
+                size: size.map(hex).transpose()?.unwrap_or(0),
+                kind,
+            });
+        }
+        Ok(symbols)
+    }
+
+    /// Every symbol, in address order
+    pub fn iter(&self) -> impl Iterator<Item = &Symbol> {
+        self.symbols.iter()
+    }
+
+    pub fn get(&self, name: &str) -> Option<u32> {
+        self.by_name.get(name).copied()
+    }
+
+    /// Resolve `name` or `name+offset`, where the offset is hex with an optional 0x
+    pub fn resolve(&self, s: &str) -> Option<u32> {
+        match s.split_once('+') {
+            Some((name, offset)) => {
+                let offset = offset.trim_start_matches("0x").trim_start_matches("0X");
+                let offset = u32::from_str_radix(offset, 16).ok()?;
+                Some(self.get(name)?.wrapping_add(offset))
+            }
+            None => self.get(s),
+        }
+    }
+
+    /// Describe an address relative to the symbol containing it, like `main+0x10`.
+    /// Symbols without a size are treated as running up to the next symbol, or for 4 KiB if
+    /// that is nearer.
+    pub fn symbolize(&self, addr: u32) -> Option<String> {
+        let index = self.symbols.partition_point(|s| s.addr <= addr).checked_sub(1)?;
+        let symbol = &self.symbols[index];
+        let offset = addr - symbol.addr;
+        let size = if symbol.size != 0 { symbol.size } else { UNSIZED_REACH };
+        if offset >= size {
+            return None;
+        }
+        if offset == 0 {
+            Some(symbol.name.clone())
+        } else {
+            Some(format!("{}+{offset:#x}", symbol.name))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table() -> SymbolTable {
+        let map = "00000010 T main\n00000040 00000008 T outs\n         U exit\nff000000 R FRAMEBUFFER\n";
+        SymbolTable::new(SymbolTable::parse_map(map, "test.map").unwrap())
+    }
+
+    #[test]
+    fn test_resolve() {
+        let symbols = table();
+        assert_eq!(symbols.resolve("main"), Some(0x10));
+        assert_eq!(symbols.resolve("outs+0x4"), Some(0x44));
+        assert_eq!(symbols.resolve("outs+10"), Some(0x50));
+        assert_eq!(symbols.resolve("FRAMEBUFFER"), Some(0xff000000));
+        assert_eq!(symbols.resolve("exit"), None);
+    }
+
+    #[test]
+    fn test_symbolize() {
+        let symbols = table();
+        assert_eq!(symbols.symbolize(0x10), Some("main".to_string()));
+        assert_eq!(symbols.symbolize(0x1c), Some("main+0xc".to_string()));
+        assert_eq!(symbols.symbolize(0x44), Some("outs+0x4".to_string()));
+        // Past the end of a sized symbol
+        assert_eq!(symbols.symbolize(0x48), None);
+        assert_eq!(symbols.symbolize(0x4), None);
+        // Unsized symbols only reach so far, so the last one doesn't name the whole address space
+        assert_eq!(symbols.symbolize(0xff000ffc), Some("FRAMEBUFFER+0xffc".to_string()));
+        assert_eq!(symbols.symbolize(0xff001000), None);
+    }
+}
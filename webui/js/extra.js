@@ -106,6 +106,9 @@ function sendcmd(msg){
             }
             element.innerHTML = toHex(response.registers[i-1])
         })
+        // Memory that was put back by stepping backwards
+        document.querySelector('.memory').textContent = response.memory_changes
+            .map(([addr, value]) => toHex(addr) + ": " + toHex(value)).join("\n")
         //reg-value-num
     });
 }